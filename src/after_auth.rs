@@ -0,0 +1,68 @@
+use std::time::Instant;
+
+use crate::prompt::{PromptMatcher, AUTH_SETTLE};
+
+/// Команда, которая отправляется в pty после входа (--after-auth-send)
+/// Вход считается состоявшимся, когда после отправки пароля в выводе появилось приглашение
+/// оболочки (--after-auth-prompt), а без него - когда за AUTH_SETTLE запрос пароля не повторился
+#[derive(Debug)]
+pub struct AfterAuth {
+    line: Vec<u8>,
+    prompt: Option<PromptMatcher>,
+    /// когда был отправлен пароль
+    armed: Option<Instant>,
+    prompt_seen: bool,
+    done: bool,
+}
+
+impl AfterAuth {
+    pub fn new(command: &str, prompt: Option<PromptMatcher>) -> Self {
+        let mut line = command.as_bytes().to_vec();
+        line.push(b'\n');
+
+        Self {
+            line,
+            prompt,
+            armed: None,
+            prompt_seen: false,
+            done: false,
+        }
+    }
+
+    /// Пароль отправлен, с этого момента ждем признак входа
+    pub fn arm(&mut self) {
+        if self.armed.is_none() {
+            self.armed = Some(Instant::now());
+        }
+    }
+
+    /// Вывод программы после отправки пароля, ищется приглашение оболочки
+    pub fn feed(&mut self, chunk: &[u8]) {
+        if self.armed.is_none() || self.done {
+            return;
+        }
+        if let Some(prompt) = self.prompt.as_mut() {
+            self.prompt_seen |= prompt.feed(chunk);
+        }
+    }
+
+    /// Команда уже отправлена
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Команда, если вход уже состоялся; отдается один раз
+    pub fn ready(&mut self) -> Option<Vec<u8>> {
+        let armed = self.armed?;
+        let ready = match self.prompt {
+            Some(_) => self.prompt_seen,
+            None => armed.elapsed() >= AUTH_SETTLE,
+        };
+        if !ready || self.done {
+            return None;
+        }
+
+        self.done = true;
+        Some(std::mem::take(&mut self.line))
+    }
+}
@@ -0,0 +1,142 @@
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use time::OffsetDateTime;
+
+/// Счетчик файлов, созданных этим процессом
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Сколько раз пробовать подобрать свободное имя при коллизии
+const MAX_COLLISIONS: usize = 1000;
+
+/// Идентификатор сессии (UUID v4), генерируется один раз за время жизни процесса
+pub fn session_id() -> &'static str {
+    static SID: OnceLock<String> = OnceLock::new();
+
+    SID.get_or_init(|| {
+        let mut bytes = [0u8; 16];
+        if let Err(e) = File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut bytes)) {
+            // без /dev/urandom id все равно должен быть уникальным для одновременных запусков
+            log::error!("failed to read /dev/urandom: {}", e);
+            let nanos = OffsetDateTime::now_utc().unix_timestamp_nanos() as u128;
+            bytes = (nanos ^ ((std::process::id() as u128) << 64)).to_be_bytes();
+        }
+
+        // версия 4, вариант RFC 4122
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    })
+}
+
+/// Шаблон пути к файлу, который создает сессия (лог и т.д.)
+/// Поддерживаемые подстановки:
+/// {date} - дата YYYY-MM-DD, {time} - время HHMMSS, {pid} - pid процесса,
+/// {sid} - id сессии, {seq} - порядковый номер файла внутри процесса,
+/// {program} - имя запускаемой программы, {host} - хост назначения ssh/scp/sftp/rsync
+/// (для остальных программ "local")
+#[derive(Debug, Clone)]
+pub struct ArtifactTemplate {
+    template: String,
+}
+
+impl ArtifactTemplate {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// В пути есть дата, файл меняется вместе с сутками
+    pub fn has_date(&self) -> bool {
+        self.template.contains("{date}")
+    }
+
+    /// Подставляет значения в шаблон
+    pub fn render(&self, program: &str, host: &str) -> PathBuf {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        let program = Path::new(program)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        let path = self
+            .template
+            .replace(
+                "{date}",
+                &format!("{:04}-{:02}-{:02}", now.year(), now.month() as u8, now.day()),
+            )
+            .replace(
+                "{time}",
+                &format!("{:02}{:02}{:02}", now.hour(), now.minute(), now.second()),
+            )
+            .replace("{pid}", &std::process::id().to_string())
+            .replace("{sid}", session_id())
+            .replace("{seq}", &SEQ.fetch_add(1, Ordering::Relaxed).to_string())
+            .replace("{program}", &program)
+            .replace("{host}", host);
+
+        PathBuf::from(path)
+    }
+
+    /// Создает файл по шаблону вместе с недостающими каталогами
+    /// Если файл уже существует (одновременный запуск нескольких сессий),
+    /// к имени добавляется суффикс -1, -2, ... пока не найдется свободное
+    pub fn create(&self, program: &str, host: &str) -> std::io::Result<(PathBuf, File)> {
+        let path = self.render(program, host);
+
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        for n in 0..MAX_COLLISIONS {
+            let candidate = if n == 0 {
+                path.clone()
+            } else {
+                with_suffix(&path, n)
+            };
+
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&candidate)
+            {
+                Ok(file) => return Ok((candidate, file)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("no free file name for {}", path.display()),
+        ))
+    }
+}
+
+/// sshpass.log -> sshpass-1.log
+fn with_suffix(path: &Path, n: usize) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    let name = match path.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, n, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, n),
+    };
+
+    path.with_file_name(name)
+}
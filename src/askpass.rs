@@ -0,0 +1,168 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{error, trace};
+use nix::libc;
+use nix::sys::signal::{signal, SigHandler, Signal};
+
+use crate::cli::Config;
+use crate::prompt::PromptMatcher;
+use crate::unix::close_inherited_fds;
+
+/// Пароль для sshpass, запущенного ssh в роли SSH_ASKPASS
+pub const ASKPASS_SECRET_ENV: &str = "SSHPASS_ASKPASS_SECRET";
+/// --prompt, по которому helper отличает запрос пароля от других вопросов ssh
+pub const ASKPASS_PROMPT_ENV: &str = "SSHPASS_ASKPASS_PROMPT";
+/// --prompt-normalize для helper
+pub const ASKPASS_NORMALIZE_ENV: &str = "SSHPASS_ASKPASS_NORMALIZE";
+
+/// Как часто проверять завершение программы, когда задан --timeout
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// pid дочернего процесса для обработчика сигналов
+static CHILD: AtomicI32 = AtomicI32::new(0);
+
+/// Если sshpass запустил ssh как SSH_ASKPASS, печатает пароль и возвращает код выхода
+/// ssh передает текст запроса единственным аргументом. Отвечать можно только на запрос пароля:
+/// на вопрос о ключе хоста (yes/no) пароль отправлять нельзя, такой запрос отклоняется
+pub fn helper() -> Option<i32> {
+    let secret = std::env::var_os(ASKPASS_SECRET_ENV)?;
+    let prompt = std::env::args_os().nth(1).unwrap_or_default();
+
+    let pattern = std::env::var(ASKPASS_PROMPT_ENV).unwrap_or_default();
+    let normalize = std::env::var_os(ASKPASS_NORMALIZE_ENV).is_some();
+    let matched = PromptMatcher::parse(&pattern, normalize)
+        .map(|mut matcher| matcher.feed(prompt.as_bytes()))
+        .unwrap_or(false);
+
+    if !matched {
+        eprintln!("sshpass: refusing to answer '{}'", prompt.to_string_lossy());
+        return Some(1);
+    }
+
+    let mut stdout = std::io::stdout();
+    let res = stdout
+        .write_all(secret.as_bytes())
+        .and_then(|_| stdout.write_all(b"\n"))
+        .and_then(|_| stdout.flush());
+
+    Some(if res.is_ok() { 0 } else { 1 })
+}
+
+/// Запускает программу без pty: пароль отдает ssh через SSH_ASKPASS сам sshpass
+/// Программа запускается в новом сеансе без управляющего терминала, поэтому
+/// ssh не может спросить пароль у терминала и всегда обращается к askpass
+/// Возвращает код выхода программы (128 + сигнал, если она убита сигналом)
+pub fn run(config: &Config, password: &[u8]) -> i32 {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => {
+            error!("askpass: current executable: {}", e);
+            eprintln!("sshpass: failed to locate own executable: {}", e);
+            return crate::exit_code::EXIT_RUNTIME_ERROR;
+        }
+    };
+
+    let mut cmd = Command::new(&config.program);
+    cmd.args(&config.program_args);
+    config.child.child_env().apply(&mut cmd);
+    cmd.env("SSH_ASKPASS", exe)
+        .env("SSH_ASKPASS_REQUIRE", "force")
+        .env(ASKPASS_SECRET_ENV, OsStr::from_bytes(password))
+        .env(ASKPASS_PROMPT_ENV, &config.prompt_pattern);
+    if config.prompt_normalize {
+        cmd.env(ASKPASS_NORMALIZE_ENV, "1");
+    }
+    // OpenSSH до 8.4 не знает SSH_ASKPASS_REQUIRE и использует askpass только при DISPLAY
+    if std::env::var_os("DISPLAY").is_none() {
+        cmd.env("DISPLAY", "sshpass:0");
+    }
+
+    let child = config.child.clone();
+    unsafe {
+        cmd.pre_exec(move || {
+            if libc::setsid() < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            // через spawn до родителя доходит только errno
+            child.apply()?;
+            // вместе с остальными закрывается и pipe, через который spawn узнает об ошибке exec:
+            // с --child-close-fds ненайденная программа видна только как код выхода
+            if child.closes_fds() {
+                close_inherited_fds();
+            }
+            Ok(())
+        });
+    }
+
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("askpass: spawn {}: {}", config.program, e);
+            eprintln!("sshpass: failed to run {}: {}", config.program, e);
+            return crate::exit_code::EXIT_RUNTIME_ERROR;
+        }
+    };
+
+    // программа в другом сеансе и ^C с терминала до нее не дойдет, сигналы пересылаются
+    CHILD.store(child.id() as i32, Ordering::SeqCst);
+    for sig in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP, Signal::SIGQUIT] {
+        if let Err(e) = unsafe { signal(sig, SigHandler::Handler(forward_signal)) } {
+            error!("askpass: signal {}: {}", sig, e);
+        }
+    }
+
+    match wait_with_timeout(&mut child, config.timeout) {
+        Ok(None) => {
+            error!("askpass: session timed out, kill {}", child.id());
+            eprintln!("sshpass: session timed out");
+            unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+            let _ = child.wait();
+            crate::exit_code::EXIT_SESSION_TIMEOUT
+        }
+        Ok(Some(status)) => {
+            trace!("askpass: child {}", status);
+            status
+                .code()
+                .or_else(|| status.signal().map(|sig| 128 + sig))
+                .unwrap_or(crate::exit_code::EXIT_RUNTIME_ERROR)
+        }
+        Err(e) => {
+            error!("askpass: wait: {}", e);
+            crate::exit_code::EXIT_RUNTIME_ERROR
+        }
+    }
+}
+
+/// child.wait() с ограничением по времени, None - программа не завершилась за timeout
+fn wait_with_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if started.elapsed() >= timeout {
+            return Ok(None);
+        }
+        std::thread::sleep(WAIT_INTERVAL);
+    }
+}
+
+extern "C" fn forward_signal(sig: libc::c_int) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe { libc::kill(pid, sig) };
+    }
+}
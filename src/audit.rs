@@ -0,0 +1,157 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::str::FromStr;
+
+use log::error;
+use nix::unistd::{getuid, ttyname, User};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::{artifact, redact};
+
+/// Когда сбрасывать журнал аудита на диск (--audit-fsync)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuditFsync {
+    /// на усмотрение ядра
+    Never,
+    /// один раз, после события shutdown
+    #[default]
+    Exit,
+    /// после каждого события
+    Always,
+}
+
+impl FromStr for AuditFsync {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(AuditFsync::Never),
+            "exit" => Ok(AuditFsync::Exit),
+            "always" => Ok(AuditFsync::Always),
+            _ => Err(format!("unknown fsync policy '{}', expected never, exit or always", s)),
+        }
+    }
+}
+
+/// События журнала аудита. Имена событий и полей - часть формата файла,
+/// их меняют только вместе с потребителями журнала
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEvent<'a> {
+    SessionStart {
+        /// аргументы sshpass, пароль из -p заменен маской
+        argv: Vec<String>,
+        user: Option<String>,
+        tty: Option<String>,
+        program: &'a str,
+        host: Option<&'a str>,
+    },
+    /// пароль отправлен программе; сам пароль в журнал не попадает
+    PasswordSent { source: &'a str },
+    ChildExit {
+        /// pid записи - это pid самого sshpass
+        child_pid: i32,
+        code: i32,
+        signal: Option<&'static str>,
+    },
+    Shutdown { code: i32, reason: Option<&'a str> },
+}
+
+impl<'a> AuditEvent<'a> {
+    /// Начало сессии: аргументы, пользователь и терминал sshpass
+    pub fn session_start(program: &'a str, host: Option<&'a str>) -> Self {
+        // пароль из -p уже зарегистрирован в redact и маскируется как везде
+        let argv = std::env::args()
+            .map(|arg| String::from_utf8_lossy(&redact::redact(arg.as_bytes())).into_owned())
+            .collect();
+        let user = User::from_uid(getuid())
+            .ok()
+            .flatten()
+            .map(|user| user.name);
+        let tty = ttyname(io::stdin())
+            .ok()
+            .map(|path| path.display().to_string());
+
+        AuditEvent::SessionStart {
+            argv,
+            user,
+            tty,
+            program,
+            host,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    session: &'static str,
+    pid: u32,
+    #[serde(flatten)]
+    event: AuditEvent<'a>,
+}
+
+/// Журнал аудита в формате JSON lines (--audit-file)
+/// Файл дописывается, каждое событие - одна строка, записанная одним write
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    fsync: AuditFsync,
+    offset: UtcOffset,
+    /// об ошибке записи сообщается один раз, дальше журнал пишется по возможности
+    failed: bool,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path, fsync: AuditFsync) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+
+        Ok(Self {
+            file,
+            fsync,
+            // смещение берется один раз при старте, пока процесс однопоточный
+            offset: UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC),
+            failed: false,
+        })
+    }
+
+    pub fn record(&mut self, event: AuditEvent<'_>) {
+        let sync = match self.fsync {
+            AuditFsync::Never => false,
+            AuditFsync::Exit => matches!(event, AuditEvent::Shutdown { .. }),
+            AuditFsync::Always => true,
+        };
+        let record = AuditRecord {
+            timestamp: OffsetDateTime::now_utc()
+                .to_offset(self.offset)
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+            session: artifact::session_id(),
+            pid: std::process::id(),
+            event,
+        };
+
+        let res = serde_json::to_vec(&record)
+            .map_err(io::Error::from)
+            .and_then(|mut line| {
+                line.push(b'\n');
+                self.file.write_all(&line)
+            })
+            .and_then(|_| if sync { self.file.sync_data() } else { Ok(()) });
+        if let Err(e) = res {
+            if !self.failed {
+                error!("failed to write audit record: {}", e);
+                eprintln!("sshpass: failed to write audit record: {}", e);
+            }
+            self.failed = true;
+        }
+    }
+}
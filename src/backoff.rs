@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Задержка между попытками восстановления (переоткрыть файл лога, переподключиться к syslog)
+/// Каждая неудача удваивает задержку до max, к ней добавляется случайная добавка до половины,
+/// чтобы несколько sshpass не стучались в один ресурс одновременно.
+/// После max_attempts неудач попытки прекращаются, пока не пройдет cooldown без попыток
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    max_attempts: Option<u32>,
+    cooldown: Duration,
+    attempts: u32,
+    next_try: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl Backoff {
+    /// Без max_attempts попытки не кончаются; cooldown по умолчанию равен max
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            max_attempts: None,
+            cooldown: max,
+            attempts: 0,
+            next_try: None,
+            last_failure: None,
+        }
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Можно ли пробовать сейчас
+    pub fn ready(&mut self) -> bool {
+        let exhausted = self.max_attempts.is_some_and(|max| self.attempts >= max);
+        if exhausted {
+            // попытки кончились, после паузы все начинается с начальной задержки
+            if self.last_failure.is_none_or(|last| last.elapsed() < self.cooldown) {
+                return false;
+            }
+            self.succeeded();
+        }
+
+        self.next_try.is_none_or(|next| Instant::now() >= next)
+    }
+
+    pub fn failed(&mut self) {
+        self.attempts = self.attempts.saturating_add(1);
+        let delay = self
+            .initial
+            .saturating_mul(1 << (self.attempts - 1).min(16))
+            .min(self.max);
+
+        let now = Instant::now();
+        self.next_try = Some(now + delay + jitter(delay / 2));
+        self.last_failure = Some(now);
+    }
+
+    pub fn succeeded(&mut self) {
+        self.attempts = 0;
+        self.next_try = None;
+        self.last_failure = None;
+    }
+}
+
+/// Случайная задержка до max; для разнесения попыток хватает наносекунд текущего времени
+fn jitter(max: Duration) -> Duration {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.subsec_nanos());
+    max.mul_f64(f64::from(nanos) / 1e9)
+}
@@ -0,0 +1,813 @@
+use std::fmt;
+use std::os::fd::RawFd;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::error::ErrorKind;
+use clap::{ArgAction, ArgGroup, Parser, Subcommand, ValueHint};
+use clap_complete::Shell;
+use log::LevelFilter;
+use nix::sys::signal::Signal;
+use nix::sys::stat::Mode;
+
+#[cfg(feature = "rule-engine")]
+use crate::expect::ExpectRule;
+use crate::audit::AuditFsync;
+use crate::control::ControlMaster;
+use crate::doctor::DoctorArgs;
+use crate::hooks::ExitHooks;
+use crate::log_sink::LogSink;
+use crate::logger::LogFormat;
+use crate::mode::{SessionMode, TRANSFER_PASSWORD_PROMPT};
+use crate::pager::DEFAULT_PAGER_PROMPTS;
+use crate::preflight::DEFAULT_PREFLIGHT_TIMEOUT;
+use crate::prompt::{PromptMatcher, DEFAULT_PASSWORD_PROMPT};
+use crate::secrets::PasswordSource;
+use crate::selftest::SelftestArgs;
+use crate::signal_action::{parse_signal_action, SignalAction, SignalActions};
+use crate::target::{with_safe_ssh_options, with_ssh_options};
+use crate::unix::{
+    without_core_dumps, ChildEnv, ChildSpawnOptions, ChildUser, CpuList, IoPriority, RLimit,
+    SchedPolicy, DEFAULT_ENV_REMOVE,
+};
+
+/// Аргументы командной строки как их видит clap
+/// Значения проверяются и переводятся в Config через Config::try_from
+#[derive(Debug, Parser)]
+#[command(name = "sshpass", version = "1.0", about = "Non-interactive ssh password provider")]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
+#[command(group(
+    ArgGroup::new("password-conflict")
+        .args([
+            "password",
+            "filename",
+            "fd",
+            "env",
+            "password_cmd",
+            "password_keyring",
+            "password_fd_handshake",
+        ])
+))]
+#[command(group(
+    ArgGroup::new("otp-conflict")
+        .args(["otp_secret"])
+        .conflicts_with_all([
+            "otp_secret_file",
+            "otp_secret_fd",
+            "otp_secret_env",
+            "otp_code",
+            "otp_code_file",
+            "otp_code_fd",
+            "otp_code_env",
+        ])
+))]
+pub struct Cli {
+    /// Provide password as argument (security unwise)
+    #[arg(short = 'p', long, value_name = "PASSWORD")]
+    pub password: Option<String>,
+
+    /// Take password to use from file
+    #[arg(short = 'f', long = "file", value_name = "FILENAME")]
+    pub filename: Option<String>,
+
+    /// Use number as file descriptor for getting password
+    #[arg(short = 'd', long, value_name = "FD")]
+    pub fd: Option<RawFd>,
+
+    /// Password is passed as env-var 'SSHPASS'
+    #[arg(
+        short = 'e',
+        long,
+        value_name = "ENV",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "SSHPASS"
+    )]
+    pub env: Option<String>,
+
+    /// Run command via 'sh -c' and use the first line of its stdout as password
+    #[arg(long, value_name = "COMMAND")]
+    pub password_cmd: Option<String>,
+
+    /// How long to wait for --password-cmd to finish
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub password_cmd_timeout: u64,
+
+    /// Take password from the 'user' key with this description in the kernel keyring
+    #[arg(long, value_name = "KEY_DESC")]
+    pub password_keyring: Option<String>,
+
+    /// Write '?' to REQ_FD when the password prompt appears and read the password line from RESP_FD
+    #[arg(long, value_name = "REQ_FD:RESP_FD", value_parser = parse_fd_pair, conflicts_with = "askpass")]
+    pub password_fd_handshake: Option<(RawFd, RawFd)>,
+
+    /// Store the password in the session keyring for reuse by later invocations
+    #[arg(long, value_name = "KEY_DESC")]
+    pub keyring_store: Option<String>,
+
+    /// Which string should sshpass search for to detect a password prompt ('re:' prefix for a regex)
+    #[arg(short = 'P', long, value_name = "PROMPT")]
+    pub prompt: Option<String>,
+
+    /// Match --prompt against normalized output: ANSI sequences stripped, whitespace folded, NFC
+    #[arg(long)]
+    pub prompt_normalize: bool,
+
+    /// Do not use a pty: run the program with SSH_ASKPASS pointing back at sshpass (OpenSSH 8.4+)
+    #[arg(long, requires = "password-conflict")]
+    pub askpass: bool,
+
+    /// Tune defaults for the program: ssh, scp or sftp. scp and sftp ignore keyboard input,
+    /// pass the progress meter through unchanged and exit with code 10 when some files were not
+    /// transferred
+    #[arg(long, value_name = "MODE", default_value = "ssh")]
+    pub mode: SessionMode,
+
+    /// Kill the program and exit with code 9 if the whole session runs longer than this
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+
+    /// Exit with code 8 if no password prompt appears within this time
+    #[arg(long, value_name = "SECS", requires = "password-conflict", conflicts_with = "askpass")]
+    pub prompt_timeout: Option<u64>,
+
+    /// Before starting ssh, scp, sftp or rsync, check that the destination resolves and accepts
+    /// a TCP connection within SECS (default 5); exit with code 11 if it does not.
+    /// Results are cached for a minute, destinations behind ProxyJump/ProxyCommand are not checked
+    #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+    pub preflight: Option<Option<u64>>,
+
+    /// Be verbose about what you're doing on stderr (-vv adds sent data, -vvv poll statistics)
+    #[arg(short = 'v', long, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// One time secret in argument
+    #[arg(long)]
+    pub otp_secret: Option<String>,
+
+    /// One time secret in file
+    #[arg(long)]
+    pub otp_secret_file: Option<String>,
+
+    /// One time secret is passed as env
+    #[arg(long, env = "SSHPASS_OTP_SECRET")]
+    pub otp_secret_env: Option<String>,
+
+    /// Use number as file descriptor for getting otp secret
+    #[arg(long)]
+    pub otp_secret_fd: Option<String>,
+
+    /// One time code in argument
+    #[arg(long)]
+    pub otp_code: Option<String>,
+
+    /// One time code in file
+    #[arg(long)]
+    pub otp_code_file: Option<String>,
+
+    /// One time code is passed as env
+    #[arg(long, env = "SSHPASS_OTP_CODE")]
+    pub otp_code_env: Option<String>,
+
+    /// Use number as file descriptor for getting otp code
+    #[arg(long)]
+    pub otp_code_fd: Option<String>,
+
+    /// Which string should sshpass search for the one time password prompt
+    #[arg(short = 'O', long)]
+    pub otp_prompt: Option<String>,
+
+    /// Send this command and a newline to the program once the password has been accepted
+    #[arg(long, value_name = "COMMAND", conflicts_with = "askpass")]
+    pub after_auth_send: Option<String>,
+
+    /// Shell prompt that marks a successful login for --after-auth-send ('re:' prefix for a regex);
+    /// without it the password counts as accepted when it is not asked again within a second
+    #[arg(long, value_name = "PROMPT", requires = "after_auth_send")]
+    pub after_auth_prompt: Option<String>,
+
+    /// Automatically continue paginated output ('--More--') and strip the pager prompts
+    #[arg(long)]
+    pub pager: bool,
+
+    /// Additional pager prompt to detect (implies --pager)
+    #[arg(long, value_name = "PROMPT")]
+    pub pager_prompt: Vec<String>,
+
+    /// Pattern to wait for in the program output, paired in order with --send.
+    /// Prefixes: 're:' regex, 'lit:' literal, 'once:' (default) or 'always:', 'timeout=SECS:'
+    #[cfg(feature = "rule-engine")]
+    #[arg(long, value_name = "PATTERN")]
+    pub expect: Vec<String>,
+
+    /// Text to send when the paired --expect pattern is seen (\n, \r, \t, \e, \\, \xHH are unescaped)
+    #[cfg(feature = "rule-engine")]
+    #[arg(long, value_name = "TEXT")]
+    pub send: Vec<String>,
+
+    /// Match --expect patterns against normalized output, like --prompt-normalize
+    #[cfg(feature = "rule-engine")]
+    #[arg(long)]
+    pub expect_normalize: bool,
+
+    /// Attach a KEY=VALUE label to every log entry of this session (e.g. change=CHG-1234)
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub label: Vec<(String, String)>,
+
+    /// Remove a variable from the program environment (SSHPASS is always removed)
+    #[arg(long, value_name = "KEY")]
+    pub env_remove: Vec<String>,
+
+    /// Set a variable in the program environment
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub env_set: Vec<(String, String)>,
+
+    /// Start the program with an empty environment (--env-set still applies)
+    #[arg(long)]
+    pub env_clear: bool,
+
+    /// Do not add '-o NumberOfPasswordPrompts=1 -o PreferredAuthentications=password,keyboard-interactive' to ssh, scp and sftp
+    #[arg(long)]
+    pub no_safe_ssh_options: bool,
+
+    /// Add '-o StrictHostKeyChecking=VALUE' to ssh, scp and sftp unless already given with -o
+    #[arg(long, value_name = "VALUE", value_parser = ["yes", "no", "accept-new", "ask", "off"])]
+    pub strict_host_key_checking: Option<String>,
+
+    /// Keep a shared ssh master connection (ControlMaster) in the background for DURATION (e.g. 10m)
+    /// after the last session, so later runs to the same host do not need the password
+    #[arg(long, value_name = "DURATION")]
+    pub control_persist: Option<String>,
+
+    /// Write the pid to FILE and hold an exclusive lock on it; refuse to start if another
+    /// sshpass holds the lock
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub pidfile: Option<PathBuf>,
+
+    /// Append a JSON line per session event (start, password sent, program exit, shutdown) to FILE.
+    /// The password itself is never written
+    #[arg(long, value_name = "FILE", value_hint = ValueHint::FilePath)]
+    pub audit_file: Option<PathBuf>,
+
+    /// When to fsync the audit file: never, exit (once, after the shutdown event) or always
+    #[arg(long, value_name = "POLICY", default_value = "exit", requires = "audit_file")]
+    pub audit_fsync: AuditFsync,
+
+    /// Batch mode: do not put stdin into raw terminal mode, forward EOF on stdin to the program
+    #[arg(long)]
+    pub no_tty: bool,
+
+    /// Give the program a separate stderr and copy it to sshpass stderr or to FILE instead of the terminal stream
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-",
+        value_hint = ValueHint::FilePath
+    )]
+    pub separate_stderr: Option<String>,
+
+    /// Relay the program output but discard keyboard input; Ctrl-C ends the session
+    #[arg(long)]
+    pub read_only: bool,
+
+    /// Share the live session on a unix socket (mode 0600): another terminal can watch it with
+    /// e.g. socat - UNIX-CONNECT:PATH
+    #[arg(long, value_name = "PATH", value_hint = ValueHint::FilePath, conflicts_with = "askpass")]
+    pub attach_socket: Option<PathBuf>,
+
+    /// Let clients of --attach-socket type into the session, not only watch it
+    #[arg(long, requires = "attach_socket")]
+    pub attach_writable: bool,
+
+    /// What to do on a signal, as SIGNAL=ACTION (e.g. HUP=shutdown); ACTION is shutdown, terminate,
+    /// forward (to the program), forward-group (to the foreground job, like the terminal does),
+    /// status (fd stats to the log) or ignore. Defaults: INT=forward-group, HUP, USR1,
+    /// USR2=forward, TERM=shutdown, QUIT=terminate, others are ignored. No signal dumps the fd
+    /// stats by default, use e.g. USR1=status
+    #[arg(long, value_name = "SIGNAL=ACTION", value_parser = parse_signal_action)]
+    pub on_signal: Vec<(Signal, SignalAction)>,
+
+    /// Recognize ssh-style escape sequences typed after a newline: CHAR. ends the session,
+    /// CHAR^Z suspends, CHAR? lists them (off by default, usually ~). Works with --read-only too
+    #[arg(long, value_name = "CHAR", value_parser = parse_escape_char)]
+    pub escape_char: Option<u8>,
+
+    /// Turn CRLF from the pty back into LF in the output (on by default when stdout is not a terminal)
+    #[arg(long)]
+    pub strip_cr: bool,
+
+    /// Remove ANSI escape sequences from the output (on by default when stdout is not a terminal)
+    #[arg(long)]
+    pub strip_ansi: bool,
+
+    /// Pass the output through unchanged even when stdout is not a terminal
+    #[arg(long, conflicts_with_all = ["strip_cr", "strip_ansi"])]
+    pub raw_output: bool,
+
+    /// Once the password is accepted, move the output from the pty to stdout with splice(2)
+    /// instead of copying it through sshpass. Used only when stdout is a pipe and the output
+    /// is passed through unchanged (--raw-output, --mode scp/sftp) with no --expect, --pager
+    /// or --attach-socket; otherwise the output is copied as usual
+    #[arg(long)]
+    pub splice_output: bool,
+
+    /// Pin sshpass and the program to these CPUs (e.g. 0-3,6)
+    #[arg(long, value_name = "LIST")]
+    pub cpus: Option<CpuList>,
+
+    /// Niceness of sshpass, inherited by the program unless --child-nice is given
+    #[arg(
+        long,
+        value_name = "N",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    pub nice: Option<i32>,
+
+    /// IO priority of sshpass as CLASS[:LEVEL], CLASS is realtime, best-effort or idle
+    #[arg(long, value_name = "CLASS[:LEVEL]")]
+    pub ionice: Option<IoPriority>,
+
+    /// Pin only the program to these CPUs
+    #[arg(long, value_name = "LIST")]
+    pub child_cpus: Option<CpuList>,
+
+    /// Niceness of the program
+    #[arg(
+        long,
+        value_name = "N",
+        allow_negative_numbers = true,
+        value_parser = clap::value_parser!(i32).range(-20..=19)
+    )]
+    pub child_nice: Option<i32>,
+
+    /// IO priority of the program as CLASS[:LEVEL]
+    #[arg(long, value_name = "CLASS[:LEVEL]")]
+    pub child_ionice: Option<IoPriority>,
+
+    /// Set a resource limit of sshpass as RESOURCE=SOFT[:HARD], RESOURCE is nofile, core or as.
+    /// Core dumps of sshpass are disabled unless a core limit is given
+    #[arg(long, value_name = "RESOURCE=SOFT[:HARD]")]
+    pub rlimit: Vec<RLimit>,
+
+    /// Set a resource limit of the program before it starts, like --rlimit
+    #[arg(long, value_name = "RESOURCE=SOFT[:HARD]")]
+    pub child_rlimit: Vec<RLimit>,
+
+    /// Working directory of the program
+    #[arg(long, value_name = "DIR", value_hint = ValueHint::DirPath)]
+    pub child_cwd: Option<PathBuf>,
+
+    /// Run the program as USER[:GROUP] (names or numeric ids, requires root)
+    #[arg(long, value_name = "USER[:GROUP]")]
+    pub child_user: Option<ChildUser>,
+
+    /// File mode creation mask of the program, in octal (e.g. 077)
+    #[arg(long, value_name = "MODE", value_parser = parse_umask)]
+    pub child_umask: Option<Mode>,
+
+    /// Close every inherited file descriptor except stdin, stdout and stderr before starting the program
+    #[arg(long)]
+    pub child_close_fds: bool,
+
+    /// Run a shell command after the session ends with exit code 0
+    #[arg(long, value_name = "COMMAND")]
+    pub on_success: Option<String>,
+
+    /// Run a shell command after the session ends with a non-zero exit code
+    #[arg(long, value_name = "COMMAND")]
+    pub on_failure: Option<String>,
+
+    /// Run a shell command after the session is cut short by --timeout, --prompt-timeout or an
+    /// --expect rule timeout (defaults to --on-failure)
+    #[arg(long, value_name = "COMMAND")]
+    pub on_timeout: Option<String>,
+
+    /// How long to wait for an --on-* hook before killing it
+    #[arg(long, value_name = "SECS", default_value_t = 10)]
+    pub hook_timeout: u64,
+
+    /// Show a session summary on the terminal and wait for Enter before starting the program.
+    /// With SECS the session starts by itself after that many seconds
+    #[arg(long, value_name = "SECS", num_args = 0..=1, require_equals = true)]
+    pub confirm: Option<Option<u64>>,
+
+    /// Program to execute and its arguments. Options of sshpass end at the program name,
+    /// so the program may use the same options (e.g. sshpass -p PASS ssh -p 2222 host)
+    #[arg(
+        value_name = "PROGRAM",
+        required = true,
+        num_args = 1..,
+        allow_hyphen_values = true,
+        trailing_var_arg = true,
+        value_hint = ValueHint::CommandWithArguments
+    )]
+    pub program: Vec<String>,
+
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+    /// Generate shell completions
+    #[command(hide = true)]
+    Completions { shell: Shell },
+    /// Act as a scripted interactive program for end-to-end checks
+    #[command(hide = true)]
+    SelftestChild(SelftestArgs),
+    /// Check that this system can run sshpass sessions and print a report
+    Doctor(DoctorArgs),
+}
+
+/// Настройки лога, берутся из переменных окружения
+/// SSHPASS_LOG - уровень, SSHPASS_LOG_FORMAT - text|json, SSHPASS_LOG_FILE - шаблон пути,
+/// SSHPASS_LOG_RATE - ограничение записей в секунду, SSHPASS_LOG_SINK - file|syslog|journald
+#[derive(Debug)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub file: Option<String>,
+    /// SSHPASS_LOG_RATE - записей в секунду на уровень и источник, None - без ограничения
+    pub rate: Option<u32>,
+    /// SSHPASS_LOG_SINK - file (по умолчанию), syslog или journald
+    pub sink: LogSink,
+}
+
+/// Проверенные настройки запуска
+#[derive(Debug)]
+pub struct Config {
+    pub program: String,
+    pub program_args: Vec<String>,
+    pub password: Option<PasswordSource>,
+    /// (REQ_FD, RESP_FD) для --password-fd-handshake, пароль приходит только после запроса
+    pub password_handshake: Option<(RawFd, RawFd)>,
+    pub keyring_store: Option<String>,
+    pub prompt: PromptMatcher,
+    /// --prompt в исходном виде и --prompt-normalize, их получает askpass helper
+    pub prompt_pattern: String,
+    pub prompt_normalize: bool,
+    /// пароль отдается через SSH_ASKPASS, а не через pty
+    pub askpass: bool,
+    /// для какой программы настроены умолчания (--mode)
+    pub mode: SessionMode,
+    /// ограничение на всю сессию (--timeout)
+    pub timeout: Option<Duration>,
+    /// сколько ждать запроса пароля (--prompt-timeout)
+    pub prompt_timeout: Option<Duration>,
+    /// сколько ждать TCP соединения с адресом назначения до запуска программы (--preflight)
+    pub preflight: Option<Duration>,
+    /// приглашения пейджера, None если пейджер не включен
+    pub pager_prompts: Option<Vec<String>>,
+    /// команда после входа и приглашение оболочки, по которому вход определяется
+    pub after_auth_send: Option<String>,
+    pub after_auth_prompt: Option<PromptMatcher>,
+    /// правила --expect/--send в порядке из командной строки
+    #[cfg(feature = "rule-engine")]
+    pub expect_rules: Vec<ExpectRule>,
+    #[cfg(feature = "rule-engine")]
+    pub expect_normalize: bool,
+    /// None если лог не включен
+    pub log: Option<LogConfig>,
+    /// уровень подробного вывода в stderr (-v, -vv, -vvv)
+    pub verbose: u8,
+    /// метки сессии (--label), попадают в каждую запись JSON лога и в событие startup
+    pub labels: Vec<(String, String)>,
+    /// stdin не терминал, даже если подключен к нему
+    pub no_tty: bool,
+    /// ввод с stdin не передается программе
+    pub read_only: bool,
+    /// escape-символ для последовательностей на вводе (--escape-char)
+    pub escape_char: Option<u8>,
+    /// сокет для подключения к сессии со стороны и можно ли через него вводить
+    pub attach_socket: Option<PathBuf>,
+    pub attach_writable: bool,
+    /// действия на сигналы: умолчания и --on-signal
+    pub signals: SignalActions,
+    /// перевод вывода; без явных флагов включается, если stdout не терминал
+    pub strip_cr: bool,
+    pub strip_ansi: bool,
+    pub raw_output: bool,
+    /// вывод pty в stdout через splice, если stdout - pipe и вывод идет без изменений
+    pub splice_output: bool,
+    /// куда копировать отделенный stderr дочернего процесса, "-" - stderr sshpass
+    pub separate_stderr: Option<String>,
+    /// общее соединение ssh (--control-persist), его опции уже добавлены в program_args
+    pub control: Option<ControlMaster>,
+    /// файл с pid и блокировкой от второго экземпляра (--pidfile)
+    pub pidfile: Option<PathBuf>,
+    /// журнал аудита (--audit-file) и когда сбрасывать его на диск
+    pub audit_file: Option<PathBuf>,
+    pub audit_fsync: AuditFsync,
+    /// --cpus/--nice/--ionice для самого sshpass, дочерний процесс их наследует
+    pub sched: SchedPolicy,
+    /// окружение, --child-* и лимиты дочернего процесса, применяются перед exec
+    pub child: ChildSpawnOptions,
+    /// лимиты sshpass (--rlimit), core dump отключены если не заданы явно
+    pub limits: Vec<RLimit>,
+    pub hooks: ExitHooks,
+    /// показать сводку и ждать подтверждения (--confirm)
+    pub confirm: bool,
+    /// через сколько продолжить без подтверждения (--confirm=SECS)
+    pub confirm_timeout: Option<Duration>,
+}
+
+/// Ошибка в аргументах или переменных окружения
+#[derive(Debug)]
+pub enum CliError {
+    MissingProgram,
+    InvalidFd(RawFd),
+    InvalidLogLevel(String),
+    InvalidLogFormat(String),
+    InvalidLogRate(String),
+    InvalidLogSink(String),
+    #[cfg(feature = "rule-engine")]
+    ExpectWithoutSend,
+    #[cfg(feature = "rule-engine")]
+    InvalidExpect(String),
+    InvalidPrompt(regex::Error),
+    InvalidAfterAuthPrompt(regex::Error),
+}
+
+impl CliError {
+    /// Вид ошибки для clap, чтобы она печаталась и завершала процесс как ошибки самого clap
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CliError::MissingProgram => ErrorKind::MissingRequiredArgument,
+            CliError::InvalidFd(_) => ErrorKind::ValueValidation,
+            CliError::InvalidLogLevel(_) => ErrorKind::InvalidValue,
+            CliError::InvalidLogFormat(_) => ErrorKind::InvalidValue,
+            CliError::InvalidLogRate(_) => ErrorKind::InvalidValue,
+            CliError::InvalidLogSink(_) => ErrorKind::InvalidValue,
+            #[cfg(feature = "rule-engine")]
+            CliError::ExpectWithoutSend => ErrorKind::WrongNumberOfValues,
+            #[cfg(feature = "rule-engine")]
+            CliError::InvalidExpect(_) => ErrorKind::InvalidValue,
+            CliError::InvalidPrompt(_) => ErrorKind::InvalidValue,
+            CliError::InvalidAfterAuthPrompt(_) => ErrorKind::InvalidValue,
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::MissingProgram => write!(f, "program to execute is required"),
+            CliError::InvalidFd(fd) => write!(f, "invalid file descriptor {}", fd),
+            CliError::InvalidLogLevel(level) => {
+                write!(f, "invalid SSHPASS_LOG level '{}'", level)
+            }
+            CliError::InvalidLogFormat(e) => write!(f, "invalid SSHPASS_LOG_FORMAT: {}", e),
+            CliError::InvalidLogRate(rate) => write!(f, "invalid SSHPASS_LOG_RATE '{}'", rate),
+            CliError::InvalidLogSink(e) => write!(f, "invalid SSHPASS_LOG_SINK: {}", e),
+            #[cfg(feature = "rule-engine")]
+            CliError::ExpectWithoutSend => write!(f, "every --expect needs a paired --send"),
+            #[cfg(feature = "rule-engine")]
+            CliError::InvalidExpect(e) => write!(f, "invalid --expect/--send: {}", e),
+            CliError::InvalidPrompt(e) => write!(f, "invalid --prompt: {}", e),
+            CliError::InvalidAfterAuthPrompt(e) => write!(f, "invalid --after-auth-prompt: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl TryFrom<Cli> for Config {
+    type Error = CliError;
+
+    fn try_from(cli: Cli) -> Result<Self, Self::Error> {
+        let mut program = cli.program.into_iter();
+        let (program, program_args) = match program.next() {
+            Some(name) => (name, program.collect::<Vec<_>>()),
+            None => return Err(CliError::MissingProgram),
+        };
+
+        // источники пароля взаимоисключающие (группа password-conflict), берется единственный
+        let password = if let Some(password) = cli.password {
+            Some(PasswordSource::Argument(password))
+        } else if let Some(filename) = cli.filename {
+            Some(PasswordSource::File(filename))
+        } else if let Some(fd) = cli.fd {
+            if fd < 0 {
+                return Err(CliError::InvalidFd(fd));
+            }
+            Some(PasswordSource::Fd(fd))
+        } else if let Some(name) = cli.env {
+            Some(PasswordSource::Env(name))
+        } else if let Some(command) = cli.password_cmd {
+            Some(PasswordSource::Command(
+                command,
+                Duration::from_secs(cli.password_cmd_timeout),
+            ))
+        } else {
+            cli.password_keyring.map(PasswordSource::Keyring)
+        };
+
+        let pager_prompts = (cli.pager || !cli.pager_prompt.is_empty()).then(|| {
+            DEFAULT_PAGER_PROMPTS
+                .iter()
+                .map(|p| p.to_string())
+                .chain(cli.pager_prompt)
+                .collect()
+        });
+
+        #[cfg(feature = "rule-engine")]
+        if cli.expect.len() != cli.send.len() {
+            return Err(CliError::ExpectWithoutSend);
+        }
+        #[cfg(feature = "rule-engine")]
+        let expect_rules = cli
+            .expect
+            .iter()
+            .zip(&cli.send)
+            .map(|(expect, send)| ExpectRule::parse(expect, send))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(CliError::InvalidExpect)?;
+
+        // пароль из переменной окружения не должен доставаться дочернему процессу
+        let mut env_remove: Vec<String> =
+            DEFAULT_ENV_REMOVE.iter().map(|key| key.to_string()).collect();
+        if let Some(PasswordSource::Env(name)) = &password {
+            env_remove.push(name.clone());
+        }
+        env_remove.extend(cli.env_remove);
+
+        // защитные опции нужны только когда sshpass сам отвечает на запрос пароля
+        let answers_prompt = password.is_some() || cli.password_fd_handshake.is_some();
+        let program_args = if answers_prompt && !cli.no_safe_ssh_options {
+            with_safe_ssh_options(&program, program_args)
+        } else {
+            program_args
+        };
+        // явно заданная опция добавляется и при --no-safe-ssh-options
+        let program_args = match &cli.strict_host_key_checking {
+            Some(value) => {
+                with_ssh_options(&program, program_args, &[("StrictHostKeyChecking", value)])
+            }
+            None => program_args,
+        };
+        let control = cli.control_persist.map(ControlMaster::new);
+        let program_args = match &control {
+            Some(control) => with_ssh_options(&program, program_args, &control.ssh_options()),
+            None => program_args,
+        };
+
+        let (limits, child_limits) = without_core_dumps(&cli.rlimit, &cli.child_rlimit);
+        let separate_stderr = cli.separate_stderr.is_some();
+
+        // scp и sftp не читают клавиатуру, а строку прогресса портит перевод вывода
+        let transfer = cli.mode.is_transfer();
+        let default_prompt = if transfer {
+            TRANSFER_PASSWORD_PROMPT
+        } else {
+            DEFAULT_PASSWORD_PROMPT
+        };
+        let prompt_pattern = cli.prompt.unwrap_or_else(|| default_prompt.to_owned());
+
+        Ok(Self {
+            program,
+            program_args,
+            password,
+            password_handshake: cli.password_fd_handshake,
+            keyring_store: cli.keyring_store,
+            prompt: PromptMatcher::parse(&prompt_pattern, cli.prompt_normalize)
+                .map_err(CliError::InvalidPrompt)?,
+            prompt_pattern,
+            prompt_normalize: cli.prompt_normalize,
+            askpass: cli.askpass,
+            mode: cli.mode,
+            timeout: cli.timeout.map(Duration::from_secs),
+            prompt_timeout: cli.prompt_timeout.map(Duration::from_secs),
+            preflight: cli
+                .preflight
+                .map(|secs| Duration::from_secs(secs.unwrap_or(DEFAULT_PREFLIGHT_TIMEOUT))),
+            pager_prompts,
+            after_auth_send: cli.after_auth_send,
+            after_auth_prompt: cli
+                .after_auth_prompt
+                .map(|prompt| PromptMatcher::parse(&prompt, false))
+                .transpose()
+                .map_err(CliError::InvalidAfterAuthPrompt)?,
+            #[cfg(feature = "rule-engine")]
+            expect_rules,
+            #[cfg(feature = "rule-engine")]
+            expect_normalize: cli.expect_normalize,
+            log: log_config_from_env()?,
+            verbose: cli.verbose,
+            labels: cli.label,
+            no_tty: cli.no_tty,
+            read_only: cli.read_only || transfer,
+            escape_char: cli.escape_char,
+            attach_socket: cli.attach_socket,
+            attach_writable: cli.attach_writable,
+            signals: SignalActions::with(&cli.on_signal),
+            strip_cr: cli.strip_cr,
+            strip_ansi: cli.strip_ansi,
+            raw_output: cli.raw_output || (transfer && !cli.strip_cr && !cli.strip_ansi),
+            splice_output: cli.splice_output,
+            separate_stderr: cli.separate_stderr,
+            control,
+            pidfile: cli.pidfile,
+            audit_file: cli.audit_file,
+            audit_fsync: cli.audit_fsync,
+            sched: SchedPolicy {
+                cpus: cli.cpus,
+                nice: cli.nice,
+                ionice: cli.ionice,
+            },
+            child: ChildSpawnOptions::default()
+                .env(ChildEnv {
+                    clear: cli.env_clear,
+                    remove: env_remove,
+                    set: cli.env_set,
+                })
+                .sched(SchedPolicy {
+                    cpus: cli.child_cpus,
+                    nice: cli.child_nice,
+                    ionice: cli.child_ionice,
+                })
+                .limits(child_limits)
+                .cwd(cli.child_cwd)
+                .user(cli.child_user)
+                .umask(cli.child_umask)
+                .close_fds(cli.child_close_fds)
+                .separate_stderr(separate_stderr),
+            limits,
+            hooks: ExitHooks {
+                on_success: cli.on_success,
+                on_failure: cli.on_failure,
+                on_timeout: cli.on_timeout,
+                timeout: Duration::from_secs(cli.hook_timeout),
+            },
+            confirm: cli.confirm.is_some(),
+            confirm_timeout: cli.confirm.flatten().map(Duration::from_secs),
+        })
+    }
+}
+
+/// REQ_FD:RESP_FD для --password-fd-handshake
+fn parse_fd_pair(s: &str) -> Result<(RawFd, RawFd), String> {
+    let pair = s
+        .split_once(':')
+        .and_then(|(req, resp)| Some((req.parse::<RawFd>().ok()?, resp.parse::<RawFd>().ok()?)));
+
+    match pair {
+        Some((req, resp)) if req >= 0 && resp >= 0 => Ok((req, resp)),
+        _ => Err(format!("expected REQ_FD:RESP_FD, got '{}'", s)),
+    }
+}
+
+/// KEY=VALUE для --env-set и --label
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", s)),
+    }
+}
+
+/// Один печатный символ ASCII, как -e у ssh
+fn parse_escape_char(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        [c] if c.is_ascii_graphic() => Ok(*c),
+        _ => Err(format!("expected a single printable character, got '{}'", s)),
+    }
+}
+
+fn parse_umask(s: &str) -> Result<Mode, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o777 => Ok(Mode::from_bits_truncate(mode)),
+        _ => Err(format!("expected an octal mode up to 777, got '{}'", s)),
+    }
+}
+
+pub fn log_config_from_env() -> Result<Option<LogConfig>, CliError> {
+    let Ok(level) = std::env::var("SSHPASS_LOG") else {
+        return Ok(None);
+    };
+
+    let level = LevelFilter::from_str(&level).map_err(|_| CliError::InvalidLogLevel(level))?;
+    let format = match std::env::var("SSHPASS_LOG_FORMAT") {
+        Ok(format) => LogFormat::from_str(&format).map_err(CliError::InvalidLogFormat)?,
+        Err(_) => LogFormat::Text,
+    };
+
+    let sink = match std::env::var("SSHPASS_LOG_SINK") {
+        Ok(sink) => LogSink::from_str(&sink).map_err(CliError::InvalidLogSink)?,
+        Err(_) => LogSink::File,
+    };
+    let rate = match std::env::var("SSHPASS_LOG_RATE") {
+        Ok(rate) => Some(rate.parse().map_err(|_| CliError::InvalidLogRate(rate))?),
+        Err(_) => None,
+    };
+
+    Ok(Some(LogConfig {
+        level,
+        format,
+        file: std::env::var("SSHPASS_LOG_FILE").ok(),
+        rate,
+        sink,
+    }))
+}
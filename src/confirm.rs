@@ -0,0 +1,83 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsFd;
+use std::time::{Duration, Instant};
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+/// Ответ на подтверждение перед запуском
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// Enter или истекло время ожидания
+    Proceed,
+    /// пользователь ответил n/q
+    Abort,
+}
+
+/// Показывает сводку сессии на /dev/tty и ждет Enter (--confirm)
+/// Пишется именно в /dev/tty, а не в stdout: дочерний процесс еще не запущен, и сводка
+/// не попадает ни в вывод, который может читать скрипт, ни к удаленной стороне.
+/// С auto_continue сессия стартует сама по истечении времени, в том числе без терминала
+pub fn confirm(summary: &str, auto_continue: Option<Duration>) -> io::Result<Confirmation> {
+    let tty = match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+        Ok(tty) => tty,
+        // подтвердить некому, но ждать и не требовалось
+        Err(_) if auto_continue.is_some() => return Ok(Confirmation::Proceed),
+        Err(e) => return Err(e),
+    };
+
+    let mut out = &tty;
+    out.write_all(summary.as_bytes())?;
+    match auto_continue {
+        Some(timeout) => write!(
+            out,
+            "Press Enter to continue or 'n' to abort (continuing in {}s) ",
+            timeout.as_secs()
+        )?,
+        None => write!(out, "Press Enter to continue or 'n' to abort ")?,
+    }
+    out.flush()?;
+
+    let answer = match auto_continue {
+        Some(timeout) => read_line_timeout(&tty, timeout)?,
+        None => Some(read_line(&tty)?),
+    };
+
+    let Some(answer) = answer else {
+        writeln!(out)?;
+        return Ok(Confirmation::Proceed);
+    };
+
+    match answer.trim() {
+        "n" | "N" | "no" | "q" => Ok(Confirmation::Abort),
+        _ => Ok(Confirmation::Proceed),
+    }
+}
+
+fn read_line(tty: &File) -> io::Result<String> {
+    let mut line = String::new();
+    BufReader::new(tty).read_line(&mut line)?;
+    Ok(line)
+}
+
+/// Ждет строку не дольше timeout, None - время вышло
+fn read_line_timeout(tty: &File, timeout: Duration) -> io::Result<Option<String>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Ok(None);
+        }
+
+        let ms = u16::try_from(left.as_millis()).unwrap_or(u16::MAX);
+        let mut fds = [PollFd::new(tty.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::from(ms)) {
+            // терминал в каноническом режиме, строка приходит целиком после Enter
+            Ok(n) if n > 0 => return read_line(tty).map(Some),
+            Ok(_) => continue,
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::PathBuf;
+
+use nix::unistd::getuid;
+
+/// Общее соединение ssh (ControlMaster) для --control-persist
+/// Первый запуск вводит пароль и оставляет мастер-соединение в фоне (его держит сам ssh
+/// через ControlPersist), следующие запуски к тому же хосту идут через сокет мастера
+/// и запроса пароля уже не видят
+#[derive(Debug, Clone)]
+pub struct ControlMaster {
+    dir: PathBuf,
+    /// значение ControlPersist: время простоя (10m, 1h) или yes
+    persist: String,
+}
+
+impl ControlMaster {
+    pub fn new(persist: String) -> Self {
+        Self {
+            dir: control_dir(),
+            persist,
+        }
+    }
+
+    /// Опции для ssh: %C - хеш от хоста, порта и пользователя, короткий и не упирается
+    /// в ограничение длины пути unix сокета
+    pub fn ssh_options(&self) -> [(&'static str, String); 3] {
+        [
+            ("ControlMaster", "auto".to_owned()),
+            ("ControlPath", self.dir.join("%C").to_string_lossy().into_owned()),
+            ("ControlPersist", self.persist.clone()),
+        ]
+    }
+
+    /// Создает каталог для сокетов (0700) и проверяет, что чужой процесс не может
+    /// подменить в нем сокет: каталог должен принадлежать нам и быть закрыт для остальных
+    pub fn prepare(&self) -> io::Result<()> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.dir)?;
+
+        let meta = std::fs::metadata(&self.dir)?;
+        if meta.uid() != getuid().as_raw() || meta.mode() & 0o077 != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "control directory {} must be owned by the current user and have mode 0700",
+                    self.dir.display()
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// $XDG_RUNTIME_DIR/sshpass, иначе ~/.ssh/sshpass, иначе /tmp/sshpass-<uid>
+/// Там же лежит кеш --preflight
+pub fn control_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join("sshpass");
+    }
+
+    if let Some(home) = std::env::var_os("HOME").filter(|home| !home.is_empty()) {
+        return PathBuf::from(home).join(".ssh").join("sshpass");
+    }
+
+    PathBuf::from(format!("/tmp/sshpass-{}", getuid()))
+}
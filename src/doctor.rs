@@ -0,0 +1,160 @@
+use std::os::fd::{FromRawFd, OwnedFd};
+
+use clap::Args;
+use nix::libc;
+use nix::pty::openpty;
+use nix::sys::signal::SigSet;
+use nix::sys::signalfd::{SfdFlags, SignalFd};
+use serde::Serialize;
+
+use crate::cli::log_config_from_env;
+use crate::unix::TerminalInfo;
+
+/// Проверка окружения: sshpass doctor
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Ok,
+    /// работать можно, но часть возможностей недоступна
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: &'static str,
+    status: Status,
+    detail: String,
+}
+
+impl Check {
+    fn from_result(name: &'static str, res: Result<String, String>) -> Self {
+        match res {
+            Ok(detail) => Self { name, status: Status::Ok, detail },
+            Err(detail) => Self { name, status: Status::Fail, detail },
+        }
+    }
+}
+
+/// Проверяет то, без чего sshpass не запустит сессию: дескрипторы ядра для цикла событий,
+/// выделение pty, настройки лога из окружения. Код выхода 1, если хоть одна проверка не прошла
+pub fn run(args: &DoctorArgs) -> i32 {
+    let checks = vec![
+        Check::from_result("signalfd", check_signalfd()),
+        Check::from_result("timerfd", check_timerfd()),
+        Check::from_result("eventfd", check_eventfd()),
+        Check::from_result("pty", check_pty()),
+        Check::from_result("log", check_log()),
+        check_terminal(),
+        check_ssh(),
+    ];
+
+    if args.json {
+        println!("{}", serde_json::json!({ "checks": checks }));
+    } else {
+        for check in &checks {
+            let status = match check.status {
+                Status::Ok => "ok",
+                Status::Warn => "warn",
+                Status::Fail => "FAIL",
+            };
+            println!("{:<4}  {:<8}  {}", status, check.name, check.detail);
+        }
+    }
+
+    if checks.iter().any(|check| check.status == Status::Fail) {
+        1
+    } else {
+        0
+    }
+}
+
+fn check_signalfd() -> Result<String, String> {
+    SignalFd::with_flags(&SigSet::empty(), SfdFlags::SFD_CLOEXEC)
+        .map(|_| "created".to_owned())
+        .map_err(|e| e.to_string())
+}
+
+fn check_timerfd() -> Result<String, String> {
+    let fd = unsafe { libc::timerfd_create(libc::CLOCK_MONOTONIC, libc::TFD_CLOEXEC) };
+    owned(fd).map(|_| "created".to_owned())
+}
+
+fn check_eventfd() -> Result<String, String> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    owned(fd).map(|_| "created".to_owned())
+}
+
+/// Дескриптор от libc закрывается при выходе из проверки
+fn owned(fd: libc::c_int) -> Result<OwnedFd, String> {
+    if fd < 0 {
+        return Err(std::io::Error::last_os_error().to_string());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn check_pty() -> Result<String, String> {
+    let pty = openpty(None, None).map_err(|e| e.to_string())?;
+    nix::unistd::ttyname(&pty.slave)
+        .map(|name| format!("allocated {}", name.display()))
+        .map_err(|e| e.to_string())
+}
+
+fn check_log() -> Result<String, String> {
+    match log_config_from_env() {
+        Ok(Some(log)) => Ok(format!("{} ({:?})", log.level, log.sink).to_lowercase()),
+        Ok(None) => Ok("off (SSHPASS_LOG not set)".to_owned()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Без терминала sshpass работает, но ответить на запрос вместо него некому
+fn check_terminal() -> Check {
+    let terminal = TerminalInfo::detect();
+    let detail = format!(
+        "stdin {}, stdout {}, controlling terminal {}",
+        tty_word(terminal.stdin_tty),
+        tty_word(terminal.stdout_tty),
+        if terminal.controlling_tty { "yes" } else { "no" },
+    );
+    let status = if terminal.interactive() { Status::Ok } else { Status::Warn };
+
+    Check { name: "terminal", status, detail }
+}
+
+fn tty_word(tty: bool) -> &'static str {
+    if tty {
+        "tty"
+    } else {
+        "not a tty"
+    }
+}
+
+/// ssh нужен не всегда (sshpass запускает любую программу), поэтому отсутствие - предупреждение
+fn check_ssh() -> Check {
+    let found = std::env::var_os("PATH").and_then(|path| {
+        std::env::split_paths(&path)
+            .map(|dir| dir.join("ssh"))
+            .find(|ssh| ssh.is_file())
+    });
+
+    match found {
+        Some(ssh) => Check {
+            name: "ssh",
+            status: Status::Ok,
+            detail: ssh.display().to_string(),
+        },
+        None => Check {
+            name: "ssh",
+            status: Status::Warn,
+            detail: "not found in PATH".to_owned(),
+        },
+    }
+}
@@ -0,0 +1,161 @@
+use crate::transform::{Transform, TransformEvent};
+
+/// ^Z после escape-символа приостанавливает sshpass
+const CTRL_Z: u8 = 0x1a;
+
+/// Escape-последовательности на вводе с клавиатуры, как у ssh (--escape-char)
+/// Распознаются только в начале строки: escape-символ, затем команда.
+/// Escape-символ в начале строки придерживается до следующего байта: если за ним
+/// не команда, в pty уходят оба байта, повтор escape-символа отправляет его один раз
+#[derive(Debug)]
+pub struct EscapeFilter {
+    escape: u8,
+    line_start: bool,
+    /// escape-символ получен в начале строки, ждем команду
+    pending: bool,
+}
+
+impl EscapeFilter {
+    pub fn new(escape: u8) -> Self {
+        Self {
+            escape,
+            line_start: true,
+            pending: false,
+        }
+    }
+
+    fn help(&self) -> String {
+        let escape = char::from(self.escape);
+        format!(
+            "Supported escape sequences:\n \
+             {0}.   - terminate the session\n \
+             {0}^Z  - suspend sshpass\n \
+             {0}?   - this message\n \
+             {0}{0}   - send the escape character by typing it twice\n\
+             (Note that escapes are only recognized immediately after newline.)\n",
+            escape
+        )
+    }
+}
+
+impl Transform for EscapeFilter {
+    fn name(&self) -> &'static str {
+        "escape"
+    }
+
+    fn feed(&mut self, chunk: Vec<u8>, events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        let mut output = Vec::with_capacity(chunk.len());
+        for b in chunk {
+            if self.pending {
+                self.pending = false;
+                match b {
+                    b'.' => {
+                        events.push(TransformEvent::Disconnect);
+                        // после завершения ввод уже никуда не идет
+                        return output;
+                    }
+                    CTRL_Z => events.push(TransformEvent::Suspend),
+                    b'?' => events.push(TransformEvent::Notice(self.help())),
+                    b if b == self.escape => {
+                        output.push(b);
+                        self.line_start = false;
+                    }
+                    b => {
+                        output.extend([self.escape, b]);
+                        self.line_start = matches!(b, b'\r' | b'\n');
+                    }
+                }
+                // команда в pty не уходит, line_start остается прежним
+                continue;
+            }
+
+            if self.line_start && b == self.escape {
+                self.pending = true;
+                continue;
+            }
+
+            output.push(b);
+            self.line_start = matches!(b, b'\r' | b'\n');
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Вывод и события фильтра после фрагментов chunks
+    fn feed(filter: &mut EscapeFilter, chunks: &[&[u8]]) -> (Vec<u8>, Vec<TransformEvent>) {
+        let mut output = vec![];
+        let mut events = vec![];
+        for chunk in chunks {
+            output.extend(filter.feed(chunk.to_vec(), &mut events));
+        }
+        (output, events)
+    }
+
+    #[test]
+    fn disconnect_at_line_start() {
+        for input in [&b"~."[..], b"ls\n~.", b"ls\r~."] {
+            let (output, events) = feed(&mut EscapeFilter::new(b'~'), &[input]);
+            assert_eq!(output, &input[..input.len() - 2]);
+            assert_eq!(events, [TransformEvent::Disconnect]);
+        }
+    }
+
+    #[test]
+    fn input_after_disconnect_is_dropped() {
+        let (output, events) = feed(&mut EscapeFilter::new(b'~'), &[b"~.exit\n"]);
+        assert!(output.is_empty());
+        assert_eq!(events, [TransformEvent::Disconnect]);
+    }
+
+    #[test]
+    fn escape_in_the_middle_of_line_passes() {
+        let (output, events) = feed(&mut EscapeFilter::new(b'~'), &[b"cd ~.ssh\n"]);
+        assert_eq!(output, b"cd ~.ssh\n");
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn sequence_split_between_reads() {
+        let mut filter = EscapeFilter::new(b'~');
+        let (output, events) = feed(&mut filter, &[b"ls\n", b"~"]);
+        assert_eq!(output, b"ls\n");
+        assert!(events.is_empty());
+
+        let (output, events) = feed(&mut filter, &[b"."]);
+        assert!(output.is_empty());
+        assert_eq!(events, [TransformEvent::Disconnect]);
+    }
+
+    #[test]
+    fn double_escape_sends_one() {
+        // после отправленного escape-символа строка уже не в начале
+        let (output, events) = feed(&mut EscapeFilter::new(b'~'), &[b"~~.", b"\n"]);
+        assert_eq!(output, b"~.\n");
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn unknown_command_sends_both_bytes() {
+        let (output, events) = feed(&mut EscapeFilter::new(b'~'), &[b"~x\n~\n~."]);
+        assert_eq!(output, b"~x\n~\n");
+        assert_eq!(events, [TransformEvent::Disconnect]);
+    }
+
+    #[test]
+    fn suspend_and_help_keep_line_start() {
+        let (output, events) = feed(&mut EscapeFilter::new(b'%'), &[b"%\x1a%?%."]);
+        assert!(output.is_empty());
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], TransformEvent::Suspend);
+        let TransformEvent::Notice(help) = &events[1] else {
+            panic!("expected help, got {:?}", events[1]);
+        };
+        assert!(help.contains("%.   - terminate the session"), "{}", help);
+        assert_eq!(events[2], TransformEvent::Disconnect);
+    }
+}
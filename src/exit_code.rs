@@ -0,0 +1,12 @@
+/// Ошибка при выполнении (например не удалось получить пароль)
+pub const EXIT_RUNTIME_ERROR: i32 = 3;
+/// Пароль не подошел, запрос пароля повторился
+pub const EXIT_INCORRECT_PASSWORD: i32 = 5;
+/// Запрос пароля не появился за --prompt-timeout
+pub const EXIT_PROMPT_TIMEOUT: i32 = 8;
+/// Сессия не завершилась за --timeout
+pub const EXIT_SESSION_TIMEOUT: i32 = 9;
+/// --mode scp/sftp: программа передала не все файлы
+pub const EXIT_TRANSFER_INCOMPLETE: i32 = 10;
+/// --preflight: адрес назначения не разрешился или не принял TCP соединение
+pub const EXIT_UNREACHABLE: i32 = 11;
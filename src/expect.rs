@@ -0,0 +1,242 @@
+use std::time::{Duration, Instant};
+
+use regex::bytes::Regex;
+
+use crate::normalize::Normalizer;
+use crate::transform::{Transform, TransformEvent};
+
+/// Сколько вывода помнится в ожидании совпадения
+/// Правило может совпасть на границе двух чтений, поэтому вывод копится,
+/// но не бесконечно: от длинного вывода без совпадений остается только хвост
+const EXPECT_BUF_LIMIT: usize = 8192;
+
+/// Образец, который ищется в выводе программы
+#[derive(Debug, Clone)]
+pub enum ExpectPattern {
+    Literal(Vec<u8>),
+    Regex(Regex),
+}
+
+impl ExpectPattern {
+    /// Конец первого совпадения в buf
+    fn find_end(&self, buf: &[u8]) -> Option<usize> {
+        match self {
+            ExpectPattern::Literal(literal) => buf
+                .windows(literal.len())
+                .position(|w| w == literal.as_slice())
+                .map(|start| start + literal.len()),
+            ExpectPattern::Regex(regex) => regex.find(buf).map(|m| m.end()),
+        }
+    }
+}
+
+/// Правило --expect/--send
+#[derive(Debug, Clone)]
+pub struct ExpectRule {
+    pub pattern: ExpectPattern,
+    pub send: Vec<u8>,
+    /// срабатывает на каждое совпадение, а не один раз
+    pub always: bool,
+    /// сколько ждать совпадения однократного правила, после чего сессия прерывается
+    pub timeout: Option<Duration>,
+    source: String,
+}
+
+impl ExpectRule {
+    /// Разбирает пару --expect/--send
+    /// Перед образцом могут стоять модификаторы через ':':
+    /// re: - регулярное выражение, lit: - дальше идет образец как есть,
+    /// once: (по умолчанию) или always:, timeout=SECS:
+    /// В тексте --send понимаются \n, \r, \t, \e, \\ и \xHH
+    pub fn parse(expect: &str, send: &str) -> Result<Self, String> {
+        let mut rest = expect;
+        let mut regex = false;
+        let mut always = false;
+        let mut timeout = None;
+
+        loop {
+            if let Some(r) = rest.strip_prefix("re:") {
+                regex = true;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("once:") {
+                always = false;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("always:") {
+                always = true;
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("timeout=") {
+                let (secs, r) = r
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected timeout=SECS: in '{}'", expect))?;
+                let secs: u64 = secs
+                    .parse()
+                    .map_err(|_| format!("invalid timeout '{}' in '{}'", secs, expect))?;
+                timeout = Some(Duration::from_secs(secs));
+                rest = r;
+            } else {
+                rest = rest.strip_prefix("lit:").unwrap_or(rest);
+                break;
+            }
+        }
+
+        if rest.is_empty() {
+            return Err(format!("empty pattern in '{}'", expect));
+        }
+
+        let pattern = if regex {
+            Regex::new(rest)
+                .map(ExpectPattern::Regex)
+                .map_err(|e| format!("invalid regex '{}': {}", rest, e))?
+        } else {
+            ExpectPattern::Literal(rest.as_bytes().to_vec())
+        };
+
+        Ok(Self {
+            pattern,
+            send: unescape(send)?,
+            always,
+            timeout,
+            source: rest.to_owned(),
+        })
+    }
+}
+
+/// Исполняет правила --expect/--send по выводу программы
+/// Правила упорядочены: однократное правило ждет, пока не сработают все однократные перед ним,
+/// правило always действует с момента, когда сработали все однократные правила перед ним
+#[derive(Debug)]
+pub struct Expect {
+    rules: Vec<ExpectRule>,
+    fired: Vec<bool>,
+    buf: Vec<u8>,
+    /// когда текущее однократное правило начало ждать совпадения
+    waiting_since: Instant,
+    /// правила проверяются по нормализованному выводу (--expect-normalize)
+    normalizer: Option<Normalizer>,
+}
+
+impl Expect {
+    /// normalize - литеральные образцы и вывод нормализуются одинаково,
+    /// регулярные выражения применяются к нормализованному выводу как есть
+    pub fn new(mut rules: Vec<ExpectRule>, normalize: bool) -> Self {
+        if normalize {
+            for rule in rules.iter_mut() {
+                if let ExpectPattern::Literal(literal) = &mut rule.pattern {
+                    *literal = Normalizer::normalize(&String::from_utf8_lossy(literal)).into_bytes();
+                }
+            }
+        }
+
+        Self {
+            fired: vec![false; rules.len()],
+            rules,
+            buf: vec![],
+            waiting_since: Instant::now(),
+            normalizer: normalize.then(Normalizer::default),
+        }
+    }
+
+    /// Обрабатывает очередной фрагмент вывода, возвращает что нужно отправить в pty
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        match self.normalizer.as_mut() {
+            Some(normalizer) => self.buf.extend(normalizer.feed(chunk)),
+            None => self.buf.extend_from_slice(chunk),
+        }
+        let mut sends = vec![];
+
+        loop {
+            // из действующих правил срабатывает то, чье совпадение заканчивается раньше
+            let found = self
+                .active()
+                .filter_map(|i| self.rules[i].pattern.find_end(&self.buf).map(|end| (end, i)))
+                .min();
+
+            let Some((end, i)) = found else {
+                break;
+            };
+
+            self.buf.drain(..end);
+            sends.push(self.rules[i].send.clone());
+            if !self.rules[i].always {
+                self.fired[i] = true;
+                self.waiting_since = Instant::now();
+            }
+        }
+
+        if self.buf.len() > EXPECT_BUF_LIMIT {
+            self.buf.drain(..self.buf.len() - EXPECT_BUF_LIMIT);
+        }
+
+        sends
+    }
+
+    /// Образец однократного правила, которое не дождалось совпадения за свой timeout
+    pub fn expired(&self) -> Option<&str> {
+        let i = self.current()?;
+        let timeout = self.rules[i].timeout?;
+        (self.waiting_since.elapsed() >= timeout).then_some(self.rules[i].source.as_str())
+    }
+
+    /// Первое еще не сработавшее однократное правило
+    fn current(&self) -> Option<usize> {
+        (0..self.rules.len()).find(|&i| !self.rules[i].always && !self.fired[i])
+    }
+
+    fn active(&self) -> impl Iterator<Item = usize> + '_ {
+        let end = self.current().map_or(self.rules.len(), |i| i + 1);
+        (0..end).filter(|&i| self.rules[i].always || !self.fired[i])
+    }
+}
+
+impl Transform for Expect {
+    fn name(&self) -> &'static str {
+        "expect"
+    }
+
+    /// Вывод не меняется, правила только отвечают в pty
+    fn feed(&mut self, chunk: Vec<u8>, events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        for send in Expect::feed(self, &chunk) {
+            events.push(TransformEvent::SendToPty("expect rule", send));
+        }
+        chunk
+    }
+
+    fn tick(&mut self, events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        if let Some(pattern) = self.expired() {
+            events.push(TransformEvent::Timeout(format!("timeout waiting for '{}'", pattern)));
+        }
+        vec![]
+    }
+}
+
+fn unescape(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len());
+    let mut bytes = s.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        match bytes.next() {
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'e') => out.push(0x1b),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hex: Vec<u8> = bytes.by_ref().take(2).collect();
+                let value = std::str::from_utf8(&hex)
+                    .ok()
+                    .filter(|h| h.len() == 2)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                    .ok_or_else(|| format!("invalid \\x escape in '{}'", s))?;
+                out.push(value);
+            }
+            _ => return Err(format!("invalid escape in '{}'", s)),
+        }
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,198 @@
+use std::os::fd::RawFd;
+
+use nix::errno::Errno;
+use nix::unistd::write;
+
+/// Байт, которым sshpass просит пароль у родительского процесса
+pub const PASSWORD_REQUEST: u8 = b'?';
+
+/// Предел длины ответа, чтобы родитель не мог заставить копить данные бесконечно
+const MAX_RESPONSE: usize = 4096;
+
+/// Состояние обмена --password-fd-handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeState {
+    /// запроса пароля еще не было
+    Idle,
+    /// запрос отправлен, ждем строку с паролем
+    Requested,
+    /// пароль получен
+    Received,
+}
+
+/// Пароль по запросу от родительского процесса (--password-fd-handshake REQ_FD:RESP_FD)
+/// Когда программа спрашивает пароль, sshpass пишет PASSWORD_REQUEST в REQ_FD и ждет
+/// первую строку из RESP_FD. До запроса RESP_FD не читается, так что родитель может
+/// отдать пароль в последний момент, а если запроса не было - не отдавать его вовсе
+#[derive(Debug)]
+pub struct Handshake {
+    request_fd: RawFd,
+    state: HandshakeState,
+    buf: Vec<u8>,
+}
+
+/// Ошибка обмена с родительским процессом
+#[derive(Debug)]
+pub enum HandshakeError {
+    /// не удалось отправить запрос
+    Request(Errno),
+    /// родитель закрыл дескриптор, не прислав пароль
+    Closed,
+    /// строка с паролем длиннее MAX_RESPONSE
+    TooLong,
+}
+
+impl std::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HandshakeError::Request(e) => write!(f, "failed to request password: {}", e),
+            HandshakeError::Closed => write!(f, "password response fd closed before the password"),
+            HandshakeError::TooLong => write!(f, "password response is too long"),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
+
+impl Handshake {
+    pub fn new(request_fd: RawFd) -> Self {
+        Self {
+            request_fd,
+            state: HandshakeState::Idle,
+            buf: vec![],
+        }
+    }
+
+    pub fn state(&self) -> HandshakeState {
+        self.state
+    }
+
+    /// Просит пароль у родительского процесса
+    pub fn request(&mut self) -> Result<(), HandshakeError> {
+        // дескриптор принадлежит вызывающему процессу, закрывать его нельзя
+        let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(self.request_fd) };
+        loop {
+            match write(fd, &[PASSWORD_REQUEST]) {
+                Ok(_) => break,
+                Err(Errno::EINTR) => continue,
+                Err(e) => return Err(HandshakeError::Request(e)),
+            }
+        }
+
+        self.state = HandshakeState::Requested;
+        Ok(())
+    }
+
+    /// Копит ответ, пока не придет перевод строки, и возвращает пароль без него
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Option<Vec<u8>>, HandshakeError> {
+        if self.state != HandshakeState::Requested {
+            return Ok(None);
+        }
+
+        self.buf.extend_from_slice(chunk);
+        let Some(pos) = self.buf.iter().position(|&b| b == b'\n') else {
+            return if self.buf.len() > MAX_RESPONSE {
+                Err(HandshakeError::TooLong)
+            } else {
+                Ok(None)
+            };
+        };
+
+        let mut password: Vec<u8> = self.buf.drain(..).take(pos).collect();
+        if password.ends_with(b"\r") {
+            password.pop();
+        }
+
+        self.state = HandshakeState::Received;
+        Ok(Some(password))
+    }
+
+    /// Родитель закрыл дескриптор ответа. Ошибка, только если пароль уже запрошен
+    pub fn closed(&mut self) -> Result<(), HandshakeError> {
+        match self.state {
+            HandshakeState::Requested => Err(HandshakeError::Closed),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::fd::{AsRawFd, OwnedFd};
+
+    use nix::unistd::{pipe, read};
+
+    fn requested() -> (Handshake, OwnedFd, OwnedFd) {
+        let (reader, writer) = pipe().unwrap();
+        let mut handshake = Handshake::new(writer.as_raw_fd());
+        handshake.request().unwrap();
+        (handshake, reader, writer)
+    }
+
+    #[test]
+    fn request_writes_one_byte() {
+        let (handshake, reader, _writer) = requested();
+        let mut buf = [0u8; 4];
+        assert_eq!(read(reader.as_raw_fd(), &mut buf), Ok(1));
+        assert_eq!(buf[0], PASSWORD_REQUEST);
+        assert_eq!(handshake.state(), HandshakeState::Requested);
+    }
+
+    #[test]
+    fn request_error_is_reported() {
+        let (reader, _writer) = pipe().unwrap();
+        let mut handshake = Handshake::new(reader.as_raw_fd());
+        assert!(matches!(handshake.request(), Err(HandshakeError::Request(Errno::EBADF))));
+        assert_eq!(handshake.state(), HandshakeState::Idle);
+    }
+
+    #[test]
+    fn response_before_request_is_ignored() {
+        let (_reader, writer) = pipe().unwrap();
+        let mut handshake = Handshake::new(writer.as_raw_fd());
+        assert!(matches!(handshake.feed(b"early\n"), Ok(None)));
+
+        handshake.request().unwrap();
+        assert_eq!(handshake.feed(b"secret\n").unwrap(), Some(b"secret".to_vec()));
+    }
+
+    #[test]
+    fn response_split_between_reads() {
+        let (mut handshake, _reader, _writer) = requested();
+        assert_eq!(handshake.feed(b"sec").unwrap(), None);
+        assert_eq!(handshake.feed(b"").unwrap(), None);
+        assert_eq!(handshake.feed(b"ret\r").unwrap(), None);
+        assert_eq!(handshake.feed(b"\nnext\n").unwrap(), Some(b"secret".to_vec()));
+        assert_eq!(handshake.state(), HandshakeState::Received);
+
+        // второй строки sshpass уже не ждет
+        assert_eq!(handshake.feed(b"again\n").unwrap(), None);
+    }
+
+    #[test]
+    fn empty_password_line() {
+        let (mut handshake, _reader, _writer) = requested();
+        assert_eq!(handshake.feed(b"\n").unwrap(), Some(vec![]));
+    }
+
+    #[test]
+    fn too_long_response() {
+        let (mut handshake, _reader, _writer) = requested();
+        assert_eq!(handshake.feed(&[b'x'; MAX_RESPONSE]).unwrap(), None);
+        assert!(matches!(handshake.feed(b"x"), Err(HandshakeError::TooLong)));
+    }
+
+    #[test]
+    fn closed_is_an_error_only_while_waiting() {
+        let (_reader, writer) = pipe().unwrap();
+        let mut handshake = Handshake::new(writer.as_raw_fd());
+        assert!(handshake.closed().is_ok());
+
+        handshake.request().unwrap();
+        assert!(matches!(handshake.closed(), Err(HandshakeError::Closed)));
+
+        handshake.feed(b"secret\n").unwrap();
+        assert!(handshake.closed().is_ok());
+    }
+}
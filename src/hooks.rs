@@ -0,0 +1,173 @@
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use log::{error, info, trace};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use crate::unix::ChildUsage;
+
+/// Чем закончилась сессия, от этого зависит какой хук запускается
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// сессию прервал --timeout, --prompt-timeout или правило --expect, не дождавшись совпадения
+    Timeout,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Timeout => "timeout",
+        }
+    }
+}
+
+/// Команды, которые запускаются после завершения сессии (--on-success, --on-failure, --on-timeout)
+#[derive(Debug, Clone, Default)]
+pub struct ExitHooks {
+    pub on_success: Option<String>,
+    pub on_failure: Option<String>,
+    /// без --on-timeout при таймауте запускается --on-failure
+    pub on_timeout: Option<String>,
+    pub timeout: Duration,
+}
+
+/// Сведения о сессии, передаются хуку через окружение
+#[derive(Debug)]
+pub struct SessionInfo<'a> {
+    pub outcome: Outcome,
+    pub exit_code: i32,
+    pub duration: Duration,
+    pub program: &'a str,
+    pub host: Option<&'a str>,
+    pub log_file: Option<&'a PathBuf>,
+    /// ресурсы, потраченные программой, если ее удалось дождаться
+    pub usage: Option<ChildUsage>,
+}
+
+impl ExitHooks {
+    /// Запускает хук для исхода сессии и ждет его не дольше timeout
+    /// Вывод хука (stdout и stderr) пишется в лог, на код выхода sshpass хук не влияет
+    pub fn run(&self, session: &SessionInfo<'_>) {
+        let command = match session.outcome {
+            Outcome::Success => self.on_success.as_ref(),
+            Outcome::Failure => self.on_failure.as_ref(),
+            Outcome::Timeout => self.on_timeout.as_ref().or(self.on_failure.as_ref()),
+        };
+        let Some(command) = command else {
+            return;
+        };
+
+        info!("run {} hook", session.outcome.as_str());
+        match run_hook(command, session, self.timeout) {
+            Ok((status, output)) => {
+                for line in String::from_utf8_lossy(&output).lines() {
+                    info!("hook: {}", line);
+                }
+
+                match status {
+                    Some(status) => info!("{} hook exit: {}", session.outcome.as_str(), status),
+                    None => {
+                        error!("{} hook timed out after {:?}", session.outcome.as_str(), self.timeout);
+                        eprintln!(
+                            "sshpass: {} hook timed out after {:?}",
+                            session.outcome.as_str(),
+                            self.timeout
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{} hook failed: {}", session.outcome.as_str(), e);
+                eprintln!("sshpass: {} hook failed: {}", session.outcome.as_str(), e);
+            }
+        }
+    }
+}
+
+/// Код выхода хука (None если он не уложился в timeout и был убит) и его вывод
+fn run_hook(
+    command: &str,
+    session: &SessionInfo<'_>,
+    timeout: Duration,
+) -> std::io::Result<(Option<ExitStatus>, Vec<u8>)> {
+    let mut cmd = Command::new("sh");
+    // stderr хука идет в тот же pipe, что и stdout, и попадает в лог вместе с ним
+    cmd.arg("-c")
+        .arg(format!("exec 2>&1\n{}", command))
+        .env("SSHPASS_OUTCOME", session.outcome.as_str())
+        .env("SSHPASS_EXIT_CODE", session.exit_code.to_string())
+        .env("SSHPASS_DURATION_MS", session.duration.as_millis().to_string())
+        .env("SSHPASS_SESSION_ID", crate::artifact::session_id())
+        .env("SSHPASS_PROGRAM", session.program)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    if let Some(host) = session.host {
+        cmd.env("SSHPASS_HOST", host);
+    }
+    if let Some(log_file) = session.log_file {
+        cmd.env("SSHPASS_LOG_PATH", log_file);
+    }
+    if let Some(usage) = &session.usage {
+        cmd.env("SSHPASS_CHILD_MAX_RSS_KB", usage.max_rss_kb.to_string())
+            .env("SSHPASS_CHILD_USER_MS", usage.user.as_millis().to_string())
+            .env("SSHPASS_CHILD_SYS_MS", usage.system.as_millis().to_string());
+    }
+
+    trace!("spawn hook");
+    let mut child = cmd.spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    let deadline = Instant::now() + timeout;
+    let mut output = vec![];
+    let mut buf = [0u8; 1024];
+
+    let timed_out = loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break true;
+        }
+
+        let mut fds = [PollFd::new(stdout.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX)) {
+            Err(Errno::EINTR) | Ok(0) => continue,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e.into());
+            }
+            Ok(_) => {}
+        }
+
+        match stdout.read(&mut buf) {
+            Ok(0) => break false,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        }
+    };
+
+    // stdout закрыт, но хук еще может работать
+    while !timed_out && Instant::now() < deadline {
+        if let Some(status) = child.try_wait()? {
+            return Ok((Some(status), output));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    trace!("hook timeout, kill pid {}", child.id());
+    let _ = child.kill();
+    let _ = child.wait();
+    Ok((None, output))
+}
@@ -0,0 +1,337 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+use time::{Date, OffsetDateTime};
+
+use crate::artifact::ArtifactTemplate;
+use crate::backoff::Backoff;
+use crate::redact;
+
+/// Сокет syslog
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// Сокет нативного протокола journald
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+/// facility user
+const SYSLOG_FACILITY_USER: u8 = 1;
+/// Как часто файл лога проверяется на смену суток и внешнюю ротацию
+const LOG_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Задержки между попытками переоткрыть файл лога или переподключиться к сокету
+const REOPEN_INITIAL_DELAY: Duration = Duration::from_secs(1);
+const REOPEN_MAX_DELAY: Duration = Duration::from_secs(60);
+/// Сколько раз подряд переподключаться к syslog/journald, прежде чем сделать паузу
+const RECONNECT_ATTEMPTS: u32 = 5;
+const RECONNECT_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// Куда пишется лог, выбирается через SSHPASS_LOG_SINK
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSink {
+    File,
+    Syslog,
+    Journald,
+}
+
+impl std::str::FromStr for LogSink {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "file" => Ok(LogSink::File),
+            "syslog" => Ok(LogSink::Syslog),
+            "journald" => Ok(LogSink::Journald),
+            _ => Err(format!("unknown log sink '{}', expected file, syslog or journald", s)),
+        }
+    }
+}
+
+/// Файл лога для SSHPASS_LOG_SINK=file
+/// Шаблон с {date} после смены суток дает новый файл (с недостающими каталогами),
+/// а файл, который переименовали или удалили снаружи (logrotate), открывается заново по тому же пути
+pub struct LogFile {
+    file: File,
+    path: PathBuf,
+    /// шаблон и значения для него, None - путь фиксированный
+    template: Option<(ArtifactTemplate, String, String)>,
+    date: Date,
+    last_check: Instant,
+    /// файл не открылся: каталог удален, диск заполнен, нет прав
+    reopen: Backoff,
+}
+
+impl LogFile {
+    /// Создает файл по шаблону SSHPASS_LOG_FILE
+    pub fn create(template: &str, program: &str, host: &str) -> io::Result<Self> {
+        let template = ArtifactTemplate::new(template);
+        let (path, file) = template.create(program, host)?;
+
+        Ok(Self {
+            file,
+            path,
+            template: Some((template, program.to_owned(), host.to_owned())),
+            date: today(),
+            last_check: Instant::now(),
+            reopen: Backoff::new(REOPEN_INITIAL_DELAY, REOPEN_MAX_DELAY),
+        })
+    }
+
+    /// Файл по фиксированному пути, содержимое перезаписывается
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+
+        Ok(Self {
+            file: File::create(&path)?,
+            path,
+            template: None,
+            date: today(),
+            last_check: Instant::now(),
+            reopen: Backoff::new(REOPEN_INITIAL_DELAY, REOPEN_MAX_DELAY),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Переключает файл, если пора. Ошибки не прерывают запись:
+    /// залогировать их некуда, запись продолжается в прежний файл
+    fn check(&mut self) {
+        if self.last_check.elapsed() < LOG_FILE_CHECK_INTERVAL {
+            return;
+        }
+        self.last_check = Instant::now();
+
+        let today = today();
+        if let Some((template, program, host)) = &self.template {
+            if template.has_date() && today != self.date {
+                if !self.reopen.ready() {
+                    return;
+                }
+                match template.create(program, host) {
+                    Ok((path, file)) => {
+                        self.file = file;
+                        self.path = path;
+                        self.date = today;
+                        self.reopen.succeeded();
+                    }
+                    Err(_) => self.reopen.failed(),
+                }
+                return;
+            }
+        }
+
+        if self.rotated() && self.reopen.ready() {
+            match OpenOptions::new().append(true).create(true).open(&self.path) {
+                Ok(file) => {
+                    self.file = file;
+                    self.reopen.succeeded();
+                }
+                Err(_) => self.reopen.failed(),
+            }
+        }
+    }
+
+    /// По пути лежит уже не тот файл, в который идет запись, или там нет ничего
+    fn rotated(&self) -> bool {
+        match (std::fs::metadata(&self.path), self.file.metadata()) {
+            (Err(e), _) => e.kind() == io::ErrorKind::NotFound,
+            (Ok(path), Ok(file)) => path.ino() != file.ino() || path.dev() != file.dev(),
+            (Ok(_), Err(_)) => false,
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check();
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn today() -> Date {
+    OffsetDateTime::now_local()
+        .unwrap_or_else(|_| OffsetDateTime::now_utc())
+        .date()
+}
+
+/// Логгер, отправляющий записи датаграммами в локальный syslog или journald
+/// Секреты маскируются здесь же: RedactWriter стоит только перед файлом
+pub struct SocketLogger {
+    sink: LogSink,
+    level: LevelFilter,
+    socket: UnixDatagram,
+    path: &'static str,
+    /// syslog или journald перезапустили, сокет нужно подключить заново
+    reconnect: Mutex<Backoff>,
+    pid: u32,
+    /// метки сессии, в journald уходят полями SSHPASS_LABEL_<KEY>
+    labels: Vec<(String, String)>,
+}
+
+impl SocketLogger {
+    pub fn new(
+        sink: LogSink,
+        level: LevelFilter,
+        labels: &[(String, String)],
+    ) -> io::Result<Box<Self>> {
+        let path = match sink {
+            LogSink::Syslog => SYSLOG_SOCKET,
+            LogSink::Journald => JOURNALD_SOCKET,
+            LogSink::File => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "file is not a socket sink"))
+            }
+        };
+
+        let socket = UnixDatagram::unbound()?;
+        socket
+            .connect(path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+
+        Ok(Box::new(Self {
+            sink,
+            level,
+            socket,
+            path,
+            reconnect: Mutex::new(
+                Backoff::new(REOPEN_INITIAL_DELAY, REOPEN_MAX_DELAY)
+                    .max_attempts(RECONNECT_ATTEMPTS)
+                    .cooldown(RECONNECT_COOLDOWN),
+            ),
+            pid: std::process::id(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (journald_field(key), value.clone()))
+                .collect(),
+        }))
+    }
+
+    fn syslog_datagram(&self, record: &Record<'_>, message: &[u8]) -> Vec<u8> {
+        let priority = SYSLOG_FACILITY_USER * 8 + severity(record.level());
+        let mut datagram = format!("<{}>sshpass[{}]: ", priority, self.pid).into_bytes();
+        datagram.extend_from_slice(message);
+        datagram
+    }
+
+    fn journald_datagram(&self, record: &Record<'_>, message: &[u8]) -> Vec<u8> {
+        let mut datagram = vec![];
+        let priority = severity(record.level()).to_string();
+        journald_append(&mut datagram, "MESSAGE", message);
+        journald_append(&mut datagram, "PRIORITY", priority.as_bytes());
+        journald_append(&mut datagram, "SYSLOG_IDENTIFIER", b"sshpass");
+        journald_append(&mut datagram, "SSHPASS_TARGET", record.target().as_bytes());
+        journald_append(
+            &mut datagram,
+            "SSHPASS_SESSION_ID",
+            crate::artifact::session_id().as_bytes(),
+        );
+        for (key, value) in &self.labels {
+            journald_append(&mut datagram, key, value.as_bytes());
+        }
+        datagram
+    }
+
+    /// Подключается к сокету заново, если задержка после прошлой неудачи прошла
+    fn reconnect(&self) -> bool {
+        let Ok(mut backoff) = self.reconnect.lock() else {
+            return false;
+        };
+        if !backoff.ready() {
+            return false;
+        }
+
+        match self.socket.connect(self.path) {
+            Ok(()) => {
+                backoff.succeeded();
+                true
+            }
+            Err(_) => {
+                backoff.failed();
+                false
+            }
+        }
+    }
+}
+
+impl Log for SocketLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = redact::redact(record.args().to_string().as_bytes());
+        let datagram = match self.sink {
+            LogSink::Journald => self.journald_datagram(record, &message),
+            _ => self.syslog_datagram(record, &message),
+        };
+
+        // потерянная запись лога не повод прерывать сессию
+        if self.socket.send(&datagram).is_err() && self.reconnect() {
+            let _ = self.socket.send(&datagram);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+impl SharedLogger for SocketLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// Уровень syslog (RFC 5424)
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Имя поля journald: только A-Z, 0-9 и '_'
+fn journald_field(label: &str) -> String {
+    let key: String = label
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9') => c,
+            _ => '_',
+        })
+        .collect();
+    format!("SSHPASS_LABEL_{}", key)
+}
+
+/// Поле нативного протокола journald
+/// Значение с переводом строки передается в бинарном виде: имя, '\n', длина (u64 LE), данные
+fn journald_append(datagram: &mut Vec<u8>, field: &str, value: &[u8]) {
+    datagram.extend_from_slice(field.as_bytes());
+    if value.contains(&b'\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value);
+    datagram.push(b'\n');
+}
@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger};
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, UtcOffset};
+
+/// Формат записей в лог файле
+/// Выбирается через переменную окружения SSHPASS_LOG_FORMAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}', expected text or json", s)),
+        }
+    }
+}
+
+/// Логгер, который пишет каждую запись отдельной JSON строкой
+/// {"timestamp": ..., "level": ..., "target": ..., "pid": ..., "labels": {...}, "message": ...}
+/// Такой формат легко разбирается сборщиками логов
+pub struct JsonLogger<W: Write + Send + 'static> {
+    level: LevelFilter,
+    pid: u32,
+    /// метки сессии (--label), одинаковые для всех записей
+    labels: serde_json::Map<String, serde_json::Value>,
+    offset: UtcOffset,
+    writable: Mutex<W>,
+}
+
+impl<W: Write + Send + 'static> JsonLogger<W> {
+    pub fn new(level: LevelFilter, writable: W, labels: &[(String, String)]) -> Box<Self> {
+        // смещение берется один раз при старте, пока процесс однопоточный
+        let offset = UtcOffset::current_local_offset().unwrap_or(UtcOffset::UTC);
+
+        Box::new(Self {
+            level,
+            pid: std::process::id(),
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone().into()))
+                .collect(),
+            offset,
+            writable: Mutex::new(writable),
+        })
+    }
+
+    fn message_format(&self, record: &Record<'_>) -> String {
+        let timestamp = OffsetDateTime::now_utc()
+            .to_offset(self.offset)
+            .format(&Rfc3339)
+            .unwrap_or_default();
+
+        let entry = serde_json::json!({
+            "timestamp": timestamp,
+            "level": record.level().as_str(),
+            "target": record.target(),
+            "pid": self.pid,
+            "labels": self.labels,
+            "message": record.args().to_string(),
+        });
+
+        entry.to_string()
+    }
+}
+
+impl<W: Write + Send + 'static> Log for JsonLogger<W> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = self.message_format(record);
+        if let Ok(mut writable) = self.writable.lock() {
+            let _ = writeln!(writable, "{}", line);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut writable) = self.writable.lock() {
+            let _ = writable.flush();
+        }
+    }
+}
+
+impl<W: Write + Send + 'static> SharedLogger for JsonLogger<W> {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// За сколько секунд запас записей накапливает один (уровень, источник):
+/// емкость token bucket равна rate * RATE_BURST_SECS
+const RATE_BURST_SECS: f64 = 1.0;
+
+#[derive(Debug)]
+struct Source {
+    last_message: String,
+    repeated: u64,
+    tokens: f64,
+    refilled: Instant,
+    suppressed: u64,
+}
+
+/// Обертка над логгером, защищающая лог от потока одинаковых ошибок
+/// Подряд идущие одинаковые сообщения одного источника схлопываются в
+/// "last message repeated N times", а при заданном rate (SSHPASS_LOG_RATE)
+/// каждый (уровень, источник) ограничен rate записями в секунду (token bucket),
+/// о пропущенных записях сообщается, когда лимит восстановится
+pub struct DedupLogger {
+    inner: Box<dyn SharedLogger>,
+    /// записей в секунду на (уровень, источник), None - без ограничения
+    rate: Option<f64>,
+    sources: Mutex<HashMap<(Level, String), Source>>,
+}
+
+impl DedupLogger {
+    pub fn new(inner: Box<dyn SharedLogger>, rate: Option<u32>) -> Box<Self> {
+        Box::new(Self {
+            inner,
+            rate: rate.filter(|rate| *rate > 0).map(f64::from),
+            sources: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn summary(&self, level: Level, target: &str, args: fmt::Arguments<'_>) {
+        self.inner.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(args)
+                .build(),
+        );
+    }
+
+    fn flush_repeated(&self, level: Level, target: &str, source: &mut Source) {
+        if source.repeated > 0 {
+            self.summary(
+                level,
+                target,
+                format_args!("last message repeated {} times", source.repeated),
+            );
+            source.repeated = 0;
+        }
+    }
+}
+
+impl Log for DedupLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let Ok(mut sources) = self.sources.lock() else {
+            return;
+        };
+        let message = record.args().to_string();
+        let source = sources
+            .entry((record.level(), record.target().to_owned()))
+            .or_insert_with(|| Source {
+                last_message: String::new(),
+                repeated: 0,
+                tokens: self.rate.unwrap_or(0.0) * RATE_BURST_SECS,
+                refilled: Instant::now(),
+                suppressed: 0,
+            });
+
+        if !source.last_message.is_empty() && source.last_message == message {
+            source.repeated += 1;
+            return;
+        }
+        self.flush_repeated(record.level(), record.target(), source);
+
+        if let Some(rate) = self.rate {
+            let now = Instant::now();
+            let refill = now.duration_since(source.refilled).as_secs_f64() * rate;
+            source.tokens = (source.tokens + refill).min(rate * RATE_BURST_SECS);
+            source.refilled = now;
+
+            if source.tokens < 1.0 {
+                source.suppressed += 1;
+                return;
+            }
+            source.tokens -= 1.0;
+
+            if source.suppressed > 0 {
+                self.summary(
+                    record.level(),
+                    record.target(),
+                    format_args!("{} messages suppressed by rate limit", source.suppressed),
+                );
+                source.suppressed = 0;
+            }
+        }
+
+        source.last_message = message;
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sources) = self.sources.lock() {
+            for ((level, target), source) in sources.iter_mut() {
+                self.flush_repeated(*level, target, source);
+                if source.suppressed > 0 {
+                    self.summary(
+                        *level,
+                        target,
+                        format_args!("{} messages suppressed by rate limit", source.suppressed),
+                    );
+                    source.suppressed = 0;
+                }
+            }
+        }
+
+        self.inner.flush();
+    }
+}
+
+impl SharedLogger for DedupLogger {
+    fn level(&self) -> LevelFilter {
+        self.inner.level()
+    }
+
+    fn config(&self) -> Option<&Config> {
+        self.inner.config()
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Логгер, запоминающий записи "уровень target: сообщение"
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            let line = format!("{} {}: {}", record.level(), record.target(), record.args());
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn flush(&self) {}
+    }
+
+    impl SharedLogger for Capture {
+        fn level(&self) -> LevelFilter {
+            LevelFilter::Trace
+        }
+
+        fn config(&self) -> Option<&Config> {
+            None
+        }
+
+        fn as_log(self: Box<Self>) -> Box<dyn Log> {
+            Box::new(*self)
+        }
+    }
+
+    fn logger(rate: Option<u32>) -> (Box<DedupLogger>, Capture) {
+        let capture = Capture::default();
+        (DedupLogger::new(Box::new(capture.clone()), rate), capture)
+    }
+
+    fn log(logger: &DedupLogger, level: Level, target: &str, message: &str) {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("{}", message))
+                .build(),
+        );
+    }
+
+    fn lines(capture: &Capture) -> Vec<String> {
+        capture.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn repeated_messages_are_collapsed() {
+        let (logger, capture) = logger(None);
+        for _ in 0..3 {
+            log(&logger, Level::Error, "poll", "read failed");
+        }
+        log(&logger, Level::Error, "poll", "write failed");
+
+        assert_eq!(
+            lines(&capture),
+            [
+                "ERROR poll: read failed",
+                "ERROR poll: last message repeated 2 times",
+                "ERROR poll: write failed",
+            ]
+        );
+    }
+
+    #[test]
+    fn flush_reports_pending_repeats() {
+        let (logger, capture) = logger(None);
+        log(&logger, Level::Warn, "pty", "busy");
+        log(&logger, Level::Warn, "pty", "busy");
+        logger.flush();
+        logger.flush();
+
+        assert_eq!(lines(&capture), ["WARN pty: busy", "WARN pty: last message repeated 1 times"]);
+    }
+
+    #[test]
+    fn sources_are_tracked_separately() {
+        let (logger, capture) = logger(None);
+        log(&logger, Level::Warn, "pty", "busy");
+        log(&logger, Level::Error, "pty", "busy");
+        log(&logger, Level::Warn, "stdin", "busy");
+        log(&logger, Level::Warn, "pty", "busy");
+
+        assert_eq!(lines(&capture), ["WARN pty: busy", "ERROR pty: busy", "WARN stdin: busy"]);
+        logger.flush();
+        assert_eq!(lines(&capture)[3], "WARN pty: last message repeated 1 times");
+    }
+
+    #[test]
+    fn rate_limit_suppresses_after_burst() {
+        let (logger, capture) = logger(Some(2));
+        for n in 0..5 {
+            log(&logger, Level::Info, "io", &format!("chunk {}", n));
+        }
+        // другой источник не делит запас с первым
+        log(&logger, Level::Info, "pty", "chunk");
+        logger.flush();
+
+        let mut lines = lines(&capture);
+        lines.sort();
+        assert_eq!(
+            lines,
+            [
+                "INFO io: 3 messages suppressed by rate limit",
+                "INFO io: chunk 0",
+                "INFO io: chunk 1",
+                "INFO pty: chunk",
+            ]
+        );
+    }
+
+    #[test]
+    fn rate_limit_reports_suppressed_when_refilled() {
+        let (logger, capture) = logger(Some(1));
+        for n in 0..3 {
+            log(&logger, Level::Info, "io", &format!("chunk {}", n));
+        }
+
+        // прошла секунда, запас восстановился
+        for source in logger.sources.lock().unwrap().values_mut() {
+            source.refilled -= Duration::from_secs(1);
+        }
+        log(&logger, Level::Info, "io", "chunk 3");
+
+        assert_eq!(
+            lines(&capture),
+            [
+                "INFO io: chunk 0",
+                "INFO io: 2 messages suppressed by rate limit",
+                "INFO io: chunk 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeats_do_not_spend_rate_tokens() {
+        let (logger, capture) = logger(Some(1));
+        for _ in 0..10 {
+            log(&logger, Level::Info, "io", "same");
+        }
+        logger.flush();
+
+        assert_eq!(lines(&capture), ["INFO io: same", "INFO io: last message repeated 9 times"]);
+    }
+
+    #[test]
+    fn zero_rate_means_no_limit() {
+        let (logger, capture) = logger(Some(0));
+        for n in 0..100 {
+            log(&logger, Level::Info, "io", &format!("chunk {}", n));
+        }
+
+        assert_eq!(lines(&capture).len(), 100);
+    }
+}
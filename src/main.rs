@@ -1,191 +1,595 @@
-use clap::{Arg, ArgGroup, Command};
-use log::trace;
+use bytes::Bytes;
+use clap::{CommandFactory, Parser};
+use log::{error, info, trace, warn};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::sys::resource::{getrusage, UsageWho};
+use nix::errno::Errno;
 use nix::sys::signal::Signal;
-use std::str::FromStr;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
 use std::cell::Ref;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{FromRawFd, OwnedFd};
+use std::path::PathBuf;
 use std::sync::mpsc;
+use std::time::{Duration, Instant};
 
+mod after_auth;
 mod app;
+mod artifact;
+mod askpass;
+mod audit;
+mod backoff;
+mod cli;
+mod confirm;
+mod handshake;
+mod control;
+mod doctor;
+mod escape;
+mod exit_code;
+mod hooks;
+#[cfg(feature = "rule-engine")]
+mod expect;
+mod log_sink;
+mod logger;
+mod mode;
+mod normalize;
+mod pager;
+mod pidfile;
+mod preflight;
+mod prompt;
+mod redact;
+mod secrets;
+mod selftest;
+mod signal_action;
+mod target;
+mod transform;
+mod translate;
+mod verbose;
+use after_auth::AfterAuth;
+use audit::{AuditEvent, AuditLog};
+use cli::{Cli, CliCommand, Config};
+use confirm::{confirm, Confirmation};
+use escape::EscapeFilter;
+use exit_code::{
+    EXIT_INCORRECT_PASSWORD, EXIT_PROMPT_TIMEOUT, EXIT_RUNTIME_ERROR, EXIT_SESSION_TIMEOUT,
+    EXIT_TRANSFER_INCOMPLETE, EXIT_UNREACHABLE,
+};
+use handshake::{Handshake, HandshakeState};
+use hooks::{Outcome, SessionInfo};
+#[cfg(feature = "rule-engine")]
+use expect::Expect;
+use log_sink::{LogFile, LogSink, SocketLogger};
+use logger::{DedupLogger, JsonLogger, LogFormat};
+use mode::SessionMode;
+use pager::Pager;
+use pidfile::PidFile;
+use prompt::{PasswordPrompt, PromptEvent};
+use redact::RedactWriter;
+use secrets::PasswordSource;
+use signal_action::SignalAction;
+use target::{parse_target, Target};
+use transform::{Pipeline, TransformEvent};
+use translate::{StripAnsi, StripCr};
+use verbose::{Verbose, VERBOSE_EVENTS, VERBOSE_POLL, VERBOSE_TRAFFIC};
 
 #[cfg(target_os = "linux")]
 mod unix;
-use unix::{UnixApp, UnixAppStop, UnixError, UnixEvent};
-
-fn cli() -> Command {
-    Command::new("sshpass")
-        .version("1.0")
-        .about("Non-interactive ssh password provider")
-        .arg(
-            Arg::new("password")
-                .short('p')
-                .long("password")
-                .value_name("PASSWORD")
-                .help("Provide password as argument (security unwise)"),
-        )
-        .arg(
-            Arg::new("filename")
-                .short('f')
-                .long("file")
-                .value_name("FILENAME")
-                .help("Take password to use from file"),
-        )
-        .arg(
-            Arg::new("fd")
-                .short('d')
-                .long("fd")
-                .value_name("FD")
-                .help("Use number as file descriptor for getting password"),
-        )
-        .arg(
-            Arg::new("env")
-                .short('e')
-                .long("env")
-                .value_name("ENV")
-                .help("Password is passed as env-var 'SSHPASS'"),
-        )
-        .arg(
-            Arg::new("prompt")
-                .short('P')
-                .long("prompt")
-                .value_name("PROMPT")
-                .help("Which string should sshpass search for to detect a password prompt"),
-        )
-        .arg(
-            Arg::new("verbose")
-                .short('v')
-                .long("verbose")
-                .value_name("VERBOSE")
-                .help("Be verbose about what you're doing"),
-        )
-        .arg(
-            Arg::new("otp-secret")
-                .long("otp-secret")
-                .help("One time secret in argument"),
-        )
-        .arg(
-            Arg::new("otp-secret-file")
-                .long("otp-secret-file")
-                .help("One time secret in file"),
-        )
-        .arg(
-            Arg::new("otp-secret-env")
-                .env("SSHPASS_OTP_SECRET")
-                .long("otp-secret-env")
-                .help("One time secret is passed as env"),
-        )
-        .arg(
-            Arg::new("otp-secret-fd")
-                .long("otp-secret-fd")
-                .help("Use number as file descriptor for getting otp secret"),
-        )
-        .arg(
-            Arg::new("otp-code")
-                .long("otp-code")
-                .help("One time code in argument"),
-        )
-        .arg(
-            Arg::new("otp-code-file")
-                .long("otp-code-file")
-                .help("One time code in file"),
-        )
-        .arg(
-            Arg::new("otp-code-env")
-                .env("SSHPASS_OTP_CODE")
-                .long("otp-code-env")
-                .help("One time code is passed as env"),
-        )
-        .arg(
-            Arg::new("otp-code-fd")
-                .long("otp-code-fd")
-                .help("Use number as file descriptor for getting otp code"),
-        )
-        .arg(
-            Arg::new("otp-prompt")
-                .short('O')
-                .long("otp-prompt")
-                .help("Which string should sshpass search for the one time password prompt"),
-        )
-        .group(
-            ArgGroup::new("password-conflict")
-                .args(["password"])
-                .conflicts_with_all(["filename", "fd", "env"]),
-        )
-        .group(
-            ArgGroup::new("otp-conflict")
-                .args(["otp-secret"])
-                .conflicts_with_all([
-                    "otp-secret-file",
-                    "otp-secret-fd",
-                    "otp-secret-env",
-                    "otp-code",
-                    "otp-code-file",
-                    "otp-code-fd",
-                    "otp-code-env",
-                ]),
-        )
-        .arg(
-            Arg::new("program")
-                .help("Program to execute")
-                .required(true)
-                .num_args(1),
-        )
-        .arg(
-            Arg::new("program_args")
-                .help("arguments that will be passed to the program being run")
-                .required(false)
-                .num_args(1..)
-                .allow_hyphen_values(true)
-                .trailing_var_arg(true),
-        )
-}
+use unix::{
+    apply_limits, ChildUsage, StopStage, StopState, TerminalInfo, UnixApp, UnixAppStop, UnixError,
+    UnixEvent,
+};
+
+/// Сколько ждать завершения дочернего процесса после начала остановки
+const STOPPING_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Сколько клиентов --attach-socket может быть подключено одновременно
+const ATTACH_MAX_CLIENTS: usize = 4;
+
+/// ^C, в режиме --read-only завершает сессию
+const CTRL_C: u8 = 0x03;
+
+/// Как часто собирать завершившиеся дочерние процессы, даже если SIGCHLD не пришел
+const REAP_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Как часто печатать статистику poll при -vvv
+const POLL_STATS_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 enum UnixEventResponse<'a> {
+    #[allow(dead_code)]
     SendTo(usize, Ref<'a, [u8]>),
-    WriteToStdOut(Ref<'a, [u8]>),
+    /// кусок вывода программы, общий с очередями клиентов --attach-socket
+    WriteToStdOut(Bytes),
+    #[allow(dead_code)]
     WriteToStdIn(Ref<'a, [u8]>),
     WriteToPtyMaster(Ref<'a, [u8]>),
+    WriteBytesToStdOut(Vec<u8>),
+    WriteBytesToPtyMaster(Vec<u8>),
 }
 
 fn main() {
-    if let Ok(level) = std::env::var("SSHPASS_LOG") {
-        let level = log::LevelFilter::from_str(&level).unwrap();
-
-        let config = simplelog::ConfigBuilder::new()
-            .set_time_format_rfc3339()
-            .set_time_offset_to_local()
-            .unwrap()
-            .set_max_level(level)
-            .build();
-
-        simplelog::CombinedLogger::init(vec![simplelog::WriteLogger::new(
-            level,
-            config,
-            std::fs::File::create("sshpass.log").unwrap(),
-        )])
-        .unwrap();
+    // sshpass запущен ssh в роли SSH_ASKPASS (режим --askpass)
+    if let Some(code) = askpass::helper() {
+        std::process::exit(code);
+    }
+
+    let cli = Cli::parse();
+
+    // подкоманды выполняются до запуска цикла событий
+    match &cli.command {
+        Some(CliCommand::Completions { shell }) => {
+            clap_complete::generate(*shell, &mut Cli::command(), "sshpass", &mut std::io::stdout());
+            return;
+        }
+        Some(CliCommand::SelftestChild(args)) => std::process::exit(selftest::run(args)),
+        Some(CliCommand::Doctor(args)) => std::process::exit(doctor::run(args)),
+        None => {}
+    }
+
+    let config = match Config::try_from(cli) {
+        Ok(config) => config,
+        Err(e) => Cli::command().error(e.kind(), e).exit(),
+    };
+
+    let target = parse_target(&config.program, &config.program_args);
+
+    // пароль из аргумента виден уже в config, маскируется до первой записи в лог
+    if let Some(PasswordSource::Argument(password)) = &config.password {
+        redact::register(password.as_bytes());
     }
 
-    let args = cli().get_matches();
-    trace!("mach arguments {:#?}", args);
+    // путь к логу передается хукам
+    let mut log_path = None;
+    if let Some(log_config) = &config.log {
+        let level = log_config.level;
+        let logger: Box<dyn simplelog::SharedLogger> = if log_config.sink != LogSink::File {
+            match SocketLogger::new(log_config.sink, level, &config.labels) {
+                Ok(logger) => logger,
+                Err(e) => {
+                    eprintln!("sshpass: failed to open log sink: {}", e);
+                    std::process::exit(EXIT_RUNTIME_ERROR);
+                }
+            }
+        } else {
+            // без SSHPASS_LOG_FILE лог по старому пишется в ./sshpass.log с перезаписью
+            let file = match &log_config.file {
+                Some(template) => {
+                    let host = target.as_ref().map(|t| t.host.as_str()).unwrap_or("local");
+                    let file = match LogFile::create(template, &config.program, host) {
+                        Ok(file) => file,
+                        Err(e) => {
+                            eprintln!("sshpass: failed to create log file {}: {}", template, e);
+                            std::process::exit(EXIT_RUNTIME_ERROR);
+                        }
+                    };
+                    log_path = Some(file.path().to_owned());
+                    file
+                }
+                None => match LogFile::open("sshpass.log") {
+                    Ok(file) => file,
+                    Err(e) => {
+                        eprintln!("sshpass: failed to open log file sshpass.log: {}", e);
+                        std::process::exit(EXIT_RUNTIME_ERROR);
+                    }
+                },
+            };
+            let file = RedactWriter::new(file);
+
+            match log_config.format {
+                LogFormat::Text => {
+                    let config = simplelog::ConfigBuilder::new()
+                        .set_time_format_rfc3339()
+                        .set_time_offset_to_local()
+                        .unwrap()
+                        .set_max_level(level)
+                        .build();
+
+                    simplelog::WriteLogger::new(level, config, file)
+                }
+                LogFormat::Json => JsonLogger::new(level, file, &config.labels),
+            }
+        };
+
+        let logger = DedupLogger::new(logger, log_config.rate);
+        simplelog::CombinedLogger::init(vec![logger]).unwrap();
+    }
+
+    trace!("session {}", artifact::session_id());
+    if let Some(target) = &target {
+        trace!("target {}", target);
+    }
+    trace!("config {:#?}", config);
+
+    // до запуска дочернего процесса, чтобы он унаследовал привязку и приоритеты
+    if let Err(e) = config.sched.apply() {
+        error!("{}", e);
+        eprintln!("sshpass: {}", e);
+        std::process::exit(EXIT_RUNTIME_ERROR);
+    }
+    if let Err(e) = apply_limits(&config.limits) {
+        error!("{}", e);
+        eprintln!("sshpass: {}", e);
+        std::process::exit(EXIT_RUNTIME_ERROR);
+    }
+    // программа не должна унаследовать канал, по которому приходит пароль
+    for fd in config.password_handshake.iter().flat_map(|(req, resp)| [*req, *resp]) {
+        if let Err(e) = fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC)) {
+            error!("password handshake fd {}: {}", fd, e);
+            eprintln!("sshpass: invalid --password-fd-handshake fd {}: {}", fd, e);
+            std::process::exit(EXIT_RUNTIME_ERROR);
+        }
+    }
+    if let Err(e) = config.control.as_ref().map_or(Ok(()), |control| control.prepare()) {
+        error!("failed to prepare control directory: {}", e);
+        eprintln!("sshpass: failed to prepare control directory: {}", e);
+        std::process::exit(EXIT_RUNTIME_ERROR);
+    }
+
+    // в контейнере или под cron терминала может не быть: тогда stdin читается как есть,
+    // а ответить на запрос пароля вместо sshpass некому
+    let terminal = TerminalInfo::detect();
+    let raw_mode = !config.no_tty && terminal.raw_mode();
+    let verbose = Verbose::new(config.verbose, raw_mode);
+    let mut degraded = Vec::new();
+    if !raw_mode {
+        degraded.push("raw_mode");
+    }
+    if !terminal.interactive() {
+        degraded.push("interactive_prompt");
+    }
+    info!(
+        "startup {}",
+        serde_json::json!({
+            "event": "startup",
+            "terminal": terminal,
+            "raw_mode": raw_mode,
+            "degraded": degraded,
+            "labels": config.labels.iter().cloned().collect::<BTreeMap<_, _>>(),
+        })
+    );
+    if config.password.is_none() && config.password_handshake.is_none() && !terminal.interactive() {
+        warn!("no terminal and no password source, password prompts will not be answered (use -e, -f or -d)");
+    }
+
+    let mut pipeline = output_pipeline(&config, terminal.stdout_tty);
+    trace!("output pipeline: {:?}", pipeline.names());
+    let mut input = input_pipeline(&config, raw_mode);
+    trace!("input pipeline: {:?}", input.names());
+    let mut after_auth = config
+        .after_auth_send
+        .as_deref()
+        .map(|command| AfterAuth::new(command, config.after_auth_prompt.clone()));
+    if config.confirm {
+        let summary = String::from_utf8_lossy(&redact::redact(
+            session_summary(&config, target.as_ref()).as_bytes(),
+        ))
+        .into_owned();
+        match confirm(&summary, config.confirm_timeout) {
+            Ok(Confirmation::Proceed) => trace!("session confirmed"),
+            Ok(Confirmation::Abort) => {
+                info!("session aborted by user");
+                eprintln!("sshpass: aborted");
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            }
+            Err(e) => {
+                error!("confirmation failed: {}", e);
+                eprintln!("sshpass: --confirm needs a terminal: {}", e);
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            }
+        }
+    }
+
+    // до запроса пароля: если хост недоступен, пароль не нужен
+    if let Some(timeout) = config.preflight {
+        let res = target.as_ref().map(|target| {
+            preflight::check(&config.program, &config.program_args, target, timeout)
+        });
+        match res {
+            Some(Ok(Some(dest))) => verbose.print(
+                VERBOSE_EVENTS,
+                format_args!("preflight: {} accepts connections", dest),
+            ),
+            Some(Ok(None)) => verbose.print(
+                VERBOSE_EVENTS,
+                format_args!("preflight: destination is behind a proxy, not checked"),
+            ),
+            Some(Err(e)) => {
+                error!("preflight: {}", e);
+                eprintln!("sshpass: {}", e);
+                std::process::exit(EXIT_UNREACHABLE);
+            }
+            None => {
+                warn!("preflight: no destination in the {} arguments, not checked", config.program);
+                verbose.print(
+                    VERBOSE_EVENTS,
+                    format_args!("preflight: no destination in the arguments, not checked"),
+                );
+            }
+        }
+    }
+
+    let mut password_prompt = match password_prompt_from_config(&config) {
+        Ok(password_prompt) => password_prompt,
+        Err(e) => {
+            error!("failed to get password: {}", e);
+            eprintln!("sshpass: failed to get password: {}", e);
+            std::process::exit(EXIT_RUNTIME_ERROR);
+        }
+    };
+
+    // отделенный stderr дочернего процесса пишется синхронно: его мало и он не должен теряться
+    let mut stderr_sink: Option<Box<dyn Write>> = match config.separate_stderr.as_deref() {
+        None => None,
+        Some("-") => Some(Box::new(std::io::stderr())),
+        Some(path) => match File::create(path) {
+            Ok(file) => Some(Box::new(file)),
+            Err(e) => {
+                error!("failed to open stderr file {}: {}", path, e);
+                eprintln!("sshpass: failed to open stderr file {}: {}", path, e);
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            }
+        },
+    };
+
+    // журнал аудита открывается до pidfile: если открыть не удалось, освобождать еще нечего
+    let mut audit = config.audit_file.as_deref().map(|path| {
+        match AuditLog::open(path, config.audit_fsync) {
+            Ok(audit) => audit,
+            Err(e) => {
+                error!("audit file {}: {}", path.display(), e);
+                eprintln!("sshpass: audit file {}: {}", path.display(), e);
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            }
+        }
+    });
+    if let Some(audit) = audit.as_mut() {
+        audit.record(AuditEvent::session_start(
+            &config.program,
+            target.as_ref().map(|t| t.host.as_str()),
+        ));
+    }
+
+    // как можно позже: после этого места sshpass завершается только через release ниже
+    let pidfile = config.pidfile.as_deref().map(|path| match PidFile::acquire(path) {
+        Ok(pidfile) => pidfile,
+        Err(e) => {
+            error!("pidfile {}: {}", path.display(), e);
+            eprintln!("sshpass: pidfile {}: {}", path.display(), e);
+            std::process::exit(EXIT_RUNTIME_ERROR);
+        }
+    });
+    if let Some(pidfile) = &pidfile {
+        trace!("pidfile {} locked", pidfile.path().display());
+    }
+
+    let started = Instant::now();
+    // сессию прервал --timeout, --prompt-timeout или правило --expect, не дождавшись совпадения
+    let mut timed_out = false;
+    // ресурсы, потраченные программой, известны после того как она забрана wait4
+    let mut child_usage = None;
+
+    if config.askpass {
+        let password = password_prompt.as_ref().map(|p| p.password()).unwrap_or_default();
+        verbose.print(VERBOSE_EVENTS, format_args!("running {} with SSH_ASKPASS", config.program));
+        let status = askpass::run(&config, password);
+        // программа уже дождана askpass::run, других потомков у sshpass в этом режиме нет
+        child_usage = getrusage(UsageWho::RUSAGE_CHILDREN)
+            .ok()
+            .map(|usage| ChildUsage::from(usage.as_ref()));
+        verbose.print(
+            VERBOSE_EVENTS,
+            format_args!(
+                "{} exited with code {}{}",
+                config.program,
+                status,
+                usage_suffix(child_usage.as_ref())
+            ),
+        );
+        let status = program_exit_code(config.mode, status, &verbose);
+        timed_out = status == EXIT_SESSION_TIMEOUT;
+        if let Some(audit) = audit.as_mut() {
+            audit.record(AuditEvent::Shutdown {
+                code: status,
+                reason: None,
+            });
+        }
+        run_exit_hooks(
+            &config,
+            target.as_ref(),
+            log_path.as_ref(),
+            started,
+            status,
+            child_usage,
+            timed_out,
+        );
+        if let Some(pidfile) = pidfile {
+            pidfile.release();
+        }
+        std::process::exit(status);
+    }
 
     #[cfg(target_os = "linux")]
     let status = {
         trace!("app ok, create unix app");
-        let app = UnixApp::new(args).unwrap();
-        let mut stop = UnixAppStop::new();
+        let mut app = UnixApp::new(
+            &config.program,
+            &config.program_args,
+            &config.child,
+            raw_mode,
+        )
+        .unwrap();
+        let mut handshake = config.password_handshake.map(|(request, response)| {
+            // дескриптор передан sshpass во владение, как и -d
+            app.reg_password_response(unsafe { OwnedFd::from_raw_fd(response) });
+            Handshake::new(request)
+        });
+        if let Some(path) = &config.attach_socket {
+            if let Err(e) = app.reg_attach_socket(path, ATTACH_MAX_CLIENTS) {
+                eprintln!("sshpass: attach socket {}: {}", path.display(), e);
+                if let Some(pidfile) = pidfile {
+                    pidfile.release();
+                }
+                std::process::exit(EXIT_RUNTIME_ERROR);
+            }
+            verbose.print(
+                VERBOSE_EVENTS,
+                format_args!("session shared on {}", path.display()),
+            );
+        }
+        if let Some(child) = app.child() {
+            verbose.print(
+                VERBOSE_EVENTS,
+                format_args!("started {} (pid {})", config.program, child),
+            );
+        }
+        let mut stop = UnixAppStop::new(STOPPING_TIMEOUT);
+        // порядок остановки: перестаем читать stdin, дожидаемся завершения дочернего процесса
+        // и записи его вывода в stdout, последним сбрасываем лог
+        stop.participant(StopStage::Input, "stdin");
+        stop.participant(StopStage::Output, "child");
+        stop.participant(StopStage::Output, "pty");
+        stop.participant(StopStage::Output, "stdout");
+        if stderr_sink.is_some() {
+            stop.participant(StopStage::Output, "stderr");
+        }
+        stop.participant(StopStage::Sink, "log");
+        let stop_rx = stop.subscribe();
+        let mut stdin_closed = false;
+        // последний переданный в pty байт stdin был концом строки
+        let mut stdin_line_start = true;
+        let mut stdout_drained = false;
+        // --splice-output включится после входа: до него вывод нужен для поиска запроса пароля
+        let mut splice_pending = config.splice_output
+            && terminal.stdout_pipe
+            && pipeline.is_empty()
+            && config.attach_socket.is_none();
+        if config.splice_output && !splice_pending {
+            verbose.print(
+                VERBOSE_EVENTS,
+                format_args!("--splice-output not used: stdout is not a pipe or the output is transformed"),
+            );
+        }
+        let mut last_reap = Instant::now();
+        let mut last_poll_stats = Instant::now();
         let (tx, rx) = mpsc::channel();
         loop {
+            stop.tick();
+
+            // signalfd склеивает одинаковые сигналы, SIGCHLD может потеряться среди других,
+            // поэтому завершившиеся процессы еще и периодически собираются без сигнала
+            if last_reap.elapsed() >= REAP_SWEEP_INTERVAL {
+                reap_children(&app, &mut stop, config.mode, &mut child_usage, audit.as_mut(), &verbose);
+                last_reap = Instant::now();
+            }
+            if verbose.enabled(VERBOSE_POLL) && last_poll_stats.elapsed() >= POLL_STATS_INTERVAL {
+                print_poll_stats(&app, &verbose);
+                last_poll_stats = Instant::now();
+            }
+            for state in stop_rx.try_iter() {
+                trace!("stop state: {:?}", state);
+                match state {
+                    // во время остановки ввод пользователя больше не передается в pty
+                    StopState::Stopping(StopStage::Input) => {
+                        stdin_closed = true;
+                        stop.drained("stdin");
+                    }
+                    StopState::Stopping(StopStage::Sink) => {
+                        log::logger().flush();
+                        stop.drained("log");
+                    }
+                    _ => {}
+                }
+            }
+
+            // придержанный пейджером хвост и таймауты --expect
+            let (output, events) = pipeline.tick();
+            if !output.is_empty() {
+                tx.send(UnixEventResponse::WriteBytesToStdOut(output)).unwrap();
+            }
+            for event in events {
+                handle_transform_event(event, &tx, &mut stop, &mut timed_out, app.child(), raw_mode, &verbose);
+            }
+            // после неверного пароля или во время остановки команду отправлять уже некуда
+            let after_auth_line = after_auth
+                .as_mut()
+                .filter(|_| !stop.is_stop())
+                .and_then(|after_auth| after_auth.ready());
+            if let Some(line) = after_auth_line {
+                verbose.print(
+                    VERBOSE_EVENTS,
+                    format_args!("login accepted, sending --after-auth-send"),
+                );
+                verbose.sent("after-auth", &line);
+                tx.send(UnixEventResponse::WriteBytesToPtyMaster(line)).unwrap();
+            }
+            if splice_pending
+                && !stop.is_stop()
+                && login_settled(&password_prompt, &after_auth, &handshake)
+            {
+                splice_pending = false;
+                app.enable_splice_output();
+                verbose.print(
+                    VERBOSE_EVENTS,
+                    format_args!("login settled, output goes to stdout with splice"),
+                );
+            }
+
+            let elapsed = started.elapsed();
+            let prompt_timeout = config
+                .prompt_timeout
+                .filter(|t| elapsed >= *t && password_prompt.as_ref().is_some_and(|p| !p.sent()));
+            if stop.is_stop() {
+                // уже останавливаемся, таймауты больше не важны
+            } else if let Some(timeout) = config.timeout.filter(|t| elapsed >= *t) {
+                timed_out = true;
+                // pty закроется только при выходе sshpass, дочерний процесс завершается сразу
+                if let Some(child) = app.child() {
+                    let res = nix::sys::signal::kill(child, Signal::SIGTERM);
+                    trace!("session timeout, kill({}, SIGTERM) = {:?}", child, res);
+                }
+                stop.shutdown_starting(
+                    EXIT_SESSION_TIMEOUT,
+                    Some(format!("session timed out after {}s", timeout.as_secs())),
+                );
+            } else if let Some(timeout) = prompt_timeout {
+                timed_out = true;
+                stop.shutdown_starting(
+                    EXIT_PROMPT_TIMEOUT,
+                    Some(format!("no password prompt within {}s", timeout.as_secs())),
+                );
+            }
+
+            // недописанный вывод задерживает остановку, но не дольше deadline
+            if stop.is_stop() && !stdout_drained && !app.has_pending_writes() {
+                stdout_drained = true;
+                stop.drained("stdout");
+            }
+
+            if stop.is_stoped() {
+                trace!("fd stats: {:#?}", app.snapshot());
+                if verbose.enabled(VERBOSE_POLL) {
+                    print_poll_stats(&app, &verbose);
+                }
+                if let Some(e) = stop.stop_error() {
+                    eprintln!("sshpass: {}", e);
+                }
+                if let Some(audit) = audit.as_mut() {
+                    audit.record(AuditEvent::Shutdown {
+                        code: stop.stop_code(),
+                        reason: stop.stop_error(),
+                    });
+                }
+                break stop.stop_code();
+            }
+
             {
                 let res = app.system_event();
                 match res {
                     Ok(res) => match res {
-                        UnixEvent::PollTimeout => {
-                            // проверяю остановлено ли приложение
-                            if stop.is_stoped() {
-                                break stop.stop_code();
-                            }
-                        }
+                        UnixEvent::PollTimeout => {}
                         // UnixEvent::ChildExited(_pid, status) => {
                         //     trace!("child {} exit: {}", _pid, status);
                         // }
@@ -194,29 +598,271 @@ fn main() {
                         // }
                         UnixEvent::PtyMaster(_index, buf) => {
                             trace!("pty utf8: {}", String::from_utf8_lossy(&buf));
-                            tx.send(UnixEventResponse::WriteToStdOut(buf)).unwrap();
-                            
+                            // одна копия вывода на stdout и всех клиентов --attach-socket; если вывод
+                            // идет через стадии, копия нужна только клиентам.
+                            // Подключенные клиенты видят вывод как есть, до стадий вывода
+                            let chunk = (pipeline.is_empty() || config.attach_socket.is_some())
+                                .then(|| Bytes::copy_from_slice(&buf));
+                            if let Some(chunk) = &chunk {
+                                app.write_to_attached(chunk);
+                            }
+                            // до запроса пароля, что бы само приглашение пароля не сошло за вход
+                            if let Some(after_auth) = after_auth.as_mut() {
+                                after_auth.feed(&buf);
+                            }
+                            if let Some(password_prompt) = password_prompt.as_mut() {
+                                match password_prompt.feed(&buf) {
+                                    Some(PromptEvent::SendPassword) if password_pending(&handshake) => {
+                                        trace!("password prompt detected, request password");
+                                        verbose.print(
+                                            VERBOSE_EVENTS,
+                                            format_args!("password prompt detected, requesting password"),
+                                        );
+                                        let res = handshake.as_mut().map_or(Ok(()), |h| h.request());
+                                        match res {
+                                            Ok(()) => app.listen_password_response(),
+                                            Err(e) => {
+                                                stop.shutdown_starting(EXIT_RUNTIME_ERROR, Some(e.to_string()))
+                                            }
+                                        }
+                                    }
+                                    Some(PromptEvent::SendPassword) => {
+                                        trace!("password prompt detected, send password");
+                                        verbose.print(
+                                            VERBOSE_EVENTS,
+                                            format_args!("password prompt detected, sending password"),
+                                        );
+                                        let line = password_prompt.password_line();
+                                        verbose.sent("password", &line);
+                                        tx.send(UnixEventResponse::WriteBytesToPtyMaster(line)).unwrap();
+                                        if let Some(audit) = audit.as_mut() {
+                                            audit.record(AuditEvent::PasswordSent {
+                                                source: config.password.as_ref().map_or("none", |p| p.kind()),
+                                            });
+                                        }
+                                        if let Some(after_auth) = after_auth.as_mut() {
+                                            after_auth.arm();
+                                        }
+                                    }
+                                    Some(PromptEvent::WrongPassword) => {
+                                        verbose.print(
+                                            VERBOSE_EVENTS,
+                                            format_args!("password prompt repeated, password was rejected"),
+                                        );
+                                        stop.shutdown_starting(
+                                            EXIT_INCORRECT_PASSWORD,
+                                            Some("incorrect password".to_owned()),
+                                        );
+                                    }
+                                    None => {}
+                                }
+                            }
+
+                            if let Some(chunk) = chunk.filter(|_| pipeline.is_empty()) {
+                                tx.send(UnixEventResponse::WriteToStdOut(chunk)).unwrap();
+                            } else {
+                                let (output, events) = pipeline.feed(&buf);
+                                tx.send(UnixEventResponse::WriteBytesToStdOut(output)).unwrap();
+                                for event in events {
+                                    handle_transform_event(event, &tx, &mut stop, &mut timed_out, app.child(), raw_mode, &verbose);
+                                }
+                            }
+
                             // app.send_to(0, buf);
                         }
+                        UnixEvent::PtySpliced(_index, n) => {
+                            // вывод уже в stdout, ни стадий, ни клиентов у него нет
+                            trace!("pty spliced {} bytes to stdout", n);
+                        }
                         UnixEvent::PtySlave(_index, buf) => {
                             trace!("pty utf8: {}", String::from_utf8_lossy(&buf));
                             // app.send_to(0, buf);
                         }
+                        UnixEvent::ChildStderr(_index, buf) => {
+                            if let Some(sink) = stderr_sink.as_mut() {
+                                if let Err(e) = sink.write_all(&buf).and_then(|_| sink.flush()) {
+                                    error!("failed to write child stderr: {}", e);
+                                }
+                            }
+                        }
+                        UnixEvent::PasswordResponse(_index, buf) => {
+                            let res = handshake.as_mut().map_or(Ok(None), |h| h.feed(&buf));
+                            match res {
+                                Ok(Some(password)) => {
+                                    trace!("password received");
+                                    redact::register(&password);
+                                    app.close_password_response();
+                                    if let Some(password_prompt) = password_prompt.as_mut() {
+                                        password_prompt.set_password(password);
+                                        let line = password_prompt.password_line();
+                                        verbose.sent("password", &line);
+                                        tx.send(UnixEventResponse::WriteBytesToPtyMaster(line)).unwrap();
+                                        if let Some(audit) = audit.as_mut() {
+                                            audit.record(AuditEvent::PasswordSent { source: "handshake" });
+                                        }
+                                        if let Some(after_auth) = after_auth.as_mut() {
+                                            after_auth.arm();
+                                        }
+                                    }
+                                }
+                                Ok(None) => {}
+                                Err(e) => {
+                                    app.close_password_response();
+                                    stop.shutdown_starting(EXIT_RUNTIME_ERROR, Some(e.to_string()));
+                                }
+                            }
+                        }
+                        UnixEvent::PasswordResponseEof(_index) => {
+                            trace!("password response closed");
+                            app.close_password_response();
+                            if let Some(Err(e)) = handshake.as_mut().map(|h| h.closed()) {
+                                stop.shutdown_starting(EXIT_RUNTIME_ERROR, Some(e.to_string()));
+                            }
+                        }
+                        UnixEvent::ChildStderrEof(_index) => {
+                            trace!("child stderr closed");
+                            app.close_child_stderr();
+                            stop.drained("stderr");
+                        }
                         UnixEvent::Stdin(_index, buf) => {
                             trace!("stdin utf8: {}", String::from_utf8_lossy(&buf));
                             // let buf_to = Ref::clone(&buf);
-                            tx.send(UnixEventResponse::WriteToPtyMaster(buf)).unwrap();
+                            if config.read_only {
+                                // ввод отбрасывается здесь, до pty он не доходит ни в каком виде,
+                                // но escape-последовательности (--escape-char) по-прежнему работают
+                                if !input.is_empty() {
+                                    let (_, events) = input.feed(&buf);
+                                    for event in events {
+                                        handle_transform_event(event, &tx, &mut stop, &mut timed_out, app.child(), raw_mode, &verbose);
+                                    }
+                                }
+                                // stdin в неканоническом режиме и ^C приходит байтом, а не сигналом
+                                if buf.contains(&CTRL_C) {
+                                    trace!("read-only: ctrl-c, stop session");
+                                    stop.shutdown_starting(0, None);
+                                }
+                            } else if !stdin_closed {
+                                stdin_line_start = buf.last() == Some(&b'\n');
+                                // содержимое ввода не показывается: с клавиатуры может быть набран пароль
+                                verbose.print(
+                                    VERBOSE_TRAFFIC,
+                                    format_args!("sent {} bytes (stdin)", buf.len()),
+                                );
+                                if input.is_empty() {
+                                    tx.send(UnixEventResponse::WriteToPtyMaster(buf)).unwrap();
+                                } else {
+                                    let (output, events) = input.feed(&buf);
+                                    tx.send(UnixEventResponse::WriteBytesToPtyMaster(output)).unwrap();
+                                    for event in events {
+                                        handle_transform_event(event, &tx, &mut stop, &mut timed_out, app.child(), raw_mode, &verbose);
+                                    }
+                                }
+                            }
+                        }
+                        UnixEvent::PtyClosed(_index) => {
+                            trace!("pty closed");
+                            app.close_pty_master();
+                            stop.drained("pty");
+                        }
+                        UnixEvent::WriteReady(_index) => {
+                            trace!("write queue of fd {} flushed", _index);
                         }
-                        UnixEvent::Signal(_index, sig, _sigino) => {
+                        UnixEvent::AttachConnected(index) => {
+                            verbose.print(VERBOSE_EVENTS, format_args!("attach: client {} connected", index));
+                        }
+                        UnixEvent::AttachInput(index, buf) => {
+                            if config.attach_writable && !stop.is_stop() {
+                                verbose.print(
+                                    VERBOSE_TRAFFIC,
+                                    format_args!("sent {} bytes (attach client {})", buf.len(), index),
+                                );
+                                tx.send(UnixEventResponse::WriteToPtyMaster(buf)).unwrap();
+                            } else {
+                                trace!("attach client {}: input ignored", index);
+                            }
+                        }
+                        UnixEvent::AttachClosed(index) => {
+                            verbose.print(VERBOSE_EVENTS, format_args!("attach: client {} disconnected", index));
+                            app.close_attach_client(index);
+                        }
+                        UnixEvent::StdinEof(_index) => {
+                            trace!("stdin eof");
+                            verbose.print(VERBOSE_EVENTS, format_args!("stdin closed"));
+                            app.close_stdin();
+                            if !stdin_closed && !config.read_only {
+                                // VEOF завершает ввод только в начале строки, иначе он лишь отдает строку
+                                let eof = app.pty_eof_char();
+                                let count = if stdin_line_start { 1 } else { 2 };
+                                tx.send(UnixEventResponse::WriteBytesToPtyMaster(vec![eof; count]))
+                                    .unwrap();
+                            }
+                            stdin_closed = true;
+                            stop.drained("stdin");
+                        }
+                        UnixEvent::Signal(_index, sig, siginfo) => {
                             trace!("signal {:#?}", sig);
-                            if matches!(sig, Signal::SIGINT | Signal::SIGTERM) {
-                                stop.shutdown_starting(0, None);
+                            if sig != Signal::SIGCHLD && sig != Signal::SIGWINCH {
+                                verbose.print(VERBOSE_EVENTS, format_args!("received {}", sig));
+                            }
+                            match config.signals.action(sig) {
+                                SignalAction::Forward | SignalAction::ForwardGroup
+                                    if app.in_child_session(Pid::from_raw(siginfo.ssi_pid as i32)) =>
+                                {
+                                    // отправлен из сессии программы (kill -HUP $PPID): вернуть его обратно
+                                    // значило бы зациклить сигнал
+                                    trace!("{} from pid {} not forwarded back", sig, siginfo.ssi_pid);
+                                }
+                                SignalAction::Forward => {
+                                    let res = app.child().map_or(Err(Errno::ESRCH), |child| {
+                                        nix::sys::signal::kill(child, sig).map(|_| child)
+                                    });
+                                    match res {
+                                        Ok(child) => trace!("{} forwarded to child {}", sig, child),
+                                        Err(e) => trace!("{} not forwarded: {}", sig, e),
+                                    }
+                                }
+                                // по умолчанию так обрабатывается SIGINT (kill -INT или ^C, когда stdin
+                                // не в сыром режиме): программа получает его, как ^C в своем терминале.
+                                // Сессию сигнал завершает, только если программы уже нет
+                                SignalAction::ForwardGroup => match app.signal_foreground(sig) {
+                                    Ok(pgrp) => trace!("{} forwarded to process group {}", sig, pgrp),
+                                    Err(e) => {
+                                        trace!("{} not forwarded: {}", sig, e);
+                                        stop.shutdown_starting(0, None);
+                                    }
+                                },
+                                SignalAction::Shutdown => stop.shutdown_starting(0, None),
+                                // не дожидаясь дочернего процесса
+                                SignalAction::Terminate => {
+                                    stop.shutdown_starting(128 + sig as i32, None);
+                                    stop.shutdown_complited();
+                                }
+                                // снимок счетчиков дескрипторов в лог, помогает искать зависшие fd
+                                SignalAction::Status => match serde_json::to_string(&app.snapshot()) {
+                                    Ok(snapshot) => info!("fd stats: {}", snapshot),
+                                    Err(e) => error!("fd stats: {}", e),
+                                },
+                                SignalAction::Ignore => {}
+                            }
+
+                            // остановка задания (kill -TSTP, escape ^Z) и продолжение после fg/bg
+                            if matches!(sig, Signal::SIGTSTP) {
+                                if let Err(e) = app.suspend() {
+                                    error!("suspend: {:?}", e);
+                                }
+                            }
+                            if matches!(sig, Signal::SIGCONT) {
+                                app.resume();
+                            }
+                            if matches!(sig, Signal::SIGWINCH) {
+                                if let Err(e) = app.resize_pty() {
+                                    trace!("resize pty: {}", e);
+                                }
                             }
     
                             if matches!(sig, Signal::SIGCHLD) {
-                                let pid = _sigino.ssi_pid as nix::libc::pid_t;
-                                let res = app.waitpid(pid);
-                                trace!("waitpid({}) = {:#?}", pid, res);
+                                reap_children(&app, &mut stop, config.mode, &mut child_usage, audit.as_mut(), &verbose);
+                                last_reap = Instant::now();
                             }
                         }
                         UnixEvent::ReadZeroBytes => {
@@ -238,26 +884,356 @@ fn main() {
             for res in rx.try_iter() {
                 match res {
                     UnixEventResponse::SendTo(index, buf) => {
-                        app.send_to(index, &buf);
+                        app.send_to(index, Bytes::copy_from_slice(&buf));
                     }
-                    UnixEventResponse::WriteToStdOut(buf) => {
-                        app.write_to_stdout(&buf);
+                    UnixEventResponse::WriteToStdOut(chunk) => {
+                        app.write_to_stdout(chunk);
                     }
                     UnixEventResponse::WriteToStdIn(buf) => {
-                        app.write_to_stdin(&buf);
+                        app.write_to_stdin(Bytes::copy_from_slice(&buf));
                     }
                     UnixEventResponse::WriteToPtyMaster(buf) => {
-                        app.write_to_pty_master(&buf);
+                        app.write_to_pty_master(Bytes::copy_from_slice(&buf));
                         // app.write_to_stdout(&buf);
                     }
+                    // готовый буфер становится куском очереди без копирования
+                    UnixEventResponse::WriteBytesToStdOut(buf) => {
+                        app.write_to_stdout(Bytes::from(buf));
+                    }
+                    UnixEventResponse::WriteBytesToPtyMaster(buf) => {
+                        app.write_to_pty_master(Bytes::from(buf));
+                    }
                 }
             }
         }
     };
 
+    run_exit_hooks(
+        &config,
+        target.as_ref(),
+        log_path.as_ref(),
+        started,
+        status,
+        child_usage,
+        timed_out,
+    );
+    if let Some(pidfile) = pidfile {
+        pidfile.release();
+    }
     std::process::exit(status);
 }
 
+/// Запускает хук --on-success/--on-failure/--on-timeout, терминал к этому моменту уже восстановлен
+fn run_exit_hooks(
+    config: &Config,
+    target: Option<&Target>,
+    log_path: Option<&PathBuf>,
+    started: Instant,
+    status: i32,
+    usage: Option<ChildUsage>,
+    timed_out: bool,
+) {
+    let outcome = match (status, timed_out) {
+        (_, true) => Outcome::Timeout,
+        (0, false) => Outcome::Success,
+        _ => Outcome::Failure,
+    };
+
+    config.hooks.run(&SessionInfo {
+        outcome,
+        exit_code: status,
+        duration: started.elapsed(),
+        program: &config.program,
+        host: target.map(|t| t.host.as_str()),
+        log_file: log_path,
+        usage,
+    });
+    log::logger().flush();
+}
+
+/// Собирает все завершившиеся дочерние процессы
+/// sshpass завершается с кодом дочернего процесса
+#[cfg(target_os = "linux")]
+fn reap_children(
+    app: &UnixApp,
+    stop: &mut UnixAppStop,
+    mode: SessionMode,
+    child_usage: &mut Option<ChildUsage>,
+    mut audit: Option<&mut AuditLog>,
+    verbose: &Verbose,
+) {
+    for (status, usage) in app.reap_children() {
+        let (pid, code) = match status {
+            WaitStatus::Exited(pid, code) => (pid, code),
+            WaitStatus::Signaled(pid, sig, _) => (pid, 128 + sig as i32),
+            _ => continue,
+        };
+
+        if app.child() == Some(pid) {
+            trace!("child {} exit code {}", pid, code);
+            info!("child {} used {}", pid, usage);
+            let suffix = usage_suffix(Some(&usage));
+            match status {
+                WaitStatus::Signaled(_, sig, _) => verbose.print(
+                    VERBOSE_EVENTS,
+                    format_args!("child {} killed by {}, exit code {}{}", pid, sig, code, suffix),
+                ),
+                _ => verbose.print(
+                    VERBOSE_EVENTS,
+                    format_args!("child {} exited with code {}{}", pid, code, suffix),
+                ),
+            }
+            if let Some(audit) = audit.as_deref_mut() {
+                audit.record(AuditEvent::ChildExit {
+                    child_pid: pid.as_raw(),
+                    code,
+                    signal: match status {
+                        WaitStatus::Signaled(_, sig, _) => Some(sig.as_str()),
+                        _ => None,
+                    },
+                });
+            }
+            *child_usage = Some(usage);
+            stop.drained("child");
+            stop.shutdown_starting(program_exit_code(mode, code, verbose), None);
+        }
+    }
+}
+
+/// Код выхода sshpass по коду программы: в --mode scp/sftp неполная передача
+/// отделяется от остальных ошибок
+fn program_exit_code(mode: SessionMode, code: i32, verbose: &Verbose) -> i32 {
+    if !mode.partial_transfer(code) {
+        return code;
+    }
+
+    verbose.print(
+        VERBOSE_EVENTS,
+        format_args!("{} did not transfer all files", mode.as_str()),
+    );
+    EXIT_TRANSFER_INCOMPLETE
+}
+
+/// Ресурсы программы для сообщения о ее завершении
+fn usage_suffix(usage: Option<&ChildUsage>) -> String {
+    usage.map(|usage| format!(" ({})", usage)).unwrap_or_default()
+}
+
+/// Стадии между выводом pty и stdout, в порядке обработки
+/// --expect видит вывод до пейджера, что бы правила могли совпасть и с "--More--",
+/// перевод вывода идет последним, правила и пейджер видят вывод как есть.
+/// Если stdout не терминал и перевод не задан явно, включаются обе стадии:
+/// \r\n и цветные последовательности ломают grep, diff и прочие инструменты в конвейере
+fn output_pipeline(config: &Config, stdout_tty: bool) -> Pipeline {
+    let mut pipeline = Pipeline::default();
+    #[cfg(feature = "rule-engine")]
+    if !config.expect_rules.is_empty() {
+        pipeline.push(Expect::new(config.expect_rules.clone(), config.expect_normalize));
+    }
+    if let Some(prompts) = config.pager_prompts.clone() {
+        pipeline.push(Pager::new(prompts));
+    }
+
+    let auto = !stdout_tty && !config.raw_output && !config.strip_cr && !config.strip_ansi;
+    if config.strip_ansi || auto {
+        pipeline.push(StripAnsi::default());
+    }
+    if config.strip_cr || auto {
+        pipeline.push(StripCr::default());
+    }
+    pipeline
+}
+
+/// Стадии между stdin и pty: escape-последовательности (--escape-char)
+/// Нужны только человеку за терминалом, при вводе из файла или конвейера отключены
+fn input_pipeline(config: &Config, raw_mode: bool) -> Pipeline {
+    let mut pipeline = Pipeline::default();
+    if let Some(escape) = config.escape_char.filter(|_| raw_mode) {
+        pipeline.push(EscapeFilter::new(escape));
+    }
+    pipeline
+}
+
+fn handle_transform_event(
+    event: TransformEvent,
+    tx: &mpsc::Sender<UnixEventResponse<'_>>,
+    stop: &mut UnixAppStop,
+    timed_out: &mut bool,
+    child: Option<Pid>,
+    raw_mode: bool,
+    verbose: &Verbose,
+) {
+    match event {
+        TransformEvent::SendToPty(what, buf) => {
+            trace!("{}: send {} bytes", what, buf.len());
+            verbose.sent(what, &buf);
+            tx.send(UnixEventResponse::WriteBytesToPtyMaster(buf)).unwrap();
+        }
+        TransformEvent::Timeout(reason) => {
+            *timed_out |= !stop.is_stop();
+            stop.shutdown_starting(EXIT_RUNTIME_ERROR, Some(reason));
+        }
+        TransformEvent::Disconnect => {
+            verbose.print(VERBOSE_EVENTS, format_args!("escape: terminating the session"));
+            // как при закрытии терминала: программа получает SIGHUP и не держит остановку до deadline
+            if let Some(child) = child {
+                let res = nix::sys::signal::kill(child, Signal::SIGHUP);
+                trace!("escape disconnect, kill({}, SIGHUP) = {:?}", child, res);
+            }
+            stop.shutdown_starting(0, None);
+        }
+        TransformEvent::Suspend => {
+            // SIGTSTP заблокирован и придет через signalfd, дальше как при kill -TSTP
+            if let Err(e) = nix::sys::signal::raise(Signal::SIGTSTP) {
+                error!("raise(SIGTSTP): {}", e);
+            }
+        }
+        TransformEvent::Notice(text) => {
+            // в сыром режиме терминал не переводит \n в \r\n сам
+            let text = if raw_mode { text.replace('\n', "\r\n") } else { text };
+            eprint!("{}", text);
+        }
+    }
+}
+
+/// Сводка для --confirm: что будет запущено и какие ограничения действуют
+fn session_summary(config: &Config, target: Option<&Target>) -> String {
+    let command = std::iter::once(&config.program)
+        .chain(&config.program_args)
+        .map(|arg| {
+            if arg.is_empty() || arg.contains(char::is_whitespace) {
+                format!("'{}'", arg)
+            } else {
+                arg.clone()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let password = match (&config.password, config.password_handshake) {
+        (Some(source), _) => source.kind(),
+        (None, Some(_)) => "handshake",
+        (None, None) => "none",
+    };
+
+    let log = match &config.log {
+        Some(log) => format!("{} ({:?})", log.level, log.sink).to_lowercase(),
+        None => "off".to_owned(),
+    };
+
+    let mut policies = vec![];
+    if config.mode.is_transfer() {
+        policies.push(format!("{} mode", config.mode.as_str()));
+    }
+    if config.read_only {
+        policies.push("read-only".to_owned());
+    }
+    if config.askpass {
+        policies.push("askpass".to_owned());
+    }
+    if let Some(timeout) = config.timeout {
+        policies.push(format!("timeout {}s", timeout.as_secs()));
+    }
+    if let Some(timeout) = config.prompt_timeout {
+        policies.push(format!("prompt-timeout {}s", timeout.as_secs()));
+    }
+    if let Some(timeout) = config.preflight {
+        policies.push(format!("preflight {}s", timeout.as_secs()));
+    }
+    #[cfg(feature = "rule-engine")]
+    if !config.expect_rules.is_empty() {
+        policies.push(format!("{} expect rules", config.expect_rules.len()));
+    }
+    if config.hooks.on_success.is_some()
+        || config.hooks.on_failure.is_some()
+        || config.hooks.on_timeout.is_some()
+    {
+        policies.push("exit hooks".to_owned());
+    }
+    if config.control.is_some() {
+        policies.push("control-persist".to_owned());
+    }
+    if policies.is_empty() {
+        policies.push("none".to_owned());
+    }
+
+    let mut summary = format!("sshpass session\n  command:  {}\n", command);
+    if let Some(target) = target {
+        summary.push_str(&format!("  target:   {}\n", target));
+    }
+    summary.push_str(&format!("  password: {}\n", password));
+    summary.push_str(&format!("  log:      {}\n", log));
+    summary.push_str(&format!("  policies: {}\n", policies.join(", ")));
+    for (key, value) in &config.labels {
+        summary.push_str(&format!("  label:    {}={}\n", key, value));
+    }
+    summary
+}
+
+/// Пароль еще не запрошен у родительского процесса (--password-fd-handshake)
+fn password_pending(handshake: &Option<Handshake>) -> bool {
+    handshake
+        .as_ref()
+        .is_some_and(|h| h.state() == HandshakeState::Idle)
+}
+
+/// Вход завершен и вывод pty sshpass больше не нужен: пароль принят или не нужен,
+/// команда --after-auth-send отправлена
+fn login_settled(
+    password_prompt: &Option<PasswordPrompt>,
+    after_auth: &Option<AfterAuth>,
+    handshake: &Option<Handshake>,
+) -> bool {
+    // запрос пароля был, но сам пароль от родителя еще не пришел
+    let waiting = handshake
+        .as_ref()
+        .is_some_and(|h| h.state() == HandshakeState::Requested);
+    !waiting
+        && password_prompt.as_ref().is_none_or(|p| p.accepted())
+        && after_auth.as_ref().is_none_or(|a| a.done())
+}
+
+/// Статистика дескрипторов для -vvv, по строке на дескриптор
+#[cfg(target_os = "linux")]
+fn print_poll_stats(app: &UnixApp, verbose: &Verbose) {
+    for fd in app.snapshot() {
+        let idle = fd.idle_ms.map(|ms| format!("{}ms", ms)).unwrap_or_else(|| "-".to_owned());
+        verbose.print(
+            VERBOSE_POLL,
+            format_args!(
+                "poll fd {} {}: events {}, read {}, written {}, errors {}, idle {}",
+                fd.fd, fd.kind, fd.events, fd.bytes_read, fd.bytes_written, fd.errors, idle
+            ),
+        );
+    }
+}
+
+fn password_prompt_from_config(
+    config: &Config,
+) -> Result<Option<PasswordPrompt>, secrets::SecretError> {
+    // с --password-fd-handshake пароль приходит позже, по запросу
+    if config.password_handshake.is_some() {
+        return Ok(Some(PasswordPrompt::new(config.prompt.clone(), vec![])));
+    }
+
+    let Some(source) = &config.password else {
+        return Ok(None);
+    };
+
+    let password = source.read()?;
+    redact::register(&password);
+
+    if let Some(description) = &config.keyring_store {
+        // не удалось сохранить - не повод прерывать сессию
+        if let Err(e) = secrets::keyring::store_key(description, &password) {
+            error!("failed to store password in keyring: {}", e);
+            eprintln!("sshpass: failed to store password in keyring: {}", e);
+        }
+    }
+
+    Ok(Some(PasswordPrompt::new(config.prompt.clone(), password)))
+}
+
 fn _strip_nl(s: &mut String) -> String {
     if s.ends_with('\n') {
         s.pop();
@@ -0,0 +1,52 @@
+use std::str::FromStr;
+
+/// Запрос пароля scp и sftp. С двоеточием, что бы имя файла вроде passwords.txt
+/// в строке прогресса не принималось за повторный запрос пароля
+pub const TRANSFER_PASSWORD_PROMPT: &str = "assword:";
+
+/// Код выхода scp и sftp -b, когда часть файлов не передана
+/// Разрыв соединения ssh сообщает кодом 255, он передается как есть
+const TRANSFER_PARTIAL_EXIT: i32 = 1;
+
+/// Для какой программы настраиваются умолчания (--mode)
+/// scp и sftp передают файлы без участия пользователя: ввод с клавиатуры им не нужен,
+/// а строка прогресса держится на \r и должна дойти до терминала без изменений
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SessionMode {
+    #[default]
+    Ssh,
+    Scp,
+    Sftp,
+}
+
+impl FromStr for SessionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssh" => Ok(SessionMode::Ssh),
+            "scp" => Ok(SessionMode::Scp),
+            "sftp" => Ok(SessionMode::Sftp),
+            _ => Err(format!("unknown mode '{}', expected ssh, scp or sftp", s)),
+        }
+    }
+}
+
+impl SessionMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionMode::Ssh => "ssh",
+            SessionMode::Scp => "scp",
+            SessionMode::Sftp => "sftp",
+        }
+    }
+
+    pub fn is_transfer(&self) -> bool {
+        matches!(self, SessionMode::Scp | SessionMode::Sftp)
+    }
+
+    /// Программа завершилась, передав не все файлы
+    pub fn partial_transfer(&self, code: i32) -> bool {
+        self.is_transfer() && code == TRANSFER_PARTIAL_EXIT
+    }
+}
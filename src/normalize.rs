@@ -0,0 +1,98 @@
+use unicode_normalization::UnicodeNormalization;
+
+/// Нормализация вывода перед поиском приглашений
+/// Прошивки разных устройств выводят одно и то же приглашение по разному:
+/// с NBSP вместо пробела, с разложенными диакритиками, с ANSI раскраской.
+/// Нормализатор убирает ANSI последовательности, сворачивает любые пробельные символы
+/// в один пробел и приводит текст к NFC. Работает потоком: escape последовательность
+/// и многобайтный UTF-8 символ могут быть разорваны между чтениями
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    escape: AnsiFilter,
+    /// начало UTF-8 символа, продолжение которого еще не пришло
+    partial: Vec<u8>,
+    last_space: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Escape {
+    #[default]
+    None,
+    /// был ESC
+    Start,
+    /// ESC [ ... до финального байта
+    Csi,
+    /// ESC ] ... до BEL или ESC \
+    Osc,
+    /// ESC внутри OSC, возможно начало завершающего ESC \
+    OscEnd,
+}
+
+impl Normalizer {
+    /// Нормализует строку целиком, для образцов
+    pub fn normalize(text: &str) -> String {
+        let mut normalizer = Self::default();
+        String::from_utf8_lossy(&normalizer.feed(text.as_bytes())).into_owned()
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut text = std::mem::take(&mut self.partial);
+        for &b in chunk {
+            if self.escape.skip(b) {
+                continue;
+            }
+            text.push(b);
+        }
+
+        // неполный символ в конце дождется следующего чтения
+        let valid = match std::str::from_utf8(&text) {
+            Ok(_) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => text.len(),
+        };
+        self.partial = text.split_off(valid);
+
+        let mut out = String::with_capacity(text.len());
+        for c in String::from_utf8_lossy(&text).nfc() {
+            if c.is_whitespace() {
+                if !self.last_space {
+                    out.push(' ');
+                }
+                self.last_space = true;
+            } else {
+                out.push(c);
+                self.last_space = false;
+            }
+        }
+
+        out.into_bytes()
+    }
+}
+
+/// Автомат ANSI последовательностей, работает побайтно и переживает разрыв между чтениями
+#[derive(Debug, Clone, Default)]
+pub struct AnsiFilter {
+    state: Escape,
+}
+
+impl AnsiFilter {
+    /// true если байт относится к escape последовательности, а не к тексту
+    pub fn skip(&mut self, b: u8) -> bool {
+        self.state = match (self.state, b) {
+            (Escape::None, 0x1b) => Escape::Start,
+            (Escape::None, _) => return false,
+            (Escape::Start, b'[') => Escape::Csi,
+            (Escape::Start, b']') => Escape::Osc,
+            // двухбайтная последовательность вроде ESC =
+            (Escape::Start, _) => Escape::None,
+            (Escape::Csi, 0x40..=0x7e) => Escape::None,
+            (Escape::Csi, _) => Escape::Csi,
+            (Escape::Osc, 0x07) => Escape::None,
+            (Escape::Osc, 0x1b) => Escape::OscEnd,
+            (Escape::Osc, _) => Escape::Osc,
+            (Escape::OscEnd, b'\\') => Escape::None,
+            (Escape::OscEnd, _) => Escape::Osc,
+        };
+        true
+    }
+}
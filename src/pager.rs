@@ -0,0 +1,117 @@
+use std::time::{Duration, Instant};
+
+use crate::transform::{Transform, TransformEvent};
+
+/// Стандартные приглашения пейджера сетевых устройств
+pub const DEFAULT_PAGER_PROMPTS: [&str; 2] = ["--More--", "---(more)---"];
+
+/// Ответ, который отправляется в pty для получения следующей страницы
+pub const PAGER_ANSWER: &[u8] = b" ";
+
+/// Сколько придерживать хвост, похожий на начало приглашения, в ожидании продолжения
+const TAIL_HOLD: Duration = Duration::from_millis(1000);
+
+/// Обработчик постраничного вывода ("--More--")
+/// Ищет приглашения пейджера в выводе pty, вырезает их из потока
+/// и сообщает сколько раз нужно отправить продолжение
+/// Приглашение может прийти разорванным между двумя чтениями,
+/// поэтому хвост, похожий на начало приглашения, придерживается до следующего чтения
+#[derive(Debug)]
+pub struct Pager {
+    prompts: Vec<Vec<u8>>,
+    tail: Vec<u8>,
+    tail_since: Option<Instant>,
+}
+
+impl Pager {
+    pub fn new<I, S>(prompts: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut prompts: Vec<Vec<u8>> = prompts
+            .into_iter()
+            .map(|p| p.as_ref().as_bytes().to_vec())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        // более длинные приглашения проверяются первыми,
+        // что бы вырезалось совпадение максимальной длины
+        prompts.sort_by_key(|p| std::cmp::Reverse(p.len()));
+
+        Self {
+            prompts,
+            tail: vec![],
+            tail_since: None,
+        }
+    }
+
+    /// Обрабатывает очередной фрагмент вывода
+    /// Возвращает очищенный вывод и количество найденных приглашений
+    pub fn feed(&mut self, chunk: &[u8]) -> (Vec<u8>, usize) {
+        let mut data = std::mem::take(&mut self.tail);
+        data.extend_from_slice(chunk);
+        self.tail_since = None;
+
+        let mut output = Vec::with_capacity(data.len());
+        let mut found = 0;
+        let mut i = 0;
+
+        'scan: while i < data.len() {
+            let rest = &data[i..];
+            for prompt in &self.prompts {
+                if rest.starts_with(prompt) {
+                    found += 1;
+                    i += prompt.len();
+                    continue 'scan;
+                }
+            }
+
+            // конец данных совпадает с началом приглашения, ждем продолжения
+            let partial = self
+                .prompts
+                .iter()
+                .any(|prompt| rest.len() < prompt.len() && prompt.starts_with(rest));
+            if partial {
+                self.tail = rest.to_vec();
+                self.tail_since = Some(Instant::now());
+                break;
+            }
+
+            output.push(data[i]);
+            i += 1;
+        }
+
+        (output, found)
+    }
+
+    /// Возвращает придержанный хвост, если продолжения так и не пришло за TAIL_HOLD
+    pub fn flush_expired(&mut self) -> Vec<u8> {
+        match self.tail_since {
+            Some(since) if since.elapsed() >= TAIL_HOLD => {
+                self.tail_since = None;
+                std::mem::take(&mut self.tail)
+            }
+            _ => vec![],
+        }
+    }
+}
+
+impl Transform for Pager {
+    fn name(&self) -> &'static str {
+        "pager"
+    }
+
+    /// Приглашения вырезаются, на каждое в pty уходит PAGER_ANSWER
+    fn feed(&mut self, chunk: Vec<u8>, events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        let (output, pages) = Pager::feed(self, &chunk);
+        for _ in 0..pages {
+            events.push(TransformEvent::SendToPty("pager", PAGER_ANSWER.to_vec()));
+        }
+        output
+    }
+
+    fn tick(&mut self, _events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        self.flush_expired()
+    }
+}
@@ -0,0 +1,101 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+use nix::errno::Errno;
+use nix::fcntl::{Flock, FlockArg};
+
+/// Файл с pid и эксклюзивной блокировкой (--pidfile)
+/// Блокировка держится, пока открыт файл, и снимается ядром при любом завершении процесса,
+/// поэтому файл, оставшийся после kill -9, не мешает следующему запуску
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    file: Flock<File>,
+}
+
+#[derive(Debug)]
+pub enum PidFileError {
+    /// файл заблокирован другим sshpass, pid из файла, если его удалось прочитать
+    Locked(Option<u32>),
+    Io(io::Error),
+}
+
+impl std::fmt::Display for PidFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PidFileError::Locked(Some(pid)) => write!(f, "another instance is running (pid {})", pid),
+            PidFileError::Locked(None) => write!(f, "another instance is running"),
+            PidFileError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for PidFileError {}
+
+impl From<io::Error> for PidFileError {
+    fn from(e: io::Error) -> Self {
+        PidFileError::Io(e)
+    }
+}
+
+impl PidFile {
+    /// Блокирует файл и записывает в него pid текущего процесса
+    pub fn acquire(path: &Path) -> Result<Self, PidFileError> {
+        loop {
+            // без truncate: пока блокировка не взята, в файле pid работающего экземпляра
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .mode(0o644)
+                .open(path)?;
+
+            let mut file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+                Ok(file) => file,
+                Err((mut file, Errno::EWOULDBLOCK)) => {
+                    let mut pid = String::new();
+                    let pid = file.read_to_string(&mut pid).ok().and_then(|_| pid.trim().parse().ok());
+                    return Err(PidFileError::Locked(pid));
+                }
+                Err((_, e)) => return Err(PidFileError::Io(e.into())),
+            };
+
+            // предыдущий экземпляр мог удалить файл между open и flock,
+            // тогда заблокирован уже никому не видимый файл и нужно открыть заново
+            let locked = file.metadata()?;
+            match std::fs::metadata(path) {
+                Ok(current) if current.dev() == locked.dev() && current.ino() == locked.ino() => {}
+                Ok(_) => continue,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+
+            file.set_len(0)?;
+            file.rewind()?;
+            writeln!(file, "{}", std::process::id())?;
+            file.flush()?;
+
+            return Ok(Self {
+                path: path.to_owned(),
+                file,
+            });
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Удаляет файл, пока блокировка еще у нас. Экземпляр, который успел открыть
+    /// удаляемый файл, заметит это после flock и откроет файл заново
+    pub fn release(self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("failed to remove pidfile {}: {}", self.path.display(), e);
+        }
+        drop(self.file);
+    }
+}
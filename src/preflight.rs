@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{trace, warn};
+use nix::unistd::getuid;
+
+use crate::control::control_dir;
+use crate::target::Target;
+
+/// Сколько действует результат проверки: пакетный запуск по списку хостов
+/// не проверяет один и тот же адрес на каждом шаге
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+const SSH_PORT: u16 = 22;
+
+/// Сколько ждать соединения, если --preflight задан без значения, в секундах
+pub const DEFAULT_PREFLIGHT_TIMEOUT: u64 = 5;
+
+/// Куда на самом деле подключится ssh, с учетом ~/.ssh/config
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Destination {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PreflightError {
+    Resolve(Destination, io::Error),
+    Unreachable(Destination, io::Error),
+    /// неудача из кеша, сообщение сохранено при проверке
+    Cached(String),
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreflightError::Resolve(dest, e) => write!(f, "cannot resolve {}: {}", dest.host, e),
+            PreflightError::Unreachable(dest, e) => write!(f, "{} unreachable: {}", dest, e),
+            PreflightError::Cached(message) => write!(f, "{} (cached)", message),
+        }
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+/// Проверяет до запуска программы, что адрес назначения разрешается и принимает TCP
+/// соединение, не дольше timeout. Ok(None) - проверка не имеет смысла: ssh идет
+/// через ProxyJump/ProxyCommand, и напрямую к хосту не подключается
+pub fn check(
+    program: &str,
+    args: &[String],
+    target: &Target,
+    timeout: Duration,
+) -> Result<Option<Destination>, PreflightError> {
+    let Some(dest) = destination(program, args, target) else {
+        return Ok(None);
+    };
+
+    let cache = cache_path(&dest);
+    if let Some(cached) = cache.as_deref().and_then(read_cache) {
+        trace!("preflight {}: cached {:?}", dest, cached);
+        return match cached {
+            None => Ok(Some(dest)),
+            Some(message) => Err(PreflightError::Cached(message)),
+        };
+    }
+
+    let res = connect(&dest, timeout);
+    if let Some(cache) = &cache {
+        let entry = match &res {
+            Ok(()) => "ok".to_owned(),
+            Err(e) => format!("error {}", e),
+        };
+        if let Err(e) = fs::write(cache, entry) {
+            warn!("preflight cache {}: {}", cache.display(), e);
+        }
+    }
+
+    res.map(|_| Some(dest))
+}
+
+fn connect(dest: &Destination, timeout: Duration) -> Result<(), PreflightError> {
+    let started = Instant::now();
+    // getaddrinfo не прерывается, поэтому timeout считается только на подключение
+    let addrs = (dest.host.as_str(), dest.port)
+        .to_socket_addrs()
+        .map_err(|e| PreflightError::Resolve(dest.clone(), e))?;
+
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses");
+    for addr in addrs {
+        let left = timeout.saturating_sub(started.elapsed());
+        if left.is_zero() {
+            last_error = io::Error::new(io::ErrorKind::TimedOut, "connection timed out");
+            break;
+        }
+
+        match TcpStream::connect_timeout(&addr, left) {
+            Ok(_) => {
+                trace!("preflight {}: {} connected in {:?}", dest, addr, started.elapsed());
+                return Ok(());
+            }
+            Err(e) => {
+                trace!("preflight {}: {}: {}", dest, addr, e);
+                last_error = e;
+            }
+        }
+    }
+
+    Err(PreflightError::Unreachable(dest.clone(), last_error))
+}
+
+/// Адрес из `ssh -G`: ssh сам применит Host, HostName и Port из конфигурации.
+/// Для ssh передаются исходные аргументы, остальным программам - только адрес из них.
+/// Если ssh -G не сработал, берется адрес из аргументов как есть
+fn destination(program: &str, args: &[String], target: &Target) -> Option<Destination> {
+    let mut command = Command::new("ssh");
+    command.arg("-G");
+    if Path::new(program).file_name().is_some_and(|name| name == "ssh") {
+        command.args(args);
+    } else {
+        if let Some(port) = target.port {
+            command.arg("-p").arg(port.to_string());
+        }
+        command.arg(&target.host);
+    }
+
+    let mut dest = Destination {
+        host: target.host.clone(),
+        port: target.port.unwrap_or(SSH_PORT),
+    };
+    let output = command
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|output| output.status.success());
+    let Some(output) = output else {
+        trace!("preflight: ssh -G failed, using {} from the arguments", dest);
+        return Some(dest);
+    };
+
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        match line.split_once(' ') {
+            Some(("hostname", host)) => dest.host = host.to_owned(),
+            Some(("port", port)) => dest.port = port.parse().unwrap_or(dest.port),
+            Some(("proxyjump" | "proxycommand", value)) if value != "none" => {
+                trace!("preflight: {} goes through a proxy, skipped", target.host);
+                return None;
+            }
+            _ => {}
+        }
+    }
+
+    Some(dest)
+}
+
+/// Файл кеша, если каталог принадлежит нам и закрыт для остальных: иначе кто-то другой
+/// мог бы подложить результат проверки
+fn cache_path(dest: &Destination) -> Option<PathBuf> {
+    let dir = control_dir().join("preflight");
+    if let Err(e) = fs::DirBuilder::new().recursive(true).mode(0o700).create(&dir) {
+        warn!("preflight cache {}: {}", dir.display(), e);
+        return None;
+    }
+
+    let meta = fs::metadata(&dir).ok()?;
+    if meta.uid() != getuid().as_raw() || meta.mode() & 0o077 != 0 {
+        warn!("preflight cache {} is not private, not used", dir.display());
+        return None;
+    }
+
+    Some(dir.join(dest.to_string().replace('/', "_")))
+}
+
+/// None - записи нет или она устарела, Some(None) - адрес был доступен,
+/// Some(Some(message)) - проверка не прошла
+fn read_cache(path: &Path) -> Option<Option<String>> {
+    let age = fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())?;
+    if age > CACHE_TTL {
+        return None;
+    }
+
+    let entry = fs::read_to_string(path).ok()?;
+    match entry.split_once(' ') {
+        Some(("error", message)) => Some(Some(message.to_owned())),
+        _ if entry == "ok" => Some(None),
+        _ => None,
+    }
+}
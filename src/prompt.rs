@@ -0,0 +1,271 @@
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use aho_corasick::AhoCorasick;
+use regex::bytes::Regex;
+
+use crate::normalize::Normalizer;
+
+/// Строка, по которой определяется запрос пароля, если не задан --prompt
+/// Без первой буквы, что бы совпадали и "Password:", и "password:"
+pub const DEFAULT_PASSWORD_PROMPT: &str = "assword";
+
+/// Сколько байт предыдущих чтений помнит регулярное выражение
+/// Совпадение длиннее этого окна, разорванное между чтениями, не найдется
+const REGEX_LOOKBEHIND: usize = 256;
+
+/// Сколько ждать повторного запроса пароля, прежде чем считать пароль принятым
+pub const AUTH_SETTLE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+enum PromptPattern {
+    Literal(AhoCorasick),
+    Regex(Regex),
+    /// пустой --prompt, запрос никогда не находится
+    Never,
+}
+
+/// Потоковый поиск приглашения в выводе pty
+/// Приглашение может прийти разорванным между двумя чтениями,
+/// поэтому хвост предыдущих чтений (не длиннее lookbehind) проверяется вместе с новым фрагментом
+#[derive(Debug, Clone)]
+pub struct PromptMatcher {
+    pattern: PromptPattern,
+    lookbehind: usize,
+    window: Vec<u8>,
+    /// поиск идет по нормализованному выводу (--prompt-normalize)
+    normalizer: Option<Normalizer>,
+}
+
+impl PromptMatcher {
+    /// Строка --prompt: с префиксом 're:' это регулярное выражение, иначе строка как есть
+    /// normalize - искать в нормализованном выводе, строка при этом нормализуется так же
+    pub fn parse(prompt: &str, normalize: bool) -> Result<Self, regex::Error> {
+        let mut matcher = match prompt.strip_prefix("re:") {
+            Some(re) => Self {
+                pattern: PromptPattern::Regex(Regex::new(re)?),
+                lookbehind: REGEX_LOOKBEHIND,
+                window: vec![],
+                normalizer: None,
+            },
+            None if normalize => Self::literal(&[&Normalizer::normalize(prompt)]),
+            None => Self::literal(&[prompt]),
+        };
+
+        if normalize {
+            matcher.normalizer = Some(Normalizer::default());
+        }
+
+        Ok(matcher)
+    }
+
+    /// Любая из строк, регистр учитывается
+    pub fn literal(prompts: &[&str]) -> Self {
+        let prompts: Vec<&str> = prompts.iter().copied().filter(|p| !p.is_empty()).collect();
+        let Some(longest) = prompts.iter().map(|p| p.len()).max() else {
+            return Self {
+                pattern: PromptPattern::Never,
+                lookbehind: 0,
+                window: vec![],
+                normalizer: None,
+            };
+        };
+
+        Self {
+            pattern: PromptPattern::Literal(
+                AhoCorasick::new(&prompts).expect("prompt literals are always valid"),
+            ),
+            // начало строки, которой не хватает одного байта, должно дождаться следующего чтения
+            lookbehind: longest - 1,
+            window: vec![],
+            normalizer: None,
+        }
+    }
+
+    /// Обрабатывает очередной фрагмент, true если в нем закончилось приглашение
+    pub fn feed(&mut self, chunk: &[u8]) -> bool {
+        let chunk = match self.normalizer.as_mut() {
+            Some(normalizer) => Cow::Owned(normalizer.feed(chunk)),
+            None => Cow::Borrowed(chunk),
+        };
+
+        let seen = self.window.len();
+        let mut data = std::mem::take(&mut self.window);
+        data.extend_from_slice(&chunk);
+
+        // совпадения целиком в старых данных уже были бы найдены раньше
+        let end = match &self.pattern {
+            PromptPattern::Literal(ac) => ac.find_iter(&data).map(|m| m.end()).find(|&e| e > seen),
+            PromptPattern::Regex(re) => re.find_iter(&data).map(|m| m.end()).find(|&e| e > seen),
+            PromptPattern::Never => None,
+        };
+
+        // после совпадения окно начинается с его конца, что бы одно приглашение не нашлось дважды
+        let from = end.unwrap_or(0).max(data.len().saturating_sub(self.lookbehind));
+        self.window = data[from..].to_vec();
+
+        end.is_some()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PromptEvent {
+    /// Найден запрос пароля, нужно отправить пароль в pty
+    SendPassword,
+    /// Запрос пароля повторился после отправки, значит пароль не подошел
+    WrongPassword,
+}
+
+/// Отслеживает запрос пароля в выводе pty
+#[derive(Debug)]
+pub struct PasswordPrompt {
+    prompt: PromptMatcher,
+    password: Vec<u8>,
+    /// когда был найден запрос пароля и отправлен пароль
+    sent: Option<Instant>,
+}
+
+impl PasswordPrompt {
+    pub fn new(prompt: PromptMatcher, password: Vec<u8>) -> Self {
+        Self {
+            prompt,
+            password,
+            sent: None,
+        }
+    }
+
+    pub fn feed(&mut self, chunk: &[u8]) -> Option<PromptEvent> {
+        if !self.prompt.feed(chunk) {
+            return None;
+        }
+
+        if self.sent.is_some() {
+            return Some(PromptEvent::WrongPassword);
+        }
+
+        self.sent = Some(Instant::now());
+        Some(PromptEvent::SendPassword)
+    }
+
+    pub fn password(&self) -> &[u8] {
+        &self.password
+    }
+
+    /// Пароль, полученный уже после запроса (--password-fd-handshake)
+    pub fn set_password(&mut self, password: Vec<u8>) {
+        self.password = password;
+        // пароль уходит только сейчас, ожидание ответа не считается
+        if self.sent.is_some() {
+            self.sent = Some(Instant::now());
+        }
+    }
+
+    /// Запрос пароля уже встречался и пароль отправлен
+    pub fn sent(&self) -> bool {
+        self.sent.is_some()
+    }
+
+    /// Пароль отправлен и за AUTH_SETTLE запрос не повторился, значит он принят
+    pub fn accepted(&self) -> bool {
+        self.sent.is_some_and(|sent| sent.elapsed() >= AUTH_SETTLE)
+    }
+
+    /// Пароль вместе с переводом строки, в том виде как он отправляется в pty
+    pub fn password_line(&self) -> Vec<u8> {
+        let mut line = self.password.clone();
+        line.push(b'\n');
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OUTPUT: &[u8] = b"Warning: added to known hosts\r\nalice@host's password: ";
+
+    /// Сколько раз matcher сработал, если вывод пришел фрагментами по границам splits
+    fn matches(matcher: &PromptMatcher, output: &[u8], splits: &[usize]) -> usize {
+        let mut matcher = matcher.clone();
+        let mut from = 0;
+        let mut count = 0;
+        for &to in splits.iter().chain([&output.len()]) {
+            count += usize::from(matcher.feed(&output[from..to]));
+            from = to;
+        }
+        count
+    }
+
+    fn assert_split_anywhere(prompt: &str, output: &[u8]) {
+        let matcher = PromptMatcher::parse(prompt, false).unwrap();
+        for i in 0..=output.len() {
+            assert_eq!(matches(&matcher, output, &[i]), 1, "{} split at {}", prompt, i);
+        }
+        let bytes: Vec<usize> = (1..output.len()).collect();
+        assert_eq!(matches(&matcher, output, &bytes), 1, "{} byte by byte", prompt);
+    }
+
+    #[test]
+    fn literal_prompt_split_at_every_byte() {
+        assert_split_anywhere(DEFAULT_PASSWORD_PROMPT, OUTPUT);
+        assert_split_anywhere("password: ", OUTPUT);
+    }
+
+    #[test]
+    fn regex_prompt_split_at_every_byte() {
+        assert_split_anywhere(r"re:\w+@\w+'s password: ", OUTPUT);
+        assert_split_anywhere(r"re:[Pp]assword: $", OUTPUT);
+    }
+
+    #[test]
+    fn prompt_matches_once() {
+        for prompt in ["password: ", r"re:pass\w*: ?"] {
+            let mut matcher = PromptMatcher::parse(prompt, false).unwrap();
+            assert!(matcher.feed(OUTPUT), "{}", prompt);
+            assert!(!matcher.feed(b""), "{}", prompt);
+            assert!(!matcher.feed(b"\r\n"), "{}", prompt);
+            // повторный запрос - это новое совпадение
+            assert!(matcher.feed(b"password: "), "{}", prompt);
+        }
+    }
+
+    #[test]
+    fn prompt_twice_in_one_chunk_matches_once() {
+        let mut matcher = PromptMatcher::parse("password: ", false).unwrap();
+        assert!(matcher.feed(b"password: \r\npassword: "));
+        assert!(!matcher.feed(b" "));
+    }
+
+    #[test]
+    fn any_of_literal_prompts() {
+        let mut matcher = PromptMatcher::literal(&["assword", "passphrase"]);
+        assert!(!matcher.feed(b"Enter pass"));
+        assert!(!matcher.feed(b"phras"));
+        assert!(matcher.feed(b"e for key: "));
+    }
+
+    #[test]
+    fn empty_prompt_never_matches() {
+        let mut matcher = PromptMatcher::parse("", false).unwrap();
+        assert!(!matcher.feed(OUTPUT));
+    }
+
+    #[test]
+    fn normalized_prompt_ignores_ansi_and_spaces() {
+        let matcher = PromptMatcher::parse("Password:  ", true).unwrap();
+        let output = b"\x1b[1mPass\x1b[0mword:\xc2\xa0";
+        for i in 0..=output.len() {
+            assert_eq!(matches(&matcher, output, &[i]), 1, "split at {}", i);
+        }
+    }
+
+    #[test]
+    fn repeated_prompt_is_wrong_password() {
+        let mut prompt = PasswordPrompt::new(PromptMatcher::literal(&["assword"]), b"pw".to_vec());
+        assert_eq!(prompt.feed(b"Pass"), None);
+        assert_eq!(prompt.feed(b"word: "), Some(PromptEvent::SendPassword));
+        assert!(prompt.sent());
+        assert_eq!(prompt.feed(b"\r\nPermission denied\r\nPassword: "), Some(PromptEvent::WrongPassword));
+        assert_eq!(prompt.password_line(), b"pw\n");
+    }
+}
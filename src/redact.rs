@@ -0,0 +1,88 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+/// Чем заменяется секрет в логе
+const MASK: &[u8] = b"***";
+
+/// Секреты, которые не должны попасть в лог
+/// Список глобальный: пароль становится известен уже после того, как лог открыт
+static SECRETS: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+/// Запоминает секрет, после этого он маскируется во всем, что пишется в лог
+pub fn register(secret: &[u8]) {
+    if secret.is_empty() {
+        return;
+    }
+
+    let mut secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut add = |secret: Vec<u8>| {
+        if !secrets.contains(&secret) {
+            secrets.push(secret);
+        }
+    };
+
+    // в JSON логе секрет может оказаться в экранированном виде
+    if let Ok(escaped) = serde_json::to_string(&String::from_utf8_lossy(secret)) {
+        add(escaped.as_bytes()[1..escaped.len() - 1].to_vec());
+    }
+    add(secret.to_vec());
+
+    // более длинные секреты маскируются первыми, что бы не осталось их хвостов
+    secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+}
+
+/// Заменяет все вхождения секретов в buf
+pub fn redact(buf: &[u8]) -> Vec<u8> {
+    let secrets = SECRETS.lock().unwrap_or_else(|e| e.into_inner());
+    let mut out = buf.to_vec();
+
+    for secret in secrets.iter() {
+        let mut i = 0;
+        while let Some(pos) = out[i..].windows(secret.len()).position(|w| w == secret.as_slice()) {
+            let start = i + pos;
+            out.splice(start..start + secret.len(), MASK.iter().copied());
+            i = start + MASK.len();
+        }
+    }
+
+    out
+}
+
+/// Обертка над файлом лога, маскирует секреты перед записью
+/// Логгер пишет запись несколькими вызовами write, поэтому данные копятся
+/// до конца строки, чтобы секрет не оказался разорван между двумя вызовами
+pub struct RedactWriter<W: Write> {
+    inner: W,
+    line: Vec<u8>,
+}
+
+impl<W: Write> RedactWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            line: vec![],
+        }
+    }
+}
+
+impl<W: Write> Write for RedactWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+
+        if let Some(end) = self.line.iter().rposition(|&b| b == b'\n') {
+            let lines: Vec<u8> = self.line.drain(..=end).collect();
+            self.inner.write_all(&redact(&lines))?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            self.inner.write_all(&redact(&line))?;
+        }
+
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,94 @@
+use std::io::Read;
+use std::os::fd::AsFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::Pid;
+
+use log::{error, trace};
+
+use crate::secrets::SecretError;
+
+/// Запускает вспомогательную программу через sh -c и возвращает ее stdout
+/// Чтение идет через poll, поэтому зависшая программа не блокирует запуск дольше timeout:
+/// по истечении времени она убивается и возвращается ошибка
+/// Ненулевой код завершения тоже считается ошибкой
+/// Программа запускается в своей группе процессов, при ошибке убивается вся группа:
+/// потомки sh -c (pass, vault) не остаются сиротами
+pub fn read_command(command: &str, timeout: Duration) -> Result<Vec<u8>, SecretError> {
+    trace!("spawn password command");
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .process_group(0)
+        .spawn()?;
+
+    let mut stdout = child.stdout.take().unwrap();
+    let deadline = Instant::now() + timeout;
+    let mut output = vec![];
+    let mut buf = [0u8; 1024];
+
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            error!("password command timeout, kill pid {}", child.id());
+            kill_group(&mut child);
+            return Err(SecretError::CommandTimeout(timeout));
+        }
+
+        let mut fds = [PollFd::new(stdout.as_fd(), PollFlags::POLLIN)];
+        let left = PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX);
+        match poll(&mut fds, left) {
+            Err(Errno::EINTR) | Ok(0) => continue,
+            Err(e) => {
+                kill_group(&mut child);
+                return Err(e.into());
+            }
+            Ok(_) => {}
+        }
+
+        match stdout.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                kill_group(&mut child);
+                return Err(e.into());
+            }
+        }
+    }
+
+    // stdout закрыт, но процесс еще может работать
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            error!("password command timeout, kill pid {}", child.id());
+            kill_group(&mut child);
+            return Err(SecretError::CommandTimeout(timeout));
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    trace!("password command exit: {}", status);
+    if !status.success() {
+        return Err(SecretError::CommandFailed(status));
+    }
+
+    Ok(output)
+}
+
+/// SIGKILL группе процессов программы и ожидание самой sh
+fn kill_group(child: &mut Child) {
+    let _ = killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL);
+    let _ = child.wait();
+}
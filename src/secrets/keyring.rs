@@ -0,0 +1,82 @@
+use std::ffi::CString;
+
+use nix::errno::Errno;
+use nix::libc;
+
+use log::trace;
+
+use crate::secrets::SecretError;
+
+// include/uapi/linux/keyctl.h
+const KEY_SPEC_SESSION_KEYRING: libc::c_long = -3;
+const KEYCTL_READ: libc::c_long = 11;
+
+/// Тип ключа, в котором хранится произвольная строка
+const KEY_TYPE_USER: &str = "user";
+
+/// Ищет ключ типа "user" с описанием description в связке ключей процесса
+/// (thread, process, session keyring) через request_key и читает его содержимое
+pub fn read_key(description: &str) -> Result<Vec<u8>, SecretError> {
+    let key_type = CString::new(KEY_TYPE_USER).unwrap();
+    let description = to_cstring(description)?;
+
+    let id = unsafe {
+        libc::syscall(
+            libc::SYS_request_key,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            std::ptr::null::<libc::c_char>(),
+            0 as libc::c_long,
+        )
+    };
+    let id = Errno::result(id)?;
+    trace!("keyring key id: {}", id);
+
+    // размер ключа заранее не известен, первый вызов возвращает длину
+    let mut buf: Vec<u8> = vec![];
+    loop {
+        let len = unsafe {
+            libc::syscall(
+                libc::SYS_keyctl,
+                KEYCTL_READ,
+                id,
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        };
+        let len = Errno::result(len)? as usize;
+
+        if len <= buf.len() {
+            buf.truncate(len);
+            return Ok(buf);
+        }
+
+        buf.resize(len, 0);
+    }
+}
+
+/// Сохраняет payload в session keyring под описанием description
+/// Если ключ уже есть, его содержимое заменяется
+pub fn store_key(description: &str, payload: &[u8]) -> Result<(), SecretError> {
+    let key_type = CString::new(KEY_TYPE_USER).unwrap();
+    let description = to_cstring(description)?;
+
+    let id = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            key_type.as_ptr(),
+            description.as_ptr(),
+            payload.as_ptr(),
+            payload.len(),
+            KEY_SPEC_SESSION_KEYRING,
+        )
+    };
+    let id = Errno::result(id)?;
+    trace!("keyring stored key id: {}", id);
+
+    Ok(())
+}
+
+fn to_cstring(s: &str) -> Result<CString, SecretError> {
+    CString::new(s).map_err(|e| SecretError::StdIoError(e.into()))
+}
@@ -0,0 +1,90 @@
+mod command;
+pub mod keyring;
+mod secret_error;
+
+use std::fs::File;
+use std::io::Read;
+use std::os::fd::{FromRawFd, RawFd};
+use std::time::Duration;
+
+pub use command::read_command;
+pub use secret_error::SecretError;
+
+/// Откуда брать пароль
+#[derive(Debug)]
+pub enum PasswordSource {
+    /// -p, пароль передан аргументом
+    Argument(String),
+    /// -f, первая строка файла
+    File(String),
+    /// -d, первая строка из открытого файлового дескриптора
+    Fd(RawFd),
+    /// -e, переменная окружения (по умолчанию SSHPASS)
+    Env(String),
+    /// --password-cmd, stdout вспомогательной программы
+    Command(String, Duration),
+    /// --password-keyring, ключ из keyring ядра
+    Keyring(String),
+}
+
+impl PasswordSource {
+    /// Вид источника без самого значения, для сводок и диагностики
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Argument(_) => "argument",
+            Self::File(_) => "file",
+            Self::Fd(_) => "fd",
+            Self::Env(_) => "env",
+            Self::Command(..) => "command",
+            Self::Keyring(_) => "keyring",
+        }
+    }
+
+    /// Получает пароль из источника
+    /// Из файлов, дескрипторов и вывода программ берется только первая строка
+    pub fn read(&self) -> Result<Vec<u8>, SecretError> {
+        match self {
+            Self::Argument(password) => Ok(password.as_bytes().to_vec()),
+            Self::File(filename) => {
+                let mut buf = vec![];
+                File::open(filename)?.read_to_end(&mut buf)?;
+                Ok(first_line(buf))
+            }
+            Self::Fd(fd) => {
+                // дескриптор принадлежит вызывающему процессу, закрывать его нельзя
+                let mut file = std::mem::ManuallyDrop::new(unsafe { File::from_raw_fd(*fd) });
+                // вызывающий процесс может держать pipe открытым, поэтому чтение по байту
+                // до перевода строки, а не до EOF, все после строки остается в дескрипторе
+                let mut buf = vec![];
+                let mut byte = [0u8; 1];
+                loop {
+                    match file.read(&mut byte) {
+                        Ok(0) => break,
+                        Ok(_) if byte[0] == b'\n' => break,
+                        Ok(_) => buf.push(byte[0]),
+                        Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+                Ok(first_line(buf))
+            }
+            Self::Env(name) => std::env::var(name)
+                .map(|password| password.into_bytes())
+                .map_err(|_| SecretError::EnvNotFound(name.clone())),
+            Self::Command(command, timeout) => Ok(first_line(read_command(command, *timeout)?)),
+            Self::Keyring(description) => keyring::read_key(description),
+        }
+    }
+}
+
+fn first_line(mut buf: Vec<u8>) -> Vec<u8> {
+    if let Some(pos) = buf.iter().position(|&b| b == b'\n') {
+        buf.truncate(pos);
+    }
+
+    if buf.ends_with(b"\r") {
+        buf.pop();
+    }
+
+    buf
+}
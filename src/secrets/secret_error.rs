@@ -0,0 +1,40 @@
+use std::fmt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum SecretError {
+    StdIoError(std::io::Error),
+    NixErrorno(nix::errno::Errno),
+    EnvNotFound(String),
+    CommandTimeout(Duration),
+    CommandFailed(ExitStatus),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::StdIoError(e) => write!(f, "{}", e),
+            SecretError::NixErrorno(e) => write!(f, "{}", e),
+            SecretError::EnvNotFound(name) => write!(f, "env variable {} is not set", name),
+            SecretError::CommandTimeout(timeout) => {
+                write!(f, "command did not finish within {}s", timeout.as_secs())
+            }
+            SecretError::CommandFailed(status) => write!(f, "command failed: {}", status),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+impl From<std::io::Error> for SecretError {
+    fn from(error: std::io::Error) -> Self {
+        SecretError::StdIoError(error)
+    }
+}
+
+impl From<nix::errno::Errno> for SecretError {
+    fn from(e: nix::errno::Errno) -> Self {
+        SecretError::NixErrorno(e)
+    }
+}
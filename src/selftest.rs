@@ -0,0 +1,100 @@
+use std::io::{BufRead, Write};
+use std::os::fd::AsRawFd;
+
+use clap::Args;
+use nix::libc;
+use termios::{tcsetattr, Termios, ECHO, TCSANOW};
+
+/// Ожидаемый пароль для selftest-child
+pub const SELFTEST_EXPECT_ENV: &str = "SSHPASS_SELFTEST_EXPECT";
+
+/// Детерминированный интерактивный процесс для проверки sshpass без OpenSSH:
+/// sshpass -p secret sshpass selftest-child
+#[derive(Debug, Args)]
+pub struct SelftestArgs {
+    /// Prompt to print before reading the password
+    #[arg(long, default_value = "Password: ")]
+    pub prompt: String,
+
+    /// How many times to ask before giving up, like ssh NumberOfPasswordPrompts
+    #[arg(long, default_value_t = 3)]
+    pub attempts: u32,
+
+    /// Print all 256 byte values after a successful login
+    #[arg(long)]
+    pub binary: bool,
+
+    /// Print the terminal size after a successful login
+    #[arg(long)]
+    pub winsize: bool,
+}
+
+/// Ведет себя как ssh: спрашивает пароль, сверяет его с SSHPASS_SELFTEST_EXPECT
+/// (без переменной подходит любой), при ошибке спрашивает снова.
+/// Код выхода 0 - пароль принят, 1 - попытки закончились
+pub fn run(args: &SelftestArgs) -> i32 {
+    let expect = std::env::var(SELFTEST_EXPECT_ENV).ok();
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+
+    for _ in 0..args.attempts {
+        // как ssh, эхо выключается до вывода приглашения, иначе быстрый ответ успеет отобразиться
+        let fd = stdin.as_raw_fd();
+        let saved = Termios::from_fd(fd).ok();
+        if let Some(mut termios) = saved {
+            termios.c_lflag &= !ECHO;
+            let _ = tcsetattr(fd, TCSANOW, &termios);
+        }
+
+        let _ = write!(stdout, "{}", args.prompt);
+        let _ = stdout.flush();
+
+        let mut line = String::new();
+        let res = stdin.lock().read_line(&mut line);
+
+        if let Some(termios) = saved {
+            let _ = tcsetattr(fd, TCSANOW, &termios);
+            let _ = writeln!(stdout);
+        }
+
+        match res {
+            Ok(0) | Err(_) => {
+                let _ = writeln!(stdout, "selftest: no input");
+                return 1;
+            }
+            Ok(_) => {}
+        }
+        let password = line.trim_end_matches(['\r', '\n']);
+
+        if expect.as_deref().is_none_or(|expect| expect == password) {
+            let _ = writeln!(stdout, "selftest: login ok");
+            after_login(args, &mut stdout);
+            return 0;
+        }
+
+        let _ = writeln!(stdout, "Permission denied, please try again.");
+    }
+
+    let _ = writeln!(stdout, "selftest: permission denied");
+    1
+}
+
+fn after_login(args: &SelftestArgs, stdout: &mut std::io::Stdout) {
+    if args.winsize {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        let ret = unsafe { libc::ioctl(stdout.as_raw_fd(), libc::TIOCGWINSZ, &mut size) };
+        if ret == 0 {
+            let _ = writeln!(stdout, "selftest: winsize {}x{}", size.ws_row, size.ws_col);
+        } else {
+            let _ = writeln!(stdout, "selftest: winsize unknown");
+        }
+    }
+
+    if args.binary {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let _ = stdout.write_all(&bytes);
+        let _ = writeln!(stdout);
+    }
+
+    let _ = stdout.flush();
+}
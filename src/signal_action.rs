@@ -0,0 +1,106 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use nix::sys::signal::Signal;
+
+/// Что sshpass делает с полученным сигналом (--on-signal)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// остановка с ожиданием программы и вывода
+    Shutdown,
+    /// остановка сразу, без ожидания программы
+    Terminate,
+    /// передать сигнал программе
+    Forward,
+    /// передать сигнал группе процессов на переднем плане pty, как это делает терминал
+    ForwardGroup,
+    /// снимок счетчиков дескрипторов в лог; по умолчанию не назначен ни одному сигналу,
+    /// включается через --on-signal USR1=status
+    Status,
+    Ignore,
+}
+
+impl FromStr for SignalAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "shutdown" => Ok(SignalAction::Shutdown),
+            "terminate" => Ok(SignalAction::Terminate),
+            "forward" => Ok(SignalAction::Forward),
+            "forward-group" => Ok(SignalAction::ForwardGroup),
+            "status" => Ok(SignalAction::Status),
+            "ignore" => Ok(SignalAction::Ignore),
+            _ => Err(format!(
+                "unknown action '{}', expected shutdown, terminate, forward, forward-group, \
+                 status or ignore",
+                s
+            )),
+        }
+    }
+}
+
+/// Сигналы, которые sshpass обрабатывает сам и переназначить которые нельзя:
+/// завершение программы, остановка и продолжение задания, размер окна
+const RESERVED: [Signal; 4] = [
+    Signal::SIGCHLD,
+    Signal::SIGTSTP,
+    Signal::SIGCONT,
+    Signal::SIGWINCH,
+];
+
+/// Действия для сигналов; не упомянутые в таблице сигналы игнорируются
+#[derive(Debug, Clone)]
+pub struct SignalActions {
+    actions: BTreeMap<Signal, SignalAction>,
+}
+
+impl Default for SignalActions {
+    fn default() -> Self {
+        Self {
+            actions: BTreeMap::from([
+                // как ^C в терминале программы
+                (Signal::SIGINT, SignalAction::ForwardGroup),
+                (Signal::SIGTERM, SignalAction::Shutdown),
+                (Signal::SIGQUIT, SignalAction::Terminate),
+                // сигналы управления самой программой: перечитать настройки, переоткрыть логи
+                (Signal::SIGHUP, SignalAction::Forward),
+                (Signal::SIGUSR1, SignalAction::Forward),
+                (Signal::SIGUSR2, SignalAction::Forward),
+            ]),
+        }
+    }
+}
+
+impl SignalActions {
+    /// Умолчания, поверх которых применены --on-signal в порядке из командной строки
+    pub fn with(overrides: &[(Signal, SignalAction)]) -> Self {
+        let mut actions = Self::default();
+        actions.actions.extend(overrides.iter().copied());
+        actions
+    }
+
+    pub fn action(&self, signal: Signal) -> SignalAction {
+        self.actions.get(&signal).copied().unwrap_or(SignalAction::Ignore)
+    }
+}
+
+/// Разбирает SIGNAL=ACTION, сигнал по имени с префиксом SIG или без: TERM, SIGHUP, usr1
+pub fn parse_signal_action(s: &str) -> Result<(Signal, SignalAction), String> {
+    let (name, action) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected SIGNAL=ACTION, got '{}'", s))?;
+
+    let name = name.trim().to_ascii_uppercase();
+    let name = if name.starts_with("SIG") { name } else { format!("SIG{}", name) };
+    let signal = Signal::from_str(&name).map_err(|_| format!("unknown signal '{}'", name))?;
+    if RESERVED.contains(&signal) {
+        return Err(format!("{} is handled by sshpass itself and cannot be remapped", signal));
+    }
+    // SIGKILL и SIGSTOP нельзя перехватить, до signalfd они не доходят
+    if matches!(signal, Signal::SIGKILL | Signal::SIGSTOP) {
+        return Err(format!("{} cannot be caught", signal));
+    }
+
+    Ok((signal, action.trim().parse()?))
+}
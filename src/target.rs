@@ -0,0 +1,621 @@
+use std::fmt;
+use std::path::Path;
+
+/// Опции ssh, после которых идет значение (man ssh)
+const SSH_OPTS_WITH_VALUE: &str = "BbcDEeFIiJLlmOoPpQRSWw";
+/// Опции scp, после которых идет значение (man scp)
+const SCP_OPTS_WITH_VALUE: &str = "cDFiJlOoPSX";
+/// Опции sftp, после которых идет значение (man sftp)
+const SFTP_OPTS_WITH_VALUE: &str = "BbcDFiJlOoPRSsX";
+/// Короткие опции rsync, после которых идет значение
+const RSYNC_OPTS_WITH_VALUE: &str = "eBfMT";
+
+/// Опции, которые не дают ssh уйти в цикл повторных запросов пароля
+/// и выбрать аутентификацию, на которую sshpass не умеет отвечать
+const SAFE_SSH_OPTIONS: [(&str, &str); 2] = [
+    ("NumberOfPasswordPrompts", "1"),
+    ("PreferredAuthentications", "password,keyboard-interactive"),
+];
+
+/// Куда подключается дочерний процесс, насколько это удалось понять по его аргументам
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Target {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(user) = &self.user {
+            write!(f, "{}@", user)?;
+        }
+
+        write!(f, "{}", self.host)?;
+
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Разбирает командную строку ssh, scp, sftp или rsync и достает из нее адрес назначения
+/// Разбор нестрогий: неизвестные опции пропускаются, а для других программ возвращается None
+pub fn parse_target<S: AsRef<str>>(program: &str, args: &[S]) -> Option<Target> {
+    let args: Vec<&str> = args.iter().map(|a| a.as_ref()).collect();
+    let name = Path::new(program).file_name()?.to_str()?;
+
+    match name {
+        "ssh" => parse_ssh(&args),
+        "scp" => parse_copy(&args, SCP_OPTS_WITH_VALUE, 'P'),
+        "sftp" => parse_sftp(&args),
+        "rsync" => parse_rsync(&args),
+        _ => None,
+    }
+}
+
+/// Добавляет SAFE_SSH_OPTIONS в аргументы ssh, scp и sftp
+/// Опции, которые пользователь уже задал через -o, не переопределяются
+pub fn with_safe_ssh_options(program: &str, args: Vec<String>) -> Vec<String> {
+    with_ssh_options(program, args, &SAFE_SSH_OPTIONS)
+}
+
+/// Добавляет -o Key=Value в начало аргументов ssh, scp и sftp, для других программ ничего не меняет
+/// Опции, которые пользователь уже задал через -o, не переопределяются
+pub fn with_ssh_options<V: AsRef<str>>(
+    program: &str,
+    args: Vec<String>,
+    options: &[(&str, V)],
+) -> Vec<String> {
+    let opts_with_value = match Path::new(program).file_name().and_then(|name| name.to_str()) {
+        Some("ssh") => SSH_OPTS_WITH_VALUE,
+        Some("scp") => SCP_OPTS_WITH_VALUE,
+        Some("sftp") => SFTP_OPTS_WITH_VALUE,
+        _ => return args,
+    };
+
+    let mut present = vec![];
+    {
+        let refs: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+        walk_args(
+            &refs,
+            opts_with_value,
+            |opt, value| {
+                if opt == 'o' {
+                    for (key, _) in options {
+                        if ssh_option(value, key).is_some() {
+                            present.push(*key);
+                        }
+                    }
+                }
+            },
+            // после назначения идет удаленная команда, ее аргументы не опции ssh
+            |_| false,
+        );
+    }
+
+    let mut res = vec![];
+    for (key, value) in options {
+        if !present.contains(key) {
+            res.push("-o".to_owned());
+            res.push(format!("{}={}", key, value.as_ref()));
+        }
+    }
+    res.extend(args);
+    res
+}
+
+/// Общая часть разбора опций в стиле getopt
+/// Для каждой опции вызывается on_opt(опция, значение), для остальных аргументов on_arg.
+/// Разбор заканчивается после "--" или когда on_arg вернет false
+fn walk_args<'a>(
+    args: &[&'a str],
+    opts_with_value: &str,
+    mut on_opt: impl FnMut(char, &'a str),
+    mut on_arg: impl FnMut(&'a str) -> bool,
+) {
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i];
+        i += 1;
+
+        if arg == "--" {
+            for arg in &args[i..] {
+                if !on_arg(arg) {
+                    break;
+                }
+            }
+            return;
+        }
+
+        if arg.starts_with("--") || arg == "-" || !arg.starts_with('-') {
+            if !arg.starts_with("--") && !on_arg(arg) {
+                return;
+            }
+            continue;
+        }
+
+        // сгруппированные флаги: -vvp 22, -p22, -oPort=22
+        for (pos, opt) in arg.char_indices().skip(1) {
+            if opts_with_value.contains(opt) {
+                let rest = &arg[pos + opt.len_utf8()..];
+                let value = if !rest.is_empty() {
+                    rest
+                } else if i < args.len() {
+                    i += 1;
+                    args[i - 1]
+                } else {
+                    ""
+                };
+                on_opt(opt, value);
+                break;
+            }
+        }
+    }
+}
+
+/// [user@]host или ssh://[user@]host[:port]
+fn split_user_host(spec: &str) -> (Option<String>, String, Option<u16>) {
+    let (spec, uri) = match spec.split_once("://") {
+        Some((_, rest)) => (rest.split('/').next().unwrap_or(rest), true),
+        None => (spec, false),
+    };
+
+    let (user, host) = match spec.rsplit_once('@') {
+        Some((user, host)) => (Some(user.to_owned()), host),
+        None => (None, spec),
+    };
+
+    if uri {
+        if let Some((host, port)) = host.rsplit_once(':') {
+            if let Ok(port) = port.parse() {
+                return (user, host.trim_matches(['[', ']']).to_owned(), Some(port));
+            }
+        }
+    }
+
+    (user, host.trim_matches(['[', ']']).to_owned(), None)
+}
+
+/// Значение -o Key=Value или -o "Key Value", ключ без учета регистра
+fn ssh_option<'a>(value: &'a str, key: &str) -> Option<&'a str> {
+    let (k, v) = value
+        .split_once('=')
+        .or_else(|| value.split_once(char::is_whitespace))?;
+    k.trim().eq_ignore_ascii_case(key).then(|| v.trim())
+}
+
+fn parse_ssh(args: &[&str]) -> Option<Target> {
+    let mut user = None;
+    let mut port = None;
+    let mut destination = None;
+
+    walk_args(
+        args,
+        SSH_OPTS_WITH_VALUE,
+        |opt, value| match opt {
+            'l' => user = Some(value.to_owned()),
+            'p' => port = value.parse().ok(),
+            'o' => {
+                if let Some(v) = ssh_option(value, "User") {
+                    user = Some(v.to_owned());
+                }
+                if let Some(v) = ssh_option(value, "Port") {
+                    port = v.parse().ok();
+                }
+            }
+            _ => {}
+        },
+        |arg| {
+            destination = Some(arg);
+            false
+        },
+    );
+
+    let (spec_user, host, spec_port) = split_user_host(destination?);
+    Some(Target {
+        user: spec_user.or(user),
+        host,
+        port: spec_port.or(port),
+    })
+}
+
+/// scp: первый аргумент вида [user@]host:path или scp://...
+fn parse_copy(args: &[&str], opts_with_value: &str, port_opt: char) -> Option<Target> {
+    let mut port = None;
+    let mut user = None;
+    let mut destination = None;
+
+    walk_args(
+        args,
+        opts_with_value,
+        |opt, value| {
+            if opt == port_opt {
+                port = value.parse().ok();
+            }
+            if opt == 'o' {
+                if let Some(v) = ssh_option(value, "User") {
+                    user = Some(v.to_owned());
+                }
+                if let Some(v) = ssh_option(value, "Port") {
+                    port = v.parse().ok();
+                }
+            }
+        },
+        |arg| {
+            if let Some(remote) = remote_spec(arg) {
+                destination = Some(remote);
+                return false;
+            }
+            true
+        },
+    );
+
+    let (spec_user, host, spec_port) = split_user_host(destination?);
+    Some(Target {
+        user: spec_user.or(user),
+        host,
+        port: spec_port.or(port),
+    })
+}
+
+/// sftp: первый аргумент назначение, путь после ':' необязателен
+fn parse_sftp(args: &[&str]) -> Option<Target> {
+    if let Some(target) = parse_copy(args, SFTP_OPTS_WITH_VALUE, 'P') {
+        return Some(target);
+    }
+
+    let mut port = None;
+    let mut destination = None;
+    walk_args(
+        args,
+        SFTP_OPTS_WITH_VALUE,
+        |opt, value| {
+            if opt == 'P' {
+                port = value.parse().ok();
+            }
+        },
+        |arg| {
+            destination = Some(arg);
+            false
+        },
+    );
+
+    let (user, host, spec_port) = split_user_host(destination?);
+    Some(Target {
+        user,
+        host,
+        port: spec_port.or(port),
+    })
+}
+
+/// rsync: [user@]host:path, [user@]host::module или rsync://...
+/// Порт ssh берется из -e "ssh -p N" / --rsh="ssh -p N"
+fn parse_rsync(args: &[&str]) -> Option<Target> {
+    let mut rsh = None;
+    let mut port = None;
+    let mut destination = None;
+
+    for arg in args {
+        if let Some(value) = arg.strip_prefix("--rsh=") {
+            rsh = Some(value);
+        }
+        if let Some(value) = arg.strip_prefix("--port=") {
+            port = value.parse().ok();
+        }
+    }
+
+    walk_args(
+        args,
+        RSYNC_OPTS_WITH_VALUE,
+        |opt, value| {
+            if opt == 'e' {
+                rsh = Some(value);
+            }
+        },
+        |arg| {
+            if arg.starts_with("rsync://") {
+                destination = Some(arg);
+                return false;
+            }
+            if let Some(remote) = remote_spec(arg) {
+                destination = Some(remote);
+                return false;
+            }
+            true
+        },
+    );
+
+    if let Some(rsh) = rsh {
+        let rsh: Vec<&str> = rsh.split_whitespace().skip(1).collect();
+        walk_args(
+            &rsh,
+            SSH_OPTS_WITH_VALUE,
+            |opt, value| {
+                if opt == 'p' && port.is_none() {
+                    port = value.parse().ok();
+                }
+            },
+            |_| true,
+        );
+    }
+
+    let (user, host, spec_port) = split_user_host(destination?);
+    Some(Target {
+        user,
+        host,
+        port: spec_port.or(port),
+    })
+}
+
+/// Из "user@host:path" возвращает "user@host", для локальных путей None
+fn remote_spec(arg: &str) -> Option<&str> {
+    if arg.contains("://") {
+        return Some(arg);
+    }
+
+    // локальный путь с двоеточием после слеша, например ./a:b
+    let colon = arg.find(':')?;
+    if arg[..colon].contains('/') || colon == 0 {
+        return None;
+    }
+
+    // [ipv6]:path
+    if arg.starts_with('[') || arg.contains("@[") {
+        let close = arg.find(']')?;
+        return Some(&arg[..close + 1]);
+    }
+
+    Some(&arg[..colon])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(user: Option<&str>, host: &str, port: Option<u16>) -> Option<Target> {
+        Some(Target {
+            user: user.map(str::to_owned),
+            host: host.to_owned(),
+            port,
+        })
+    }
+
+    #[test]
+    fn ssh_user_and_port_options() {
+        assert_eq!(
+            parse_target("ssh", &["-l", "alice", "-p", "2222", "example.com"]),
+            target(Some("alice"), "example.com", Some(2222))
+        );
+        assert_eq!(
+            parse_target("/usr/bin/ssh", &["-p2222", "-lalice", "example.com", "uptime"]),
+            target(Some("alice"), "example.com", Some(2222))
+        );
+    }
+
+    #[test]
+    fn ssh_user_and_port_from_o_options() {
+        assert_eq!(
+            parse_target("ssh", &["-o", "User=bob", "-oPort=2200", "example.com"]),
+            target(Some("bob"), "example.com", Some(2200))
+        );
+        assert_eq!(
+            parse_target("ssh", &["-o", "port 2201", "example.com"]),
+            target(None, "example.com", Some(2201))
+        );
+    }
+
+    #[test]
+    fn ssh_destination_overrides_options() {
+        assert_eq!(
+            parse_target("ssh", &["-l", "alice", "bob@example.com"]),
+            target(Some("bob"), "example.com", None)
+        );
+    }
+
+    #[test]
+    fn ssh_remote_command_is_not_parsed() {
+        assert_eq!(
+            parse_target("ssh", &["-v", "example.com", "ls", "-p", "1"]),
+            target(None, "example.com", None)
+        );
+    }
+
+    #[test]
+    fn ssh_uri() {
+        assert_eq!(
+            parse_target("ssh", &["ssh://alice@example.com:2222"]),
+            target(Some("alice"), "example.com", Some(2222))
+        );
+        assert_eq!(
+            parse_target("ssh", &["ssh://[::1]:2222"]),
+            target(None, "::1", Some(2222))
+        );
+    }
+
+    #[test]
+    fn scp_host_path() {
+        assert_eq!(
+            parse_target("scp", &["-P", "2222", "./local:file", "alice@example.com:/tmp/"]),
+            target(Some("alice"), "example.com", Some(2222))
+        );
+        assert_eq!(
+            parse_target("scp", &["-o", "User=bob", "example.com:file", "."]),
+            target(Some("bob"), "example.com", None)
+        );
+        assert_eq!(parse_target("scp", &["a", "b"]), None);
+    }
+
+    #[test]
+    fn scp_ipv6_path() {
+        assert_eq!(
+            parse_target("scp", &["file", "alice@[fe80::1]:/tmp/"]),
+            target(Some("alice"), "fe80::1", None)
+        );
+        assert_eq!(parse_target("scp", &["[::1]:file", "."]), target(None, "::1", None));
+    }
+
+    #[test]
+    fn sftp_destination_without_path() {
+        assert_eq!(
+            parse_target("sftp", &["-P", "2222", "alice@example.com"]),
+            target(Some("alice"), "example.com", Some(2222))
+        );
+    }
+
+    #[test]
+    fn rsync_host_module() {
+        assert_eq!(
+            parse_target("rsync", &["-av", "alice@example.com::backup/", "."]),
+            target(Some("alice"), "example.com", None)
+        );
+        assert_eq!(
+            parse_target("rsync", &["-av", "./dir", "example.com:/srv/"]),
+            target(None, "example.com", None)
+        );
+    }
+
+    #[test]
+    fn rsync_uri() {
+        assert_eq!(
+            parse_target("rsync", &["rsync://alice@example.com:8730/backup", "."]),
+            target(Some("alice"), "example.com", Some(8730))
+        );
+    }
+
+    #[test]
+    fn rsync_port_from_rsh() {
+        assert_eq!(
+            parse_target("rsync", &["-e", "ssh -p 2222", "./dir", "example.com:/srv/"]),
+            target(None, "example.com", Some(2222))
+        );
+        assert_eq!(
+            parse_target("rsync", &["--rsh=ssh -p2223", "example.com:/srv/", "."]),
+            target(None, "example.com", Some(2223))
+        );
+    }
+
+    #[test]
+    fn other_programs_have_no_target() {
+        assert_eq!(parse_target("telnet", &["example.com"]), None);
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    /// Опции и аргументы в порядке, в котором их увидел walk_args
+    fn walk(args: &[&str], stop_at: Option<&str>) -> (Vec<(char, String)>, Vec<String>) {
+        let mut opts = vec![];
+        let mut rest = vec![];
+        walk_args(
+            args,
+            SSH_OPTS_WITH_VALUE,
+            |opt, value| opts.push((opt, value.to_owned())),
+            |arg| {
+                rest.push(arg.to_owned());
+                Some(arg) != stop_at
+            },
+        );
+        (opts, rest)
+    }
+
+    #[test]
+    fn safe_options_are_prepended() {
+        assert_eq!(
+            with_safe_ssh_options("ssh", strings(&["example.com"])),
+            strings(&[
+                "-o",
+                "NumberOfPasswordPrompts=1",
+                "-o",
+                "PreferredAuthentications=password,keyboard-interactive",
+                "example.com",
+            ])
+        );
+    }
+
+    #[test]
+    fn options_given_by_user_are_kept() {
+        let expected = |args: &[&str]| {
+            let mut res = strings(&["-o", "PreferredAuthentications=password,keyboard-interactive"]);
+            res.extend(strings(args));
+            res
+        };
+
+        // -o Key=Value, -oKey=Value и -o "Key Value", ключ без учета регистра
+        for args in [
+            &["-o", "NumberOfPasswordPrompts=3", "example.com"][..],
+            &["-oNumberOfPasswordPrompts=3", "example.com"],
+            &["-o", "numberofpasswordprompts 3", "example.com"],
+            &["-vo", "NumberOfPasswordPrompts=3", "example.com"],
+        ] {
+            assert_eq!(with_safe_ssh_options("ssh", strings(args)), expected(args));
+        }
+    }
+
+    #[test]
+    fn options_after_destination_are_not_counted() {
+        // -o после назначения относится к удаленной команде, а после -- это уже не опция
+        for args in [
+            &["example.com", "cmd", "-o", "NumberOfPasswordPrompts=3"][..],
+            &["--", "example.com", "-o", "NumberOfPasswordPrompts=3"],
+            &["-p", "22", "--", "-oNumberOfPasswordPrompts=3"],
+        ] {
+            let res = with_safe_ssh_options("ssh", strings(args));
+            assert_eq!(res[..2], strings(&["-o", "NumberOfPasswordPrompts=1"]), "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn ssh_options_for_scp_and_sftp_only() {
+        let res = with_ssh_options("/usr/bin/scp", strings(&["-P", "22", "a", "host:b"]), &[("StrictHostKeyChecking", "yes")]);
+        assert_eq!(res, strings(&["-o", "StrictHostKeyChecking=yes", "-P", "22", "a", "host:b"]));
+
+        let res = with_ssh_options("sftp", strings(&["-o", "StrictHostKeyChecking=no", "host"]), &[("StrictHostKeyChecking", "yes")]);
+        assert_eq!(res, strings(&["-o", "StrictHostKeyChecking=no", "host"]));
+
+        let args = strings(&["-o", "x", "host"]);
+        assert_eq!(with_safe_ssh_options("rsync", args.clone()), args);
+    }
+
+    #[test]
+    fn walk_args_grouped_and_attached_values() {
+        let (opts, rest) = walk(&["-vvp", "22", "-lalice", "-oPort=2", "host"], None);
+        assert_eq!(
+            opts,
+            vec![('p', "22".to_owned()), ('l', "alice".to_owned()), ('o', "Port=2".to_owned())]
+        );
+        assert_eq!(rest, strings(&["host"]));
+    }
+
+    #[test]
+    fn walk_args_missing_value_at_the_end() {
+        let (opts, rest) = walk(&["host", "-p"], None);
+        assert_eq!(opts, vec![('p', String::new())]);
+        assert_eq!(rest, strings(&["host"]));
+    }
+
+    #[test]
+    fn walk_args_double_dash_ends_options() {
+        let (opts, rest) = walk(&["-p", "22", "--", "-l", "host", "--"], None);
+        assert_eq!(opts, vec![('p', "22".to_owned())]);
+        assert_eq!(rest, strings(&["-l", "host", "--"]));
+
+        // длинные опции пропускаются, "-" это аргумент
+        let (opts, rest) = walk(&["--verbose", "-", "host"], None);
+        assert!(opts.is_empty());
+        assert_eq!(rest, strings(&["-", "host"]));
+    }
+
+    #[test]
+    fn walk_args_stops_when_on_arg_returns_false() {
+        let (opts, rest) = walk(&["host", "-p", "22", "cmd"], Some("host"));
+        assert!(opts.is_empty());
+        assert_eq!(rest, strings(&["host"]));
+
+        let (_, rest) = walk(&["--", "host", "cmd"], Some("host"));
+        assert_eq!(rest, strings(&["host"]));
+    }
+}
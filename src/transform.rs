@@ -0,0 +1,79 @@
+/// Что стадия просит сделать помимо передачи данных дальше по конвейеру
+/// Стадии вывода pty отвечают в pty и ограничивают ожидание, стадии ввода (escape-последовательности)
+/// управляют самой сессией
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransformEvent {
+    /// записать в pty; первое поле - кто отправляет, для -vv
+    SendToPty(&'static str, Vec<u8>),
+    /// стадия не дождалась нужного вывода, сессия завершается
+    #[cfg_attr(not(feature = "rule-engine"), allow(dead_code))]
+    Timeout(String),
+    /// завершить сессию по просьбе пользователя
+    Disconnect,
+    /// приостановить sshpass, как по ^Z
+    Suspend,
+    /// сообщение пользователю в терминал
+    Notice(String),
+}
+
+/// Стадия обработки потока данных (вывода pty или ввода с клавиатуры)
+/// Получает фрагмент от предыдущей стадии и возвращает то, что пойдет следующей,
+/// действия (ответы в pty, таймауты) складываются в events
+pub trait Transform: std::fmt::Debug {
+    fn name(&self) -> &'static str;
+
+    fn feed(&mut self, chunk: Vec<u8>, events: &mut Vec<TransformEvent>) -> Vec<u8>;
+
+    /// Вызывается на каждом обороте цикла: для стадий, которые что-то ждут по времени
+    fn tick(&mut self, _events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        vec![]
+    }
+}
+
+/// Упорядоченный набор стадий между pty и stdout (или между stdin и pty)
+/// Вывод каждой стадии - вход следующей, вывод последней уходит дальше по пути
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn push(&mut self, stage: impl Transform + 'static) {
+        self.stages.push(Box::new(stage));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    /// Пропускает фрагмент вывода через все стадии
+    pub fn feed(&mut self, chunk: &[u8]) -> (Vec<u8>, Vec<TransformEvent>) {
+        let mut events = vec![];
+        let output = self.run(0, chunk.to_vec(), &mut events);
+        (output, events)
+    }
+
+    /// То, что стадии отдали по времени, проходит через стадии после них
+    pub fn tick(&mut self) -> (Vec<u8>, Vec<TransformEvent>) {
+        let mut events = vec![];
+        let mut output = vec![];
+        for i in 0..self.stages.len() {
+            let released = self.stages[i].tick(&mut events);
+            if !released.is_empty() {
+                output.extend(self.run(i + 1, released, &mut events));
+            }
+        }
+
+        (output, events)
+    }
+
+    fn run(&mut self, from: usize, chunk: Vec<u8>, events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        self.stages[from..]
+            .iter_mut()
+            .fold(chunk, |chunk, stage| stage.feed(chunk, events))
+    }
+}
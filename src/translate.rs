@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use crate::normalize::AnsiFilter;
+use crate::transform::{Transform, TransformEvent};
+
+/// Сколько придерживать \r в конце чтения в ожидании \n
+const CR_HOLD: Duration = Duration::from_millis(100);
+
+/// Обратное преобразование onlcr: pty превращает каждый \n в \r\n, здесь \r\n снова становится \n
+/// Одиночный \r (строка прогресса) остается как есть. \r в конце чтения придерживается,
+/// потому что \n может прийти следующим чтением
+#[derive(Debug, Default)]
+pub struct StripCr {
+    held_since: Option<Instant>,
+}
+
+impl Transform for StripCr {
+    fn name(&self) -> &'static str {
+        "strip-cr"
+    }
+
+    fn feed(&mut self, chunk: Vec<u8>, _events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        // предыдущая стадия могла все придержать у себя
+        if chunk.is_empty() {
+            return chunk;
+        }
+
+        let mut output = Vec::with_capacity(chunk.len() + 1);
+        if self.held_since.take().is_some() && chunk.first() != Some(&b'\n') {
+            output.push(b'\r');
+        }
+
+        let mut bytes = chunk.iter().peekable();
+        while let Some(&b) = bytes.next() {
+            match (b, bytes.peek()) {
+                (b'\r', Some(b'\n')) => {}
+                (b'\r', None) => self.held_since = Some(Instant::now()),
+                _ => output.push(b),
+            }
+        }
+
+        output
+    }
+
+    fn tick(&mut self, _events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        match self.held_since {
+            Some(since) if since.elapsed() >= CR_HOLD => {
+                self.held_since = None;
+                b"\r".to_vec()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Убирает из вывода ANSI последовательности: цвета, перемещения курсора, заголовки окна
+#[derive(Debug, Default)]
+pub struct StripAnsi {
+    filter: AnsiFilter,
+}
+
+impl Transform for StripAnsi {
+    fn name(&self) -> &'static str {
+        "strip-ansi"
+    }
+
+    fn feed(&mut self, mut chunk: Vec<u8>, _events: &mut Vec<TransformEvent>) -> Vec<u8> {
+        chunk.retain(|&b| !self.filter.skip(b));
+        chunk
+    }
+}
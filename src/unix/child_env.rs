@@ -0,0 +1,29 @@
+use std::process::Command;
+
+/// Переменные окружения, которые sshpass всегда убирает у дочернего процесса,
+/// чтобы пароль не утек дальше (как в оригинальном sshpass)
+pub const DEFAULT_ENV_REMOVE: [&str; 1] = ["SSHPASS"];
+
+/// Окружение дочернего процесса
+/// Порядок применения: clear, затем remove, затем set
+#[derive(Debug, Clone, Default)]
+pub struct ChildEnv {
+    /// запустить с пустым окружением
+    pub clear: bool,
+    pub remove: Vec<String>,
+    pub set: Vec<(String, String)>,
+}
+
+impl ChildEnv {
+    pub fn apply(&self, cmd: &mut Command) {
+        if self.clear {
+            cmd.env_clear();
+        }
+
+        for key in &self.remove {
+            cmd.env_remove(key);
+        }
+
+        cmd.envs(self.set.iter().map(|(key, value)| (key, value)));
+    }
+}
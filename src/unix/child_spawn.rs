@@ -0,0 +1,231 @@
+use std::ffi::CString;
+use std::fmt;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::resource::{getrlimit, Resource};
+use nix::sys::stat::{umask, Mode};
+use nix::unistd::{
+    chdir, getpid, initgroups, setgid, setsid, setuid, tcsetpgrp, Gid, Group, Uid, User,
+};
+
+use super::child_env::ChildEnv;
+use super::limits::{apply_limits, LimitError, RLimit};
+use super::sched::{SchedError, SchedPolicy};
+
+/// Предел перебора дескрипторов, если ядро не знает close_range (до 5.9)
+const CLOSE_FDS_FALLBACK_LIMIT: u64 = 65536;
+
+/// Пользователь, от имени которого запускается программа (--child-user USER[:GROUP])
+/// Имена разрешаются при разборе аргументов, в дочернем процессе остаются только системные вызовы
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChildUser {
+    name: CString,
+    uid: Uid,
+    gid: Gid,
+}
+
+impl FromStr for ChildUser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+
+        let found = match user.parse::<u32>() {
+            Ok(uid) => User::from_uid(Uid::from_raw(uid)),
+            Err(_) => User::from_name(user),
+        };
+        let user = found
+            .map_err(|e| format!("failed to look up user '{}': {}", user, e))?
+            .ok_or_else(|| format!("unknown user '{}'", user))?;
+
+        let gid = match group {
+            None => user.gid,
+            Some(group) => match group.parse::<u32>() {
+                Ok(gid) => Gid::from_raw(gid),
+                Err(_) => {
+                    Group::from_name(group)
+                        .map_err(|e| format!("failed to look up group '{}': {}", group, e))?
+                        .ok_or_else(|| format!("unknown group '{}'", group))?
+                        .gid
+                }
+            },
+        };
+
+        Ok(Self {
+            name: CString::new(user.name).map_err(|e| e.to_string())?,
+            uid: user.uid,
+            gid,
+        })
+    }
+}
+
+/// Как запускать дочерний процесс: окружение, рабочий каталог, пользователь, umask,
+/// приоритеты и лимиты, закрытие унаследованных дескрипторов
+/// Все применяется в дочернем процессе между fork и exec
+#[derive(Debug, Clone, Default)]
+pub struct ChildSpawnOptions {
+    env: ChildEnv,
+    sched: SchedPolicy,
+    limits: Vec<RLimit>,
+    cwd: Option<PathBuf>,
+    user: Option<ChildUser>,
+    umask: Option<Mode>,
+    close_fds: bool,
+    separate_stderr: bool,
+}
+
+/// Какую настройку дочернего процесса не удалось применить
+#[derive(Debug)]
+pub enum ChildSpawnError {
+    Sched(SchedError),
+    Limit(LimitError),
+    Cwd(PathBuf, Errno),
+    User(&'static str, Errno),
+    /// новая сессия или управляющий терминал
+    Session(&'static str, Errno),
+}
+
+impl fmt::Display for ChildSpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChildSpawnError::Sched(e) => write!(f, "{}", e),
+            ChildSpawnError::Limit(e) => write!(f, "{}", e),
+            ChildSpawnError::Cwd(dir, e) => {
+                write!(f, "failed to change directory to {}: {}", dir.display(), e)
+            }
+            ChildSpawnError::User(call, e) => write!(f, "failed to switch user, {}: {}", call, e),
+            ChildSpawnError::Session(call, e) => {
+                write!(f, "failed to attach the controlling terminal, {}: {}", call, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ChildSpawnError {}
+
+/// Из pre_exec до родителя доходит только errno
+impl From<ChildSpawnError> for io::Error {
+    fn from(e: ChildSpawnError) -> Self {
+        match e {
+            ChildSpawnError::Sched(e) => e.source,
+            ChildSpawnError::Limit(e) => e.errno.into(),
+            ChildSpawnError::Cwd(_, errno)
+            | ChildSpawnError::User(_, errno)
+            | ChildSpawnError::Session(_, errno) => errno.into(),
+        }
+    }
+}
+
+impl ChildSpawnOptions {
+    pub fn env(mut self, env: ChildEnv) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn sched(mut self, sched: SchedPolicy) -> Self {
+        self.sched = sched;
+        self
+    }
+
+    pub fn limits(mut self, limits: Vec<RLimit>) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn cwd(mut self, cwd: Option<PathBuf>) -> Self {
+        self.cwd = cwd;
+        self
+    }
+
+    pub fn user(mut self, user: Option<ChildUser>) -> Self {
+        self.user = user;
+        self
+    }
+
+    pub fn umask(mut self, umask: Option<Mode>) -> Self {
+        self.umask = umask;
+        self
+    }
+
+    /// Закрыть все дескрипторы кроме stdin, stdout и stderr перед exec
+    pub fn close_fds(mut self, close_fds: bool) -> Self {
+        self.close_fds = close_fds;
+        self
+    }
+
+    /// stderr дочернего процесса идет в отдельный pipe, а не в pty
+    pub fn separate_stderr(mut self, separate_stderr: bool) -> Self {
+        self.separate_stderr = separate_stderr;
+        self
+    }
+
+    pub fn child_env(&self) -> &ChildEnv {
+        &self.env
+    }
+
+    pub fn has_separate_stderr(&self) -> bool {
+        self.separate_stderr
+    }
+
+    pub fn closes_fds(&self) -> bool {
+        self.close_fds
+    }
+
+    /// Применяет настройки к текущему процессу, вызывается в дочернем процессе после fork
+    /// Лимиты и приоритеты ставятся до смены пользователя, пока на них хватает прав
+    pub fn apply(&self) -> Result<(), ChildSpawnError> {
+        self.sched.apply().map_err(ChildSpawnError::Sched)?;
+        apply_limits(&self.limits).map_err(ChildSpawnError::Limit)?;
+
+        if let Some(mask) = self.umask {
+            umask(mask);
+        }
+        if let Some(cwd) = &self.cwd {
+            chdir(cwd).map_err(|e| ChildSpawnError::Cwd(cwd.clone(), e))?;
+        }
+        if let Some(user) = &self.user {
+            // группы до uid: после setuid права на их смену уже не будет
+            initgroups(&user.name, user.gid).map_err(|e| ChildSpawnError::User("initgroups", e))?;
+            setgid(user.gid).map_err(|e| ChildSpawnError::User("setgid", e))?;
+            setuid(user.uid).map_err(|e| ChildSpawnError::User("setuid", e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Делает slave pty управляющим терминалом текущего процесса, вызывается в дочернем процессе
+/// Процесс становится лидером новой сессии и группы процессов, и эта группа - группа
+/// переднего плана терминала: ^C, ^Z и закрытие pty достаются программе, а не sshpass
+pub fn attach_controlling_terminal(slave: impl AsFd) -> Result<(), ChildSpawnError> {
+    setsid().map_err(|e| ChildSpawnError::Session("setsid", e))?;
+
+    let res = unsafe { libc::ioctl(slave.as_fd().as_raw_fd(), libc::TIOCSCTTY, 0) };
+    Errno::result(res).map_err(|e| ChildSpawnError::Session("TIOCSCTTY", e))?;
+    tcsetpgrp(slave, getpid()).map_err(|e| ChildSpawnError::Session("tcsetpgrp", e))?;
+
+    Ok(())
+}
+
+/// Закрывает все дескрипторы начиная с 3, вызывается перед exec, когда stdio уже на месте
+pub fn close_inherited_fds() {
+    let res = unsafe { libc::syscall(libc::SYS_close_range, 3, libc::c_uint::MAX, 0) };
+    if res == 0 {
+        return;
+    }
+
+    let max = getrlimit(Resource::RLIMIT_NOFILE)
+        .map_or(1024, |(soft, _)| soft)
+        .min(CLOSE_FDS_FALLBACK_LIMIT);
+    for fd in 3..max as libc::c_int {
+        unsafe { libc::close(fd) };
+    }
+}
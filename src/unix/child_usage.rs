@@ -0,0 +1,41 @@
+use std::fmt;
+use std::time::Duration;
+
+use nix::libc;
+
+/// Ресурсы, потраченные завершившимся процессом, из rusage, который вернул wait4
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChildUsage {
+    /// пиковый размер резидентной памяти, в Linux ru_maxrss в килобайтах
+    pub max_rss_kb: u64,
+    pub user: Duration,
+    pub system: Duration,
+}
+
+impl From<&libc::rusage> for ChildUsage {
+    fn from(usage: &libc::rusage) -> Self {
+        Self {
+            max_rss_kb: u64::try_from(usage.ru_maxrss).unwrap_or(0),
+            user: timeval(&usage.ru_utime),
+            system: timeval(&usage.ru_stime),
+        }
+    }
+}
+
+fn timeval(tv: &libc::timeval) -> Duration {
+    let secs = u64::try_from(tv.tv_sec).unwrap_or(0);
+    let micros = u32::try_from(tv.tv_usec).unwrap_or(0);
+    Duration::new(secs, micros.saturating_mul(1000))
+}
+
+impl fmt::Display for ChildUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "max rss {} KiB, user {:.3}s, sys {:.3}s",
+            self.max_rss_kb,
+            self.user.as_secs_f64(),
+            self.system.as_secs_f64()
+        )
+    }
+}
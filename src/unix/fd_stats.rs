@@ -0,0 +1,83 @@
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::unix::write_fd::WriteResult;
+
+/// Счетчики активности одного дескриптора
+#[derive(Debug, Default)]
+pub struct FdStats {
+    events: u64,
+    bytes_read: u64,
+    bytes_written: u64,
+    errors: u64,
+    last_activity: Option<Instant>,
+}
+
+impl FdStats {
+    /// poll вернул событие для дескриптора
+    pub fn record_event(&mut self) {
+        self.events += 1;
+        self.touch();
+    }
+
+    pub fn record_read(&mut self, res: &nix::Result<usize>) {
+        match res {
+            Ok(n) => self.bytes_read += *n as u64,
+            Err(_) => self.errors += 1,
+        }
+        self.touch();
+    }
+
+    pub fn record_write(&mut self, res: &WriteResult) {
+        match res {
+            WriteResult::Done(n) | WriteResult::PartialWrite { written: n } => {
+                self.bytes_written += *n as u64
+            }
+            WriteResult::BrokenPipe | WriteResult::Fatal(_) => self.errors += 1,
+            WriteResult::WouldBlock | WriteResult::Interrupted => {}
+        }
+        self.touch();
+    }
+
+    fn touch(&mut self) {
+        self.last_activity = Some(Instant::now());
+    }
+}
+
+/// Снимок счетчиков дескриптора для отладки и внешних потребителей
+#[derive(Debug, Serialize)]
+pub struct FdReport {
+    pub index: usize,
+    pub kind: &'static str,
+    pub fd: i32,
+    pub poll_events: String,
+    pub events: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub errors: u64,
+    /// сколько миллисекунд прошло с последней активности, None если ее не было
+    pub idle_ms: Option<u128>,
+}
+
+impl FdReport {
+    pub fn new(
+        index: usize,
+        kind: &'static str,
+        fd: i32,
+        poll_events: String,
+        stats: &FdStats,
+    ) -> Self {
+        Self {
+            index,
+            kind,
+            fd,
+            poll_events,
+            events: stats.events,
+            bytes_read: stats.bytes_read,
+            bytes_written: stats.bytes_written,
+            errors: stats.errors,
+            idle_ms: stats.last_activity.map(|t| t.elapsed().as_millis()),
+        }
+    }
+}
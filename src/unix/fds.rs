@@ -1,20 +1,26 @@
-use std::borrow::{Borrow, BorrowMut};
 use std::io::{Stdin, Stdout};
 // use std::ops::Deref;
-use std::os::fd::OwnedFd;
-use std::cell::{Ref, RefCell, RefMut};
-use std::ops::{Deref, DerefMut};
+use std::os::fd::{BorrowedFd, OwnedFd};
+use std::cell::{Cell, Ref, RefCell, RefMut};
+use std::ops::DerefMut;
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
 
+use bytes::Bytes;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
 use nix::libc::{self};
 use nix::poll::{PollFlags, PollTimeout};
 use nix::pty::OpenptyResult;
 use nix::sys::signalfd::SignalFd;
-use nix::unistd::{write, Pid};
+use nix::unistd::Pid;
 
-use log::error;
+use log::{error, trace, warn};
+
+use crate::unix::fd_stats::{FdReport, FdStats};
+use crate::unix::write_fd::{splice_fd, write_all_fd, WriteResult};
+use crate::unix::write_queue::WriteQueue;
 
-use termios::Termios;
 
 #[derive(Debug)]
 pub enum Fd {
@@ -25,21 +31,47 @@ pub enum Fd {
     Stdin {
         fd: Stdin,
         events: PollFlags,
-        termios: Termios,
     },
     Stdout {
         fd: Stdout,
         events: PollFlags,
+        queue: WriteQueue,
     },
     PtyMaster {
         fd: OwnedFd,
         events: PollFlags,
         child: Pid,
+        queue: WriteQueue,
     },
+    #[allow(dead_code)]
     PtySlave {
         fd: OwnedFd,
         events: PollFlags,
     },
+    /// читающий конец pipe, в который дочерний процесс пишет stderr (--separate-stderr)
+    ChildStderr {
+        fd: OwnedFd,
+        events: PollFlags,
+    },
+    /// откуда родительский процесс присылает пароль (--password-fd-handshake)
+    /// опрашивается только после запроса пароля
+    PasswordResponse {
+        fd: OwnedFd,
+        events: PollFlags,
+    },
+    /// сокет для подключения к сессии со стороны (--attach-socket), файл удаляется при выходе
+    AttachListener {
+        fd: UnixListener,
+        events: PollFlags,
+        path: PathBuf,
+    },
+    /// место для подключенного клиента; слоты создаются заранее и переиспользуются,
+    /// None - свободный слот
+    AttachClient {
+        fd: Option<UnixStream>,
+        events: PollFlags,
+        queue: WriteQueue,
+    },
 }
 
 impl Fd {
@@ -50,6 +82,36 @@ impl Fd {
             Fd::Stdout { fd, .. } => fd.as_raw_fd(),
             Fd::PtyMaster { fd, .. } => fd.as_raw_fd(),
             Fd::PtySlave { fd, .. } => fd.as_raw_fd(),
+            Fd::ChildStderr { fd, .. } => fd.as_raw_fd(),
+            Fd::PasswordResponse { fd, .. } => fd.as_raw_fd(),
+            Fd::AttachListener { fd, .. } => fd.as_raw_fd(),
+            Fd::AttachClient { fd, .. } => fd.as_ref().map_or(-1, |fd| fd.as_raw_fd()),
+        }
+    }
+    pub fn set_events(&mut self, new_events: PollFlags) {
+        match self {
+            Fd::Signal { events, .. } => *events = new_events,
+            Fd::Stdin { events, .. } => *events = new_events,
+            Fd::Stdout { events, .. } => *events = new_events,
+            Fd::PtyMaster { events, .. } => *events = new_events,
+            Fd::PtySlave { events, .. } => *events = new_events,
+            Fd::ChildStderr { events, .. } => *events = new_events,
+            Fd::PasswordResponse { events, .. } => *events = new_events,
+            Fd::AttachListener { events, .. } => *events = new_events,
+            Fd::AttachClient { events, .. } => *events = new_events,
+        }
+    }
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Fd::Signal { .. } => "signal",
+            Fd::Stdin { .. } => "stdin",
+            Fd::Stdout { .. } => "stdout",
+            Fd::PtyMaster { .. } => "pty_master",
+            Fd::PtySlave { .. } => "pty_slave",
+            Fd::ChildStderr { .. } => "child_stderr",
+            Fd::PasswordResponse { .. } => "password_response",
+            Fd::AttachListener { .. } => "attach_listener",
+            Fd::AttachClient { .. } => "attach_client",
         }
     }
     pub fn events(&self) -> &PollFlags {
@@ -59,6 +121,10 @@ impl Fd {
             Fd::Stdout { events, .. } => events,
             Fd::PtyMaster { events, .. } => events,
             Fd::PtySlave { events, .. } => events,
+            Fd::ChildStderr { events, .. } => events,
+            Fd::PasswordResponse { events, .. } => events,
+            Fd::AttachListener { events, .. } => events,
+            Fd::AttachClient { events, .. } => events,
         }
     }
 }
@@ -71,7 +137,17 @@ pub struct Fds {
     stdin_index: Option<usize>,
     stdout_index: Option<usize>,
     pty_master_index: Option<usize>,
+    #[allow(dead_code)]
     pty_slave_index: Option<usize>,
+    child_stderr_index: Option<usize>,
+    password_response_index: Option<usize>,
+    attach_listener_index: Option<usize>,
+    /// слоты клиентов --attach-socket
+    attach_client_indexes: Vec<usize>,
+    /// закрытые дескрипторы (EOF), обратное давление не должно снова включать их чтение
+    closed: RefCell<Vec<usize>>,
+    /// счетчики активности, индекс совпадает с inner
+    stats: RefCell<Vec<FdStats>>,
 }
 
 impl Fds {
@@ -84,6 +160,12 @@ impl Fds {
             stdout_index: None,
             pty_master_index: None,
             pty_slave_index: None,
+            child_stderr_index: None,
+            password_response_index: None,
+            attach_listener_index: None,
+            attach_client_indexes: vec![],
+            closed: RefCell::new(vec![]),
+            stats: RefCell::new(vec![]),
         }
     }
 
@@ -101,6 +183,7 @@ impl Fds {
     }
 
     /// Возвращает true, если список файловых дескрипторов пуст
+    #[allow(dead_code)]
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
@@ -120,6 +203,7 @@ impl Fds {
         self.inner.get(index)
     }
 
+    #[allow(dead_code)]
     pub fn get_fd_by_raw_fd(&self, raw_fd: i32) -> Option<&RefCell<Fd>> {
         self.inner
             .iter()
@@ -130,17 +214,28 @@ impl Fds {
     /// Метод возвращает массив pollfd, который используется в nix::poll::poll.
     /// Если pollfds не был создан, то он создается и возвращается.
     /// Если pollfds был создан, то возвращается ссылка на него.
-    pub fn as_pollfds(&self) -> RefMut<Vec<libc::pollfd>> {
+    pub fn as_pollfds(&self) -> RefMut<'_, Vec<libc::pollfd>> {
         let res = self.pollfds.borrow_mut().as_deref().is_none();
 
         if res {
             let fds: Vec<libc::pollfd> = self
                 .inner
                 .iter()
-                .map(|fd| libc::pollfd {
-                    fd: fd.borrow().as_raw_fd(),
-                    events: fd.borrow().events().bits(),
-                    revents: 0,
+                .map(|fd| {
+                    let fd = fd.borrow();
+                    // POLLHUP и POLLERR приходят даже без запрошенных событий,
+                    // отрицательный fd poll пропускает целиком
+                    let raw_fd = if fd.events().is_empty() {
+                        -1
+                    } else {
+                        fd.as_raw_fd()
+                    };
+
+                    libc::pollfd {
+                        fd: raw_fd,
+                        events: fd.events().bits(),
+                        revents: 0,
+                    }
                 })
                 .collect();
 
@@ -156,10 +251,12 @@ impl Fds {
 
     fn _push_fd(&mut self, new_fd: Fd) {
         self.inner.push(RefCell::new(new_fd));
+        self.stats.get_mut().push(FdStats::default());
         self.pollfds = RefCell::new(None); // Обнуляем кэш, чтобы пересоздать его позже
     }
 
     /// Добавляет новый файловый дескриптор в список файловых дескрипторов.
+    #[allow(dead_code)]
     pub fn push_fd(&mut self, new_fd: Fd) {
         match new_fd {
             Fd::Signal { .. } => self._push_fd(new_fd),
@@ -167,23 +264,33 @@ impl Fds {
             Fd::Stdout { .. } => self._push_fd(new_fd),
             Fd::PtyMaster { .. } => self._push_fd(new_fd),
             Fd::PtySlave { .. } => self._push_fd(new_fd),
+            Fd::ChildStderr { .. } => self._push_fd(new_fd),
+            Fd::PasswordResponse { .. } => self._push_fd(new_fd),
+            Fd::AttachListener { .. } => self._push_fd(new_fd),
+            Fd::AttachClient { .. } => self._push_fd(new_fd),
         }
     }
 
     /// Добавляет дескриптор pty (master и slave дестрикторы) в список файловых дскрипторов
-    pub fn push_pty_fd(&mut self, pty_fd: OpenptyResult, child: Pid, events: PollFlags) {
+    pub fn push_pty_fd(
+        &mut self,
+        pty_fd: OpenptyResult,
+        child: Pid,
+        events: PollFlags,
+        queue: WriteQueue,
+    ) {
         self._push_fd(Fd::PtyMaster {
             fd: pty_fd.master,
             events,
             child,
+            queue,
         });
         self.pty_master_index = Some(self.inner.len() - 1);
 
-        self._push_fd(Fd::PtySlave {
-            fd: pty_fd.slave,
-            events,
-        });
-        self.pty_master_index = Some(self.inner.len() - 1);
+        // slave нужен только дочернему процессу, родитель свою копию закрывает:
+        // когда slave закроют все потомки, чтение master вернет EIO и станет понятно,
+        // что весь вывод дочернего процесса прочитан
+        drop(pty_fd.slave);
     }
 
     /// Добавляет дескриптор сигнала в список файловых дескрипторов
@@ -196,25 +303,64 @@ impl Fds {
     }
 
     /// Добавляет дескриптор stdout в список файловых дескрипторов
-    pub fn push_stdout_fd(&mut self, stdout: Stdout, events: PollFlags) {
-        self._push_fd(Fd::Stdout { fd: stdout, events });
+    pub fn push_stdout_fd(&mut self, stdout: Stdout, events: PollFlags, queue: WriteQueue) {
+        self._push_fd(Fd::Stdout {
+            fd: stdout,
+            events,
+            queue,
+        });
         self.stdout_index = Some(self.inner.len() - 1);
     }
 
-    /// Добавляет дескриптор stdin в список файловых дескрипторов
-    pub fn push_stdin_fd(&mut self, stdin: Stdin, termios: Termios, events: PollFlags) {
-        self._push_fd(Fd::Stdin {
-            fd: stdin,
-            termios,
-            events,
+    /// Добавляет pipe stderr дочернего процесса в список файловых дескрипторов
+    pub fn push_child_stderr_fd(&mut self, stderr: OwnedFd, events: PollFlags) {
+        self._push_fd(Fd::ChildStderr { fd: stderr, events });
+        self.child_stderr_index = Some(self.inner.len() - 1);
+    }
+
+    /// Добавляет дескриптор ответа с паролем в список файловых дескрипторов
+    pub fn push_password_response_fd(&mut self, fd: OwnedFd, events: PollFlags) {
+        self._push_fd(Fd::PasswordResponse { fd, events });
+        self.password_response_index = Some(self.inner.len() - 1);
+    }
+
+    /// Добавляет сокет --attach-socket и clients свободных слотов для подключений
+    pub fn push_attach_fds(
+        &mut self,
+        listener: UnixListener,
+        path: PathBuf,
+        clients: usize,
+        queue: impl Fn() -> WriteQueue,
+    ) {
+        self._push_fd(Fd::AttachListener {
+            fd: listener,
+            events: PollFlags::POLLIN,
+            path,
         });
+        self.attach_listener_index = Some(self.inner.len() - 1);
+
+        for _ in 0..clients {
+            self._push_fd(Fd::AttachClient {
+                fd: None,
+                events: PollFlags::empty(),
+                queue: queue(),
+            });
+            self.attach_client_indexes.push(self.inner.len() - 1);
+        }
+    }
+
+    /// Добавляет дескриптор stdin в список файловых дескрипторов
+    pub fn push_stdin_fd(&mut self, stdin: Stdin, events: PollFlags) {
+        self._push_fd(Fd::Stdin { fd: stdin, events });
         self.stdin_index = Some(self.inner.len() - 1);
     }
 
     /// Удаляет последний файловый дескриптор из списка файловых дескрипторов
     /// Если список файловых дескрипторов пуст, то ничего не делает
+    #[allow(dead_code)]
     pub fn pop_fd(&mut self) {
         let res = self.inner.pop();
+        self.stats.get_mut().pop();
 
         if let Some(fd) = res {
             match *fd.borrow() {
@@ -233,49 +379,349 @@ impl Fds {
                 Fd::PtySlave { .. } => {
                     self.pty_slave_index = None;
                 }
+                Fd::ChildStderr { .. } => {
+                    self.child_stderr_index = None;
+                }
+                Fd::PasswordResponse { .. } => {
+                    self.password_response_index = None;
+                }
+                Fd::AttachListener { .. } => {
+                    self.attach_listener_index = None;
+                }
+                Fd::AttachClient { .. } => {
+                    self.attach_client_indexes.pop();
+                }
             }
 
             self.pollfds = RefCell::new(None);
         }
     }
 
-    pub fn send_to(&self, index: usize, buf: &Ref<[u8]>) {
+    /// Меняет набор событий, которые poll отслеживает для дескриптора
+    pub fn set_events(&self, index: usize, events: PollFlags) {
+        if let Some(fd) = self.inner.get(index) {
+            fd.borrow_mut().set_events(events);
+            self.pollfds.replace(None);
+        }
+    }
+
+    /// Перестает опрашивать дескриптор, например после EOF
+    pub fn close(&self, index: usize) {
+        self.closed.borrow_mut().push(index);
+        self.set_events(index, PollFlags::empty());
+    }
+
+    pub fn close_stdin(&self) {
+        if let Some(index) = self.stdin_index {
+            self.close(index);
+        }
+    }
+
+    pub fn close_pty_master(&self) {
+        if let Some(index) = self.pty_master_index {
+            self.close(index);
+        }
+    }
+
+    pub fn close_child_stderr(&self) {
+        if let Some(index) = self.child_stderr_index {
+            self.close(index);
+        }
+    }
+
+    /// Начинает опрашивать дескриптор ответа с паролем
+    pub fn listen_password_response(&self) {
+        if let Some(index) = self.password_response_index {
+            if !self.closed.borrow().contains(&index) {
+                self.set_events(index, PollFlags::POLLIN);
+            }
+        }
+    }
+
+    pub fn close_password_response(&self) {
+        if let Some(index) = self.password_response_index {
+            self.close(index);
+        }
+    }
+
+    /// Занимает свободный слот клиентом --attach-socket, возвращает индекс слота
+    /// Если свободных слотов нет, клиент возвращается обратно
+    pub fn add_attach_client(&self, stream: UnixStream) -> Result<usize, UnixStream> {
+        let free = self.attach_client_indexes.iter().copied().find(|index| {
+            matches!(&*self.inner[*index].borrow(), Fd::AttachClient { fd: None, .. })
+        });
+        let Some(index) = free else {
+            return Err(stream);
+        };
+
+        if let Fd::AttachClient { fd, queue, .. } = &mut *self.inner[index].borrow_mut() {
+            queue.clear();
+            *fd = Some(stream);
+        }
+        self.closed.borrow_mut().retain(|closed| *closed != index);
+        self.set_events(index, PollFlags::POLLIN);
+
+        Ok(index)
+    }
+
+    /// Отключает клиента --attach-socket, слот освобождается
+    pub fn close_attach_client(&self, index: usize) {
+        if let Fd::AttachClient { fd, .. } = &mut *self.inner[index].borrow_mut() {
+            *fd = None;
+        }
+        self.close(index);
+    }
+
+    /// Вывод программы всем подключенным клиентам --attach-socket, кусок общий для всех очередей
+    pub fn write_to_attached(&self, chunk: &Bytes) {
+        for index in self.attach_client_indexes.iter().copied() {
+            if !matches!(&*self.inner[index].borrow(), Fd::AttachClient { fd: Some(_), .. }) {
+                continue;
+            }
+            self.send_to(index, chunk.clone());
+        }
+    }
+
+    pub fn send_to(&self, index: usize, buf: Bytes) {
         if let Some(fd) = self.inner.get(index) {
-            let mut res = fd.borrow_mut();
-            let res = res.deref_mut();
-            let res = match res {
+            let len = buf.len();
+            let res = match fd.borrow_mut().deref_mut() {
                 Fd::Signal { fd, .. } => {
                     error!("attempt to send a message to signalfd. this is not possible because signalfd can only be read");
-                    write(fd, buf.borrow())
+                    write_all_fd(fd, &buf)
                 }
                 Fd::Stdin { fd, .. } => {
                     error!("attempt to send a message to signalfd. this is not possible because signalfd can only be read");
-                    write(fd, buf.borrow())
+                    write_all_fd(fd, &buf)
                 }
-                Fd::Stdout { fd, .. } => write(fd, buf.borrow()),
-                Fd::PtyMaster { fd, .. } => write(&fd, buf.borrow()),
-                Fd::PtySlave { fd, .. } => write(&fd, buf.borrow()),
+                // stdout и pty пишутся через очередь, то что не влезло допишется по POLLOUT
+                Fd::Stdout { fd, queue, .. } => {
+                    queue.push(buf);
+                    queue.flush(fd)
+                }
+                Fd::PtyMaster { fd, queue, .. } => {
+                    queue.push(buf);
+                    queue.flush(fd)
+                }
+                Fd::PtySlave { fd, .. } => write_all_fd(fd, &buf),
+                Fd::ChildStderr { fd, .. } => {
+                    error!("attempt to send a message to the read end of the child stderr pipe");
+                    write_all_fd(fd, &buf)
+                }
+                Fd::PasswordResponse { fd, .. } => {
+                    error!("attempt to send a message to the password response fd");
+                    write_all_fd(fd, &buf)
+                }
+                Fd::AttachListener { .. } => {
+                    error!("attempt to send a message to the attach listener");
+                    return;
+                }
+                Fd::AttachClient { fd: Some(fd), queue, .. } => {
+                    queue.push(buf);
+                    queue.flush(&*fd)
+                }
+                Fd::AttachClient { fd: None, .. } => return,
+            };
+
+            // отключившийся клиент --attach-socket не ошибка сессии
+            let broken = matches!(res, WriteResult::BrokenPipe | WriteResult::Fatal(_));
+            if broken && self.attach_client_indexes.contains(&index) {
+                trace!("attach client {} gone", index);
+                self.close_attach_client(index);
+                return;
+            }
+
+            Self::log_write_result(&res, len);
+            self.record_write(index, &res);
+            self.update_write_events(index);
+        }
+    }
+
+    /// Переносит вывод из from прямо в stdout через splice, минуя буфер и очередь.
+    /// None - stdout закрыт или в его очереди есть данные: сначала должна дописаться
+    /// очередь, иначе вывод перемешается
+    pub fn splice_to_stdout(&self, from: BorrowedFd<'_>, len: usize) -> Option<nix::Result<usize>> {
+        let index = self.stdout_index?;
+        let res = match &*self.inner[index].borrow() {
+            Fd::Stdout { fd, queue, .. } if queue.is_empty() => splice_fd(from, fd, len),
+            _ => return None,
+        };
+
+        if let Ok(n) = res {
+            self.record_write(index, &WriteResult::Done(n));
+        }
+        Some(res)
+    }
+
+    /// Дописывает очередь дескриптора, вызывается когда poll вернул POLLOUT
+    pub fn flush(&self, index: usize) {
+        if let Some(fd) = self.inner.get(index) {
+            let res = match fd.borrow_mut().deref_mut() {
+                Fd::Stdout { fd, queue, .. } => queue.flush(fd),
+                Fd::PtyMaster { fd, queue, .. } => queue.flush(fd),
+                Fd::AttachClient { fd: Some(fd), queue, .. } => queue.flush(&*fd),
+                _ => return,
             };
 
-            if let Err(e) = res {
+            Self::log_write_result(&res, 0);
+            self.record_write(index, &res);
+            self.update_write_events(index);
+        }
+    }
+
+    /// Есть ли данные, которые еще не записаны
+    pub fn has_pending_writes(&self) -> bool {
+        self.inner.iter().any(|fd| match &*fd.borrow() {
+            Fd::Stdout { queue, .. } => !queue.is_empty(),
+            Fd::PtyMaster { queue, .. } => !queue.is_empty(),
+            _ => false,
+        })
+    }
+
+    pub fn record_event(&self, index: usize) {
+        if let Some(stats) = self.stats.borrow_mut().get_mut(index) {
+            stats.record_event();
+        }
+    }
+
+    pub fn record_read(&self, index: usize, res: &nix::Result<usize>) {
+        if let Some(stats) = self.stats.borrow_mut().get_mut(index) {
+            stats.record_read(res);
+        }
+    }
+
+    fn record_write(&self, index: usize, res: &WriteResult) {
+        if let Some(stats) = self.stats.borrow_mut().get_mut(index) {
+            stats.record_write(res);
+        }
+    }
+
+    /// Снимок счетчиков всех дескрипторов
+    pub fn snapshot(&self) -> Vec<FdReport> {
+        let stats = self.stats.borrow();
+        self.inner
+            .iter()
+            .zip(stats.iter())
+            .enumerate()
+            .map(|(index, (fd, stats))| {
+                let fd = fd.borrow();
+                FdReport::new(
+                    index,
+                    fd.kind(),
+                    fd.as_raw_fd(),
+                    format!("{:?}", fd.events()),
+                    stats,
+                )
+            })
+            .collect()
+    }
+
+    /// Проверка перед запуском дочернего процесса: ни один дескриптор sshpass не должен
+    /// пережить exec. Найденные без FD_CLOEXEC записываются в лог и исправляются.
+    /// 0, 1 и 2 наследуются намеренно и не проверяются
+    pub fn audit_cloexec(&self) -> Vec<(RawFd, &'static str)> {
+        let mut leaked = vec![];
+        for fd in self.inner.iter() {
+            let fd = fd.borrow();
+            let raw = fd.as_raw_fd();
+            if raw <= libc::STDERR_FILENO {
+                continue;
+            }
+
+            match ensure_cloexec(raw) {
+                Ok(true) => {
+                    warn!("fd {} ({}) was inheritable, FD_CLOEXEC set", raw, fd.kind());
+                    leaked.push((raw, fd.kind()));
+                }
+                Ok(false) => {}
+                Err(e) => error!("fd {} ({}): failed to check FD_CLOEXEC: {}", raw, fd.kind(), e),
+            }
+        }
+
+        leaked
+    }
+
+    fn log_write_result(res: &WriteResult, len: usize) {
+        match res {
+            WriteResult::Done(_) => {}
+            WriteResult::PartialWrite { written } => {
+                trace!("fd is not ready, {} bytes queued", len.saturating_sub(*written));
+            }
+            WriteResult::WouldBlock => {
+                error!("fd is not ready, {} bytes were dropped", len);
+            }
+            WriteResult::BrokenPipe => {
+                error!("error while sending message to fd: the other side is closed");
+            }
+            WriteResult::Interrupted => {}
+            WriteResult::Fatal(e) => {
                 error!("error while sending message to fd: {}", e);
             }
         }
     }
 
-    pub fn write_to_stdout(&self, buf: &Ref<[u8]>) {
+    /// POLLOUT нужен только пока очередь не пуста
+    /// Переполненная очередь приостанавливает чтение своего источника:
+    /// для stdout это вывод pty, для pty это stdin
+    fn update_write_events(&self, index: usize) {
+        let Some(fd) = self.inner.get(index) else {
+            return;
+        };
+
+        let (pending, pressure, source) = match fd.borrow_mut().deref_mut() {
+            Fd::Stdout { queue, .. } => (!queue.is_empty(), queue.pressure(), self.pty_master_index),
+            Fd::PtyMaster { queue, .. } => (!queue.is_empty(), queue.pressure(), self.stdin_index),
+            Fd::AttachClient { fd: Some(_), queue, .. } => {
+                (!queue.is_empty(), queue.pressure(), None)
+            }
+            _ => return,
+        };
+
+        // клиент, который не успевает читать, отключается: сессия его не ждет
+        if pressure == Some(true) && self.attach_client_indexes.contains(&index) {
+            warn!("attach client {} is too slow, disconnected", index);
+            self.close_attach_client(index);
+            return;
+        }
+        let source = source.filter(|source| !self.closed.borrow().contains(source));
+
+        self.update_events(index, |events| events.difference(PollFlags::POLLOUT));
+        if pending {
+            self.update_events(index, |events| events.union(PollFlags::POLLOUT));
+        }
+
+        if let (Some(paused), Some(source)) = (pressure, source) {
+            trace!("backpressure on fd index {}: pause source {} = {}", index, source, paused);
+            self.update_events(source, |events| {
+                if paused {
+                    events.difference(PollFlags::POLLIN)
+                } else {
+                    events.union(PollFlags::POLLIN)
+                }
+            });
+        }
+    }
+
+    fn update_events(&self, index: usize, f: impl FnOnce(PollFlags) -> PollFlags) {
+        if let Some(fd) = self.inner.get(index) {
+            let events = f(*fd.borrow().events());
+            self.set_events(index, events);
+        }
+    }
+
+    pub fn write_to_stdout(&self, buf: Bytes) {
         if let Some(index) = self.stdout_index {
             self.send_to(index, buf);
         }
     }
 
-    pub fn write_to_stdin(&self, buf: &Ref<[u8]>) {
+    pub fn write_to_stdin(&self, buf: Bytes) {
         if let Some(index) = self.stdin_index {
             self.send_to(index, buf);
         }
     }
 
-    pub fn write_to_pty_master(&self, buf: &Ref<[u8]>) {
+    pub fn write_to_pty_master(&self, buf: Bytes) {
         if let Some(index) = self.pty_master_index {
             self.send_to(index, buf);
         }
@@ -286,6 +732,8 @@ impl Fds {
 pub struct Poller {
     pub fds: Fds,
     pub poll_timeout: PollTimeout,
+    /// с какого дескриптора начинать поиск готовых событий
+    next_index: Cell<usize>,
 }
 
 /// Итератор по событиям, возвращаемым poll
@@ -293,20 +741,25 @@ pub struct Poller {
 /// А именно те, у которых revent != 0
 /// Важно! после того как событие будет найдено поле revents будет обнулено
 /// Это достигается за счет RefCell
+/// Обход начинается с дескриптора, следующего за последним отданным, и идет по кругу:
+/// за один вызов читается не больше буфера, поэтому дескриптор, который готов всегда
+/// (например pty, в который дочерний процесс льет гигабайты), не может занять цикл целиком
 #[derive(Debug)]
 pub struct PollReventIterator<'a> {
     fds: &'a Fds,
-    index: usize,
+    next_index: &'a Cell<usize>,
+    start: usize,
+    checked: usize,
 }
 
 impl<'a> Iterator for PollReventIterator<'a> {
-    type Item = (Ref<'a, Fd>, usize);
+    type Item = (Ref<'a, Fd>, usize, PollFlags);
 
     fn next(&mut self) -> Option<Self::Item> {
         let len = self.fds.len();
-        while self.index < len {
-            let index = self.index;
-            self.index += 1;
+        while self.checked < len {
+            let index = (self.start + self.checked) % len;
+            self.checked += 1;
 
             let fd = self.fds.get_fd_by_index(index).unwrap();
             let fd = fd.borrow();
@@ -316,8 +769,11 @@ impl<'a> Iterator for PollReventIterator<'a> {
 
             if let Some(res) = res {
                 if res.revents != 0 {
+                    let revents = PollFlags::from_bits_truncate(res.revents);
                     res.revents = 0;
-                    return Some((fd, index));
+                    self.fds.record_event(index);
+                    self.next_index.set(index + 1);
+                    return Some((fd, index, revents));
                 }
             }
         }
@@ -353,6 +809,7 @@ impl Poller {
         Self {
             fds: Fds::new(),
             poll_timeout,
+            next_index: Cell::new(0),
         }
     }
 
@@ -368,17 +825,35 @@ impl Poller {
         nix::errno::Errno::result(res)
     }
 
-    pub fn revent_iter(&self) -> PollReventIterator {
+    pub fn revent_iter(&self) -> PollReventIterator<'_> {
         PollReventIterator {
             fds: &self.fds,
-            index: 0,
+            next_index: &self.next_index,
+            start: self.next_index.get(),
+            checked: 0,
         }
     }
 
-    pub fn iter(&self) -> FdsIterator {
+    /// Снимок счетчиков по каждому дескриптору, пригоден для сериализации
+    pub fn snapshot(&self) -> Vec<FdReport> {
+        self.fds.snapshot()
+    }
+
+    pub fn iter(&self) -> FdsIterator<'_> {
         FdsIterator {
             poller: self,
             index: 0,
         }
     }
 }
+
+/// Ставит FD_CLOEXEC, true если флага не было
+pub fn ensure_cloexec(fd: RawFd) -> nix::Result<bool> {
+    let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+    if flags.contains(FdFlag::FD_CLOEXEC) {
+        return Ok(false);
+    }
+
+    fcntl(fd, FcntlArg::F_SETFD(flags | FdFlag::FD_CLOEXEC))?;
+    Ok(true)
+}
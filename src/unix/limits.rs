@@ -0,0 +1,200 @@
+use std::fmt;
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use nix::sys::resource::{getrlimit, rlim_t, setrlimit, Resource, RLIM_INFINITY};
+
+/// Ограничения, которые можно задать через --rlimit и --child-rlimit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitResource {
+    /// RLIMIT_NOFILE - число открытых дескрипторов
+    NoFile,
+    /// RLIMIT_CORE - размер core dump в байтах
+    Core,
+    /// RLIMIT_AS - размер адресного пространства в байтах
+    As,
+}
+
+impl LimitResource {
+    fn resource(self) -> Resource {
+        match self {
+            LimitResource::NoFile => Resource::RLIMIT_NOFILE,
+            LimitResource::Core => Resource::RLIMIT_CORE,
+            LimitResource::As => Resource::RLIMIT_AS,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LimitResource::NoFile => "nofile",
+            LimitResource::Core => "core",
+            LimitResource::As => "as",
+        }
+    }
+}
+
+/// Лимит в виде RESOURCE=SOFT[:HARD]
+/// Без HARD жесткий лимит остается прежним, значения - число с суффиксом K, M, G или unlimited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RLimit {
+    pub resource: LimitResource,
+    pub soft: rlim_t,
+    pub hard: Option<rlim_t>,
+}
+
+impl FromStr for RLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((resource, value)) = s.split_once('=') else {
+            return Err(format!("expected RESOURCE=SOFT[:HARD], got '{}'", s));
+        };
+
+        let resource = match resource.to_ascii_lowercase().as_str() {
+            "nofile" => LimitResource::NoFile,
+            "core" => LimitResource::Core,
+            "as" => LimitResource::As,
+            _ => {
+                return Err(format!(
+                    "unknown resource '{}', expected nofile, core or as",
+                    resource
+                ))
+            }
+        };
+
+        let (soft, hard) = match value.split_once(':') {
+            Some((soft, hard)) => (parse_value(soft)?, Some(parse_value(hard)?)),
+            None => (parse_value(value)?, None),
+        };
+        if hard.is_some_and(|hard| soft > hard) {
+            return Err(format!("soft limit exceeds hard limit in '{}'", s));
+        }
+
+        Ok(Self {
+            resource,
+            soft,
+            hard,
+        })
+    }
+}
+
+fn parse_value(s: &str) -> Result<rlim_t, String> {
+    if s == "unlimited" {
+        return Ok(RLIM_INFINITY);
+    }
+
+    let (digits, unit) = match s.as_bytes().last() {
+        Some(b'K' | b'k') => (&s[..s.len() - 1], 1 << 10),
+        Some(b'M' | b'm') => (&s[..s.len() - 1], 1 << 20),
+        Some(b'G' | b'g') => (&s[..s.len() - 1], 1 << 30),
+        _ => (s, 1),
+    };
+
+    digits
+        .parse::<rlim_t>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .ok_or_else(|| format!("invalid limit value '{}'", s))
+}
+
+fn format_value(value: rlim_t) -> String {
+    if value == RLIM_INFINITY {
+        "unlimited".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Какой лимит не удалось применить
+#[derive(Debug)]
+pub struct LimitError {
+    pub limit: RLimit,
+    /// жесткий лимит, который действовал на момент ошибки
+    pub current_hard: rlim_t,
+    pub errno: Errno,
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hard = self.limit.hard.unwrap_or(self.current_hard);
+        write!(
+            f,
+            "failed to set {} limit to {}:{}",
+            self.limit.resource.name(),
+            format_value(self.limit.soft),
+            format_value(hard)
+        )?;
+
+        // поднять жесткий лимит может только CAP_SYS_RESOURCE, мягкий не выше жесткого
+        if hard > self.current_hard || self.limit.soft > self.current_hard {
+            write!(f, " (hard limit is {})", format_value(self.current_hard))?;
+        }
+
+        write!(f, ": {}", self.errno.desc())
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+impl RLimit {
+    /// Применяет лимит к текущему процессу
+    /// Не выделяет память, поэтому годится и для дочернего процесса после fork
+    pub fn apply(&self) -> Result<(), LimitError> {
+        let resource = self.resource.resource();
+        let (_, current_hard) = getrlimit(resource).map_err(|errno| LimitError {
+            limit: *self,
+            current_hard: RLIM_INFINITY,
+            errno,
+        })?;
+
+        let hard = self.hard.unwrap_or(current_hard);
+        setrlimit(resource, self.soft, hard).map_err(|errno| LimitError {
+            limit: *self,
+            current_hard,
+            errno,
+        })
+    }
+}
+
+/// Применяет лимиты по порядку, первая ошибка прерывает применение
+pub fn apply_limits(limits: &[RLimit]) -> Result<(), LimitError> {
+    limits.iter().try_for_each(RLimit::apply)
+}
+
+/// Лимиты sshpass и дочернего процесса с отключенными core dump для sshpass
+/// В памяти sshpass лежит пароль, поэтому без явного --rlimit core дампы отключаются.
+/// Дочерний процесс получает исходный мягкий лимит, если для него не задан свой
+pub fn without_core_dumps(
+    limits: &[RLimit],
+    child_limits: &[RLimit],
+) -> (Vec<RLimit>, Vec<RLimit>) {
+    let mut limits = limits.to_vec();
+    let mut child_limits = child_limits.to_vec();
+    let has_core = |limits: &[RLimit]| limits.iter().any(|l| l.resource == LimitResource::Core);
+
+    if !has_core(&limits) {
+        if !has_core(&child_limits) {
+            if let Ok((soft, _)) = getrlimit(Resource::RLIMIT_CORE) {
+                child_limits.insert(
+                    0,
+                    RLimit {
+                        resource: LimitResource::Core,
+                        soft,
+                        hard: None,
+                    },
+                );
+            }
+        }
+
+        limits.insert(
+            0,
+            RLimit {
+                resource: LimitResource::Core,
+                soft: 0,
+                hard: None,
+            },
+        );
+    }
+
+    (limits, child_limits)
+}
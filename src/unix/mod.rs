@@ -1,8 +1,26 @@
+mod child_env;
+mod child_spawn;
+mod child_usage;
+mod fd_stats;
 mod fds;
+mod limits;
+mod sched;
+mod terminal;
+mod terminal_guard;
 mod unix_app;
 mod unix_error;
 mod unix_event;
+mod unix_stop;
+mod write_fd;
+mod write_queue;
 
-pub use unix_app::{UnixApp, UnixAppStop};
+pub use child_env::{ChildEnv, DEFAULT_ENV_REMOVE};
+pub use child_spawn::{close_inherited_fds, ChildSpawnOptions, ChildUser};
+pub use child_usage::ChildUsage;
+pub use limits::{apply_limits, without_core_dumps, RLimit};
+pub use sched::{CpuList, IoPriority, SchedPolicy};
+pub use terminal::TerminalInfo;
+pub use unix_app::UnixApp;
 pub use unix_error::UnixError;
 pub use unix_event::UnixEvent;
+pub use unix_stop::{StopStage, StopState, UnixAppStop};
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use nix::libc;
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+/// Класс планировщика ввода-вывода для ioprio_set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+/// Приоритет ввода-вывода, как у ionice: класс и уровень 0..=7 (у idle уровня нет)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: IoClass,
+    pub level: u8,
+}
+
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+
+impl IoPriority {
+    fn value(&self) -> libc::c_int {
+        let class = match self.class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        (class << IOPRIO_CLASS_SHIFT) | self.level as libc::c_int
+    }
+}
+
+/// CLASS[:LEVEL], CLASS - realtime|best-effort|idle или 1|2|3 как у ionice -c
+impl FromStr for IoPriority {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, level) = match s.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (s, None),
+        };
+
+        let class = match class {
+            "realtime" | "1" => IoClass::Realtime,
+            "best-effort" | "2" => IoClass::BestEffort,
+            "idle" | "3" => IoClass::Idle,
+            _ => {
+                return Err(format!(
+                    "unknown io class '{}', expected realtime, best-effort or idle",
+                    class
+                ))
+            }
+        };
+
+        let level = match (class, level) {
+            (IoClass::Idle, Some(_)) => return Err("idle io class has no level".to_owned()),
+            (IoClass::Idle, None) => 0,
+            (_, None) => 4,
+            (_, Some(level)) => match level.parse::<u8>() {
+                Ok(level) if level <= 7 => level,
+                _ => return Err(format!("io level must be 0..7, got '{}'", level)),
+            },
+        };
+
+        Ok(Self { class, level })
+    }
+}
+
+/// Список процессоров для sched_setaffinity: "0-3,6"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuList(CpuSet);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut set = CpuSet::new();
+
+        for item in s.split(',') {
+            let (first, last) = match item.split_once('-') {
+                Some((first, last)) => (first, last),
+                None => (item, item),
+            };
+            let (Ok(first), Ok(last)) =
+                (first.trim().parse::<usize>(), last.trim().parse::<usize>())
+            else {
+                return Err(format!("expected a cpu list like 0-3,6, got '{}'", s));
+            };
+            if first > last {
+                return Err(format!("invalid cpu range '{}'", item));
+            }
+
+            for cpu in first..=last {
+                set.set(cpu).map_err(|_| {
+                    format!("cpu {} is out of range (max {})", cpu, CpuSet::count() - 1)
+                })?;
+            }
+        }
+
+        Ok(Self(set))
+    }
+}
+
+/// Что применить к процессу: привязка к процессорам, nice и приоритет ввода-вывода
+/// Пустые поля не трогаются, процесс оставляет унаследованные значения
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SchedPolicy {
+    pub cpus: Option<CpuList>,
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+}
+
+/// Какую настройку не удалось применить и почему
+#[derive(Debug)]
+pub struct SchedError {
+    pub setting: &'static str,
+    pub source: io::Error,
+}
+
+impl fmt::Display for SchedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to set {}: {}", self.setting, self.source)
+    }
+}
+
+impl std::error::Error for SchedError {}
+
+impl SchedPolicy {
+    /// Применяет настройки к текущему процессу
+    /// Не выделяет память, поэтому годится и для дочернего процесса после fork
+    pub fn apply(&self) -> Result<(), SchedError> {
+        if let Some(CpuList(set)) = &self.cpus {
+            sched_setaffinity(Pid::from_raw(0), set).map_err(|e| SchedError {
+                setting: "cpu affinity",
+                source: e.into(),
+            })?;
+        }
+
+        if let Some(nice) = self.nice {
+            // nice задается абсолютным значением, уменьшение требует CAP_SYS_NICE
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } < 0 {
+                return Err(SchedError {
+                    setting: "nice",
+                    source: io::Error::last_os_error(),
+                });
+            }
+        }
+
+        if let Some(ionice) = self.ionice {
+            let res = unsafe {
+                libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ionice.value())
+            };
+            if res < 0 {
+                return Err(SchedError {
+                    setting: "io priority",
+                    source: io::Error::last_os_error(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
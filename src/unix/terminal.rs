@@ -0,0 +1,54 @@
+use std::fs::OpenOptions;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+
+use nix::libc;
+use nix::sys::stat::{fstat, SFlag};
+use nix::unistd::isatty;
+use serde::Serialize;
+
+/// Что известно о терминале, в котором запущен sshpass
+/// В минимальных контейнерах и под cron терминала может не быть совсем
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TerminalInfo {
+    pub stdin_tty: bool,
+    pub stdout_tty: bool,
+    /// удалось открыть /dev/tty, то есть у процесса есть управляющий терминал
+    pub controlling_tty: bool,
+    /// stdout - pipe, в него можно переносить вывод через splice (--splice-output)
+    pub stdout_pipe: bool,
+}
+
+impl TerminalInfo {
+    pub fn detect() -> Self {
+        let controlling_tty = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open("/dev/tty")
+            .is_ok();
+
+        Self {
+            stdin_tty: isatty(std::io::stdin().as_raw_fd()).unwrap_or(false),
+            stdout_tty: isatty(std::io::stdout().as_raw_fd()).unwrap_or(false),
+            controlling_tty,
+            stdout_pipe: is_pipe(std::io::stdout().as_raw_fd()),
+        }
+    }
+
+    /// Можно ли переводить stdin в неканонический режим
+    pub fn raw_mode(&self) -> bool {
+        self.stdin_tty
+    }
+
+    /// Есть ли человек, который может сам ответить на запрос в дочернем процессе
+    pub fn interactive(&self) -> bool {
+        self.stdin_tty && self.controlling_tty
+    }
+}
+
+fn is_pipe(fd: i32) -> bool {
+    fstat(fd).is_ok_and(|st| {
+        SFlag::from_bits_truncate(st.st_mode) & SFlag::S_IFMT == SFlag::S_IFIFO
+    })
+}
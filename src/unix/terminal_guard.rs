@@ -0,0 +1,71 @@
+use std::os::fd::RawFd;
+use std::sync::{Mutex, Once};
+
+use log::trace;
+use nix::libc;
+use termios::{tcsetattr, Termios, TCSANOW};
+
+/// Исходные настройки терминала, которые нужно вернуть при выходе
+/// Хранятся глобально, чтобы до них могли добраться panic hook и atexit
+static SAVED: Mutex<Option<(RawFd, Termios)>> = Mutex::new(None);
+
+static HOOKS: Once = Once::new();
+
+/// Возвращает терминал в исходное состояние при drop
+/// Дополнительно ставит panic hook и atexit обработчик: process::exit не вызывает drop,
+/// а паника может случиться там, где UnixApp уже не будет корректно разрушен
+#[derive(Debug)]
+pub struct TerminalGuard {
+    _private: (),
+}
+
+impl TerminalGuard {
+    /// Запоминает настройки терминала fd до их изменения
+    pub fn save(fd: RawFd, termios: Termios) -> Self {
+        *SAVED.lock().unwrap_or_else(|e| e.into_inner()) = Some((fd, termios));
+
+        HOOKS.call_once(|| {
+            let prev = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                restore();
+                prev(info);
+            }));
+
+            unsafe { libc::atexit(restore_at_exit) };
+        });
+
+        Self { _private: () }
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore();
+    }
+}
+
+/// Восстанавливает сохраненные настройки, повторный вызов ничего не делает
+pub fn restore() {
+    // из panic hook мьютекс может оказаться отравлен, настройки при этом целы
+    let saved = SAVED.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+    if let Some((fd, termios)) = saved {
+        let res = tcsetattr(fd, TCSANOW, &termios);
+        trace!("termios restore: {:?}", res);
+    }
+}
+
+/// Возвращает сохраненные настройки на время остановки (SIGTSTP), не забывая их:
+/// после SIGCONT терминал снова переводится в сырой режим, а при выходе восстанавливается
+pub fn restore_for_suspend() {
+    let saved = SAVED.lock().unwrap_or_else(|e| e.into_inner());
+
+    if let Some((fd, termios)) = saved.as_ref() {
+        let res = tcsetattr(*fd, TCSANOW, termios);
+        trace!("termios restore for suspend: {:?}", res);
+    }
+}
+
+extern "C" fn restore_at_exit() {
+    restore();
+}
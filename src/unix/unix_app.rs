@@ -1,20 +1,25 @@
-use std::borrow::{Borrow, BorrowMut};
 use std::boxed::Box;
-use std::cell::{Ref, RefCell};
+use std::cell::{Cell, RefCell};
 use std::io::Stdin;
-use std::os::fd::{OwnedFd, RawFd};
+use std::os::fd::{AsFd, OwnedFd, RawFd};
 use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
 use std::os::unix::process::CommandExt;
+use std::path::Path;
 use std::process::Stdio;
-use std::time::Instant;
 
+use bytes::Bytes;
+use nix::errno::Errno;
 use nix::errno::Errno::EAGAIN;
-use nix::pty::{openpty, OpenptyResult};
-use nix::sys::signal::{SigSet, Signal};
+use nix::pty::openpty;
+use nix::sys::signal::{killpg, raise, SigSet, Signal};
 use nix::sys::signalfd::{siginfo, SfdFlags, SignalFd};
-use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::sys::stat::{umask, Mode};
+use nix::libc;
+use nix::sys::wait::{WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use nix::unistd::{fork, ForkResult};
+use nix::fcntl::OFlag;
+use nix::unistd::{fork, getpgrp, getsid, pipe2, tcgetpgrp, ForkResult};
 use nix::{
     poll::{PollFlags, PollTimeout},
     unistd::read,
@@ -23,16 +28,35 @@ use nix::{
 use termios::Termios;
 use termios::{
     tcsetattr, BRKINT, CS8, CSIZE, ECHO, ECHONL, ICANON, ICRNL, IEXTEN, IGNBRK, IGNCR, INLCR, ISIG,
-    ISTRIP, IXON, OPOST, PARENB, PARMRK, TCSANOW, VMIN, VTIME,
+    ISTRIP, IXON, OPOST, PARENB, PARMRK, TCSANOW, VEOF, VMIN, VTIME,
 };
 
-use clap::parser::ValuesRef;
-use clap::ArgMatches;
-use log::{error, trace};
+use log::{error, trace, warn};
 
-use crate::unix::fds::{Fd, Poller};
+use crate::exit_code::EXIT_RUNTIME_ERROR;
+use crate::unix::child_spawn::{
+    attach_controlling_terminal, close_inherited_fds, ChildSpawnOptions,
+};
+use crate::unix::child_usage::ChildUsage;
+use crate::unix::fd_stats::FdReport;
+use crate::unix::fds::{ensure_cloexec, Fd, Poller};
+use crate::unix::terminal_guard::{restore_for_suspend, TerminalGuard};
 use crate::unix::unix_error::UnixError;
 use crate::unix::unix_event::UnixEvent;
+use crate::unix::write_queue::WriteQueue;
+
+/// Размер очереди stdout, после которого перестаем читать вывод pty
+const STDOUT_HIGH_WATER: usize = 256 * 1024;
+/// Чтение pty возобновляется, когда очередь stdout опустится до этого размера
+const STDOUT_LOW_WATER: usize = 64 * 1024;
+/// Очередь клиента --attach-socket; клиент, который ее переполнил, отключается
+const ATTACH_HIGH_WATER: usize = 256 * 1024;
+const ATTACH_LOW_WATER: usize = 64 * 1024;
+/// Размер очереди pty, после которого перестаем читать stdin
+const PTY_HIGH_WATER: usize = 64 * 1024;
+const PTY_LOW_WATER: usize = 16 * 1024;
+/// Сколько вывода pty переносить в stdout за один splice, по емкости pipe по умолчанию
+const SPLICE_CHUNK: usize = 64 * 1024;
 
 // Флаг          Значение
 // ISIG          Разрешить посылку сигналов
@@ -75,7 +99,7 @@ fn get_termios(stdin_fild: i32) -> std::io::Result<Termios> {
     Termios::from_fd(stdin_fild)
 }
 
-fn _get_termsize(stdin_fild: i32) -> std::io::Result<Box<nix::libc::winsize>> {
+fn get_termsize(stdin_fild: i32) -> std::io::Result<Box<nix::libc::winsize>> {
     let mut size = Box::new(nix::libc::winsize {
         ws_row: 25,
         ws_col: 80,
@@ -90,6 +114,12 @@ fn _get_termsize(stdin_fild: i32) -> std::io::Result<Box<nix::libc::winsize>> {
     }
 }
 
+/// Размер окна терминала sshpass: stdin, а если он перенаправлен, то stdout
+fn terminal_size() -> std::io::Result<Box<nix::libc::winsize>> {
+    get_termsize(std::io::stdin().as_raw_fd())
+        .or_else(|_| get_termsize(std::io::stdout().as_raw_fd()))
+}
+
 // pub fn _set_termsize(fd: i32, mut size: Box<nix::libc::winsize>) -> std::io::Result<()> {
 //     let ret = unsafe { nix::libc::ioctl(fd, nix::libc::TIOCSWINSZ, &mut *size) };
 
@@ -98,7 +128,7 @@ fn _get_termsize(stdin_fild: i32) -> std::io::Result<Box<nix::libc::winsize>> {
 //         _ => Err(std::io::Error::last_os_error()),
 //     }
 // }
-pub fn _set_termsize(fd: i32, mut size: nix::libc::winsize) -> std::io::Result<()> {
+pub fn set_termsize(fd: i32, mut size: nix::libc::winsize) -> std::io::Result<()> {
     let ret = unsafe { nix::libc::ioctl(fd, nix::libc::TIOCSWINSZ, &mut size) };
 
     match ret {
@@ -119,12 +149,12 @@ impl Buffer {
         }
     }
 
-    pub fn get_slice_len(&self, len: usize) -> std::cell::Ref<[u8]> {
+    pub fn get_slice_len(&self, len: usize) -> std::cell::Ref<'_, [u8]> {
         std::cell::Ref::map(self.buf.borrow(), |vec| &vec[..len])
     }
 
     /// Получает изменяемый срез
-    pub fn get_mut_slice(&self) -> std::cell::RefMut<[u8]> {
+    pub fn get_mut_slice(&self) -> std::cell::RefMut<'_, [u8]> {
         std::cell::RefMut::map(self.buf.borrow_mut(), |vec| vec.as_mut_slice())
     }
 }
@@ -133,23 +163,40 @@ impl Buffer {
 pub struct UnixApp {
     poller: Poller,
     buf: Buffer,
+    /// исходные настройки терминала stdin, возвращаются при любом выходе
+    terminal: Option<TerminalGuard>,
+    /// вывод pty переносится в stdout через splice (--splice-output)
+    splice_output: Cell<bool>,
 }
 
 impl UnixApp {
-    pub fn new(args: ArgMatches) -> Result<Self, UnixError> {
+    /// tty - переводить ли stdin в неканонический режим, stdin при этом должен быть терминалом
+    /// (см. TerminalInfo::raw_mode). Без терминала (cron, CI, pipe) stdin читается как есть
+    /// child - как запускать дочерний процесс, см. ChildSpawnOptions
+    pub fn new(
+        program: &str,
+        program_args: &[String],
+        child: &ChildSpawnOptions,
+        tty: bool,
+    ) -> Result<Self, UnixError> {
         // Создаем контейнер для дескрипторов, которые будут опрашиваться через poll
         let mut res = Self {
             poller: Poller::new(PollTimeout::from(200_u16)),
             buf: Buffer::new(4096),
+            terminal: None,
+            splice_output: Cell::new(false),
         };
 
         res.reg_signals()?;
 
-        let program = args.get_one::<String>("program").unwrap();
-        let program_args = args.get_many::<String>("program_args");
-        res.reg_pty_child(program, program_args)?;
+        res.reg_pty_child(program, program_args, child)?;
 
-        res.reg_non_canonical_stdin()?;
+        if tty {
+            res.reg_non_canonical_stdin()?;
+        } else {
+            trace!("no tty, skip non canonical mode");
+            res.reg_stdin()?;
+        }
 
         res.reg_stdout()?;
 
@@ -157,11 +204,28 @@ impl UnixApp {
     }
     pub fn reg_pty_child(
         &mut self,
-        program: &String,
-        args: Option<ValuesRef<String>>,
+        program: &str,
+        args: &[String],
+        child: &ChildSpawnOptions,
     ) -> Result<(), UnixError> {
-        // Создаем псевдотерминал (PTY)
-        let pty = openpty(None, None).expect("Failed to open PTY");
+        // Создаем псевдотерминал (PTY) с размером окна терминала sshpass, без терминала
+        // размер остается 0x0, как у pipe
+        let size = terminal_size().ok();
+        trace!("pty size {:?}", size.as_deref().map(|size| (size.ws_col, size.ws_row)));
+        let pty = openpty(size.as_deref(), None).expect("Failed to open PTY");
+        // openpty не ставит FD_CLOEXEC: master достался бы программе и ее потомкам,
+        // slave программе нужен только как stdio, которые std создаст через dup2
+        for fd in [pty.master.as_raw_fd(), pty.slave.as_raw_fd()] {
+            ensure_cloexec(fd)?;
+        }
+        self.poller.fds.audit_cloexec();
+
+        // (читающий, пишущий) концы pipe для stderr дочернего процесса
+        let stderr_pipe = if child.has_separate_stderr() {
+            Some(pipe2(OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
 
         // fork() - создает дочерний процесс из текущего
         // parent блок это продолжение текущего запущенного процесса
@@ -169,18 +233,21 @@ impl UnixApp {
         // все окружение дочернего процесса наследуется из родительского
         let status = match unsafe { fork() } {
             Ok(ForkResult::Child) => {
-                let master = match pty.master.try_clone() {
-                    Err(e) => {
-                        error!("Failed to clone PTY master: {}", e);
-                        return Err(e.into());
-                    }
-                    Ok(master) => master,
-                };
+                // родитель заблокировал все сигналы ради signalfd, маска наследуется через fork и exec,
+                // без сброса дочерний процесс не реагирует ни на SIGTERM, ни на SIGINT
+                if let Err(e) = SigSet::empty().thread_set_mask() {
+                    error!("failed to reset child signal mask: {}", e);
+                }
+
+                // stderr еще не перенаправлен в pty, ошибка будет видна как ошибка самого sshpass
+                let res = attach_controlling_terminal(&pty.slave).and_then(|_| child.apply());
+                if let Err(e) = res {
+                    error!("child {}", e);
+                    eprintln!("sshpass: {}", e);
+                    unsafe { nix::libc::_exit(EXIT_RUNTIME_ERROR) };
+                }
 
                 // Перенаправляем стандартный ввод, вывод и ошибки в псевдотерминал
-                unsafe { nix::libc::ioctl(master.as_raw_fd(), nix::libc::TIOCNOTTY) };
-                unsafe { nix::libc::setsid() };
-                unsafe { nix::libc::ioctl(pty.slave.as_raw_fd(), nix::libc::TIOCSCTTY) };
                 // эта программа исполняется только в дочернем процессе
                 // родительский процесс в это же время выполняется и что то делает
 
@@ -192,26 +259,53 @@ impl UnixApp {
                 // Command будет выполняться под pid этого дочернего процесса и буквально станет им
                 // осуществляется всё это с помощью exec()
                 let mut cmd = std::process::Command::new(program);
-                if let Some(args) = args {
-                    cmd.args(args);
+                cmd.args(args);
+                child.child_env().apply(&mut cmd);
+                if child.closes_fds() {
+                    // std уже поставил stdio на место, остальное программе не нужно
+                    unsafe {
+                        cmd.pre_exec(|| {
+                            close_inherited_fds();
+                            Ok(())
+                        })
+                    };
                 }
 
+                let stderr = match stderr_pipe {
+                    Some((_, writer)) => Stdio::from(writer),
+                    None => new_follower_stdio(),
+                };
+
                 let e = cmd
                     .stdin(new_follower_stdio())
                     .stdout(new_follower_stdio())
-                    .stderr(new_follower_stdio())
+                    .stderr(stderr)
                     .exec();
 
+                // exec вернул управление - программа не запустилась, возврат из fork в main
+                // запустил бы в дочернем процессе восстановление терминала и деструкторы родителя
                 error!("child error: {e}");
-
-                Err(e.into())
+                eprintln!("sshpass: {}: {}", program, e);
+                unsafe { nix::libc::_exit(EXIT_RUNTIME_ERROR) };
             }
             Ok(ForkResult::Parent { child }) => {
                 // эта исполняется только в родительском процессе
                 // возвращаю pty дескриптор для отслеживания событий через poll
                 self.poller
                     .fds
-                    .push_pty_fd(pty, child, PollFlags::POLLIN);
+                    .push_pty_fd(
+                        pty,
+                        child,
+                        PollFlags::POLLIN,
+                        WriteQueue::new(PTY_HIGH_WATER, PTY_LOW_WATER),
+                    );
+
+                // пишущий конец остается только у дочернего процесса, иначе EOF не наступит
+                if let Some((reader, _)) = stderr_pipe {
+                    self.poller
+                        .fds
+                        .push_child_stderr_fd(reader, PollFlags::POLLIN);
+                }
 
                 Ok(())
             }
@@ -242,12 +336,23 @@ impl UnixApp {
     pub fn reg_non_canonical_stdin(&mut self) -> Result<(), UnixError> {
         // перевожу stdin в режим non canonical для побайтовой обработки вводимых данных
         // добавляю в контейнер fds для дальнейшего отслеживания событий через poll
-        let termios = get_termios(std::io::stdin().lock().as_raw_fd())?;
+        let fd = std::io::stdin().lock().as_raw_fd();
+        let termios = get_termios(fd)?;
 
+        // настройки запоминаются до изменения, чтобы их можно было вернуть даже при панике
+        self.terminal = Some(TerminalGuard::save(fd, termios));
         Self::set_non_canonical_stdin()?;
         self.poller
             .fds
-            .push_stdin_fd(std::io::stdin(), termios, PollFlags::POLLIN);
+            .push_stdin_fd(std::io::stdin(), PollFlags::POLLIN);
+
+        Ok(())
+    }
+
+    pub fn reg_stdin(&mut self) -> Result<(), UnixError> {
+        self.poller
+            .fds
+            .push_stdin_fd(std::io::stdin(), PollFlags::POLLIN);
 
         Ok(())
     }
@@ -255,7 +360,12 @@ impl UnixApp {
     pub fn reg_stdout(&mut self) -> Result<(), UnixError> {
         let stdout = std::io::stdout();
 
-        self.poller.fds.push_stdout_fd(stdout, PollFlags::POLLIN);
+        // stdout опрашивается только на POLLOUT и только пока есть недописанные данные
+        self.poller.fds.push_stdout_fd(
+            stdout,
+            PollFlags::empty(),
+            WriteQueue::new(STDOUT_HIGH_WATER, STDOUT_LOW_WATER),
+        );
 
         Ok(())
     }
@@ -299,31 +409,208 @@ impl UnixApp {
                 Fd::Signal { .. } => {}
                 Fd::PtyMaster { .. } => {}
                 Fd::PtySlave { .. } => {}
-                Fd::Stdin { fd, termios, .. } => {
-                    // Восстанавливаем исходные атрибуты терминала
-                    trace!("termios restore: {:#?}", termios);
-                    let res = set_termios(fd.as_raw_fd(), termios);
-                    trace!("termios restore: {:?}", res);
-                }
+                Fd::ChildStderr { .. } => {}
+                Fd::PasswordResponse { .. } => {}
+                Fd::Stdin { .. } => {}
                 Fd::Stdout { .. } => {}
+                Fd::AttachListener { path, .. } => {
+                    let res = std::fs::remove_file(path);
+                    trace!("remove attach socket {}: {:?}", path.display(), res);
+                }
+                Fd::AttachClient { .. } => {}
             }
         }
+
+        // Восстанавливаем исходные атрибуты терминала
+        drop(self.terminal.take());
         trace!("deinit fds");
 
         Ok(())
     }
 
-    pub fn waitpid(&self, pid: nix::libc::pid_t) -> nix::Result<WaitStatus> {
-        trace!("check child process {} is running...", pid);
-        let pid = Pid::from_raw(pid);
-        let options = Some(
-            WaitPidFlag::WNOHANG
-                | WaitPidFlag::WSTOPPED
-                | WaitPidFlag::WCONTINUED
-                | WaitPidFlag::WUNTRACED,
-        );
+    /// Перестает читать stdin
+    pub fn close_stdin(&self) {
+        self.poller.fds.close_stdin();
+    }
+
+    /// Перестает читать pty, после того как его закрыли все потомки
+    pub fn close_pty_master(&self) {
+        self.poller.fds.close_pty_master();
+    }
+
+    /// Перестает читать stderr дочернего процесса
+    pub fn close_child_stderr(&self) {
+        self.poller.fds.close_child_stderr();
+    }
+
+    /// Регистрирует дескриптор, из которого придет пароль (--password-fd-handshake)
+    /// До запроса пароля дескриптор не опрашивается, см. listen_password_response
+    pub fn reg_password_response(&mut self, fd: OwnedFd) {
+        self.poller
+            .fds
+            .push_password_response_fd(fd, PollFlags::empty());
+    }
+
+    /// Открывает сокет, через который к сессии можно подключиться со стороны (--attach-socket)
+    /// clients - сколько клиентов может быть подключено одновременно
+    pub fn reg_attach_socket(&mut self, path: &Path, clients: usize) -> Result<(), UnixError> {
+        let listener = bind_attach_socket(path)?;
+        self.poller.fds.push_attach_fds(listener, path.to_owned(), clients, || {
+            WriteQueue::new(ATTACH_HIGH_WATER, ATTACH_LOW_WATER)
+        });
+
+        Ok(())
+    }
+
+    /// Клиент --attach-socket отключился
+    pub fn close_attach_client(&self, index: usize) {
+        self.poller.fds.close_attach_client(index);
+    }
+
+    /// Вывод программы подключенным клиентам --attach-socket
+    pub fn write_to_attached(&self, chunk: &Bytes) {
+        self.poller.fds.write_to_attached(chunk);
+    }
+
+    /// Пароль запрошен, можно читать ответ
+    pub fn listen_password_response(&self) {
+        self.poller.fds.listen_password_response();
+    }
+
+    pub fn close_password_response(&self) {
+        self.poller.fds.close_password_response();
+    }
+
+    /// Вывод pty дальше идет в stdout через splice, минуя sshpass. stdout должен быть pipe.
+    /// Если ядро не умеет splice для pty, вывод снова читается как обычно
+    pub fn enable_splice_output(&self) {
+        self.splice_output.set(true);
+    }
+
+    /// Отправляет сигнал группе переднего плана терминала программы, как это сделал бы
+    /// терминал на ^C: если программа (например оболочка) запустила задание, сигнал получит оно
+    pub fn signal_foreground(&self, sig: Signal) -> nix::Result<Pid> {
+        let pgrp = self
+            .poller
+            .iter()
+            .find_map(|fd| match &*fd {
+                Fd::PtyMaster { fd, .. } => Some(tcgetpgrp(fd)),
+                _ => None,
+            })
+            .ok_or(Errno::ENOTTY)??;
+
+        killpg(pgrp, sig)?;
+        Ok(pgrp)
+    }
+
+    /// Переносит размер окна терминала sshpass на pty программы (SIGWINCH).
+    /// Ядро само отправит SIGWINCH группе переднего плана в pty
+    pub fn resize_pty(&self) -> std::io::Result<()> {
+        let size = terminal_size()?;
+        let master = self
+            .poller
+            .iter()
+            .find_map(|fd| match &*fd {
+                Fd::PtyMaster { fd, .. } => Some(fd.as_raw_fd()),
+                _ => None,
+            })
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+
+        trace!("resize pty to {}x{}", size.ws_col, size.ws_row);
+        set_termsize(master, *size)
+    }
+
+    /// Процесс из сессии программы: сама программа или запущенные ей процессы
+    /// Программа - лидер своей сессии, поэтому id сессии совпадает с ее pid
+    pub fn in_child_session(&self, pid: Pid) -> bool {
+        let Some(child) = self.child() else {
+            return false;
+        };
+        pid == child || getsid(Some(pid)) == Ok(child)
+    }
 
-        waitpid(pid, options)
+    /// Символ конца файла (VEOF) терминала дочернего процесса, обычно ^D
+    pub fn pty_eof_char(&self) -> u8 {
+        let termios = self.poller.iter().find_map(|fd| match &*fd {
+            Fd::PtyMaster { fd, .. } => get_termios(fd.as_raw_fd()).ok(),
+            _ => None,
+        });
+
+        termios.map(|t| t.c_cc[VEOF]).unwrap_or(0x04)
+    }
+
+    /// pid дочернего процесса, запущенного в pty
+    pub fn child(&self) -> Option<Pid> {
+        self.poller.iter().find_map(|fd| match &*fd {
+            Fd::PtyMaster { child, .. } => Some(*child),
+            _ => None,
+        })
+    }
+
+    /// Останавливает программу и sshpass (SIGTSTP: kill -TSTP или escape-последовательность ^Z)
+    /// На время остановки терминал возвращается в исходный режим, что бы оболочка, в которую
+    /// вернется пользователь, работала как обычно. Выполнение продолжается после SIGCONT
+    pub fn suspend(&self) -> Result<(), UnixError> {
+        // программа лидер своей сессии (setsid), ее группа процессов - ее pid
+        if let Some(child) = self.child() {
+            let res = killpg(child, Signal::SIGSTOP);
+            trace!("suspend, killpg({}, SIGSTOP) = {:?}", child, res);
+        }
+        if self.terminal.is_some() {
+            restore_for_suspend();
+        }
+
+        // SIGSTOP не блокируется: процесс останавливается прямо здесь до SIGCONT
+        raise(Signal::SIGSTOP)?;
+        self.resume();
+
+        Ok(())
+    }
+
+    /// Продолжение после остановки (SIGCONT): терминал снова в сыром режиме, программа продолжает работу
+    /// Повторный вызов безопасен. Из фоновой группы (bg) терминал не трогается:
+    /// его настройки вернутся при следующем SIGCONT, когда sshpass выведут на передний план (fg)
+    pub fn resume(&self) {
+        if self.terminal.is_some() {
+            let stdin = std::io::stdin();
+            let foreground = tcgetpgrp(&stdin).is_ok_and(|pgrp| pgrp == getpgrp());
+            if foreground {
+                if let Err(e) = Self::set_non_canonical_stdin() {
+                    error!("resume, non canonical stdin: {:?}", e);
+                }
+            } else {
+                trace!("resume in background, terminal left as is");
+            }
+        }
+
+        if let Some(child) = self.child() {
+            let res = killpg(child, Signal::SIGCONT);
+            trace!("resume, killpg({}, SIGCONT) = {:?}", child, res);
+        }
+    }
+
+    /// Забирает статусы всех завершившихся потомков вместе с потраченными ими ресурсами
+    /// SIGCHLD склеиваются: один сигнал может прийти за нескольких потомков,
+    /// поэтому wait4(-1) вызывается пока есть кого забирать
+    pub fn reap_children(&self) -> Vec<(WaitStatus, ChildUsage)> {
+        let mut res = vec![];
+        loop {
+            match wait4_any() {
+                Ok((WaitStatus::StillAlive, _)) => break,
+                Ok((status, usage)) => {
+                    trace!("wait4(-1) = {:?}, {}", status, usage);
+                    res.push((status, usage));
+                }
+                Err(Errno::EINTR) => continue,
+                Err(Errno::ECHILD) => break,
+                Err(e) => {
+                    error!("wait4(-1) error: {}", e);
+                    break;
+                }
+            }
+        }
+
+        res
 
         // match waitpid(pid, options) {
         //     Err(e) => {
@@ -455,19 +742,22 @@ impl UnixApp {
         }
     }
 
-    fn map_ref_to_siginfo(bytes: Ref<[u8]>) -> Ref<siginfo> {
-        Ref::map(bytes, |slice| {
-            // Преобразуем срез байт в ссылку на siginfo
-            assert!(
-                slice.len() >= std::mem::size_of::<siginfo>(),
-                "Slice too small"
-            );
-            unsafe { &*(slice.as_ptr() as *const siginfo) }
-        })
+    /// Копирует siginfo из прочитанных байт
+    /// Буфер выровнен по u8, поэтому ссылку &siginfo на него брать нельзя (UB при невыровненном
+    /// адресе), структура читается по значению через read_unaligned
+    fn siginfo_from_bytes(bytes: &[u8]) -> siginfo {
+        assert!(
+            bytes.len() >= std::mem::size_of::<siginfo>(),
+            "Slice too small"
+        );
+        unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const siginfo) }
     }
 
-    fn match_signal_event(&self, index: usize, fd: &SignalFd) -> Result<UnixEvent, UnixError> {
-        let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+    fn match_signal_event(&self, index: usize, fd: &SignalFd) -> Result<UnixEvent<'_>, UnixError> {
+        // читаю ровно одну структуру, остальные сигналы придут следующими событиями poll
+        let size = std::mem::size_of::<siginfo>();
+        let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice()[..size]);
+        self.poller.fds.record_read(index, &res);
         match res {
             Err(e) => {
                 // error
@@ -483,8 +773,7 @@ impl UnixApp {
                 // read n bytes
                 trace!("signal match Ok({n}) bytes");
                 trace!("try convert to struct siginfo");
-                let buf = self.buf.get_slice_len(n);
-                let res = Self::map_ref_to_siginfo(buf);
+                let res = Self::siginfo_from_bytes(&self.buf.get_slice_len(n));
 
                 let signal = Signal::try_from(res.ssi_signo as i32);
                 if let Err(e) = signal {
@@ -503,9 +792,21 @@ impl UnixApp {
         &self,
         index: usize,
         fd: &OwnedFd,
-    ) -> Result<UnixEvent, UnixError> {
+    ) -> Result<UnixEvent<'_>, UnixError> {
+        if self.splice_output.get() {
+            if let Some(event) = self.splice_pty_master(index, fd) {
+                return Ok(event);
+            }
+        }
+
         let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
         match res {
+            Err(Errno::EIO) => {
+                // slave закрыт всеми процессами, вывода больше не будет
+                trace!("pty match Err(EIO), pty closed");
+                Ok(UnixEvent::PtyClosed(index))
+            }
             Err(e) => {
                 // error
                 trace!("pty match Err({:?})", e);
@@ -526,12 +827,43 @@ impl UnixApp {
         }
     }
 
+    /// None - splice сейчас невозможен, вывод читается как обычно: очередь stdout
+    /// не пуста или pipe полон (тогда вывод встанет в очередь и сработает backpressure)
+    fn splice_pty_master(&self, index: usize, fd: &OwnedFd) -> Option<UnixEvent<'_>> {
+        let res = self.poller.fds.splice_to_stdout(fd.as_fd(), SPLICE_CHUNK)?;
+        match res {
+            Err(EAGAIN) => None,
+            Err(Errno::EIO) => {
+                trace!("pty splice Err(EIO), pty closed");
+                self.poller.fds.record_read(index, &res);
+                Some(UnixEvent::PtyClosed(index))
+            }
+            Err(e) => {
+                // EINVAL: ядро не поддерживает splice из pty
+                warn!("splice from pty failed: {}, falling back to read", e);
+                self.splice_output.set(false);
+                None
+            }
+            Ok(0) => {
+                trace!("pty splice Ok(0) bytes");
+                self.poller.fds.record_read(index, &res);
+                Some(UnixEvent::ReadZeroBytes)
+            }
+            Ok(n) => {
+                trace!("pty splice Ok({n}) bytes");
+                self.poller.fds.record_read(index, &res);
+                Some(UnixEvent::PtySpliced(index, n))
+            }
+        }
+    }
+
     fn match_pty_slave_event(
         &self,
         index: usize,
         fd: &OwnedFd,
-    ) -> Result<UnixEvent, UnixError> {
+    ) -> Result<UnixEvent<'_>, UnixError> {
         let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
         match res {
             Err(e) => {
                 // error
@@ -553,8 +885,62 @@ impl UnixApp {
         }
     }
 
-    fn match_stdin_event(&self, index: usize, fd: &Stdin) -> Result<UnixEvent, UnixError> {
+    fn match_child_stderr_event(
+        &self,
+        index: usize,
+        fd: &OwnedFd,
+    ) -> Result<UnixEvent<'_>, UnixError> {
+        let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
+        match res {
+            Err(e) => {
+                // error
+                trace!("child stderr match Err({:?})", e);
+                Err(e.into())
+            }
+            Ok(0) => {
+                // EOF, все пишущие концы pipe закрыты
+                trace!("child stderr match Ok(0) bytes");
+                Ok(UnixEvent::ChildStderrEof(index))
+            }
+            Ok(n) => {
+                // read n bytes
+                trace!("child stderr match Ok({n}) bytes");
+                let buf = self.buf.get_slice_len(n);
+                let res = UnixEvent::ChildStderr(index, buf);
+                Ok(res)
+            }
+        }
+    }
+
+    fn match_password_response_event(
+        &self,
+        index: usize,
+        fd: &OwnedFd,
+    ) -> Result<UnixEvent<'_>, UnixError> {
+        let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
+        match res {
+            Err(e) => {
+                trace!("password response match Err({:?})", e);
+                Err(e.into())
+            }
+            Ok(0) => {
+                trace!("password response match Ok(0) bytes");
+                Ok(UnixEvent::PasswordResponseEof(index))
+            }
+            Ok(n) => {
+                // содержимое не логируется, это пароль
+                trace!("password response match Ok({n}) bytes");
+                let buf = self.buf.get_slice_len(n);
+                Ok(UnixEvent::PasswordResponse(index, buf))
+            }
+        }
+    }
+
+    fn match_stdin_event(&self, index: usize, fd: &Stdin) -> Result<UnixEvent<'_>, UnixError> {
         let res = Self::read_event(fd.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
         match res {
             Err(e) => {
                 // error
@@ -562,9 +948,9 @@ impl UnixApp {
                 Err(e.into())
             }
             Ok(0) => {
-                // EOF
+                // EOF, poll сообщил о данных, а прочитать нечего
                 trace!("stdin match Ok(0) bytes");
-                Ok(UnixEvent::ReadZeroBytes)
+                Ok(UnixEvent::StdinEof(index))
             }
             Ok(n) => {
                 // read n bytes
@@ -576,7 +962,44 @@ impl UnixApp {
         }
     }
 
-    pub fn system_event(&self) -> Result<UnixEvent, UnixError> {
+    fn match_attach_listener_event(
+        &self,
+        accepted: std::io::Result<(UnixStream, SocketAddr)>,
+    ) -> UnixEvent<'_> {
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                error!("attach accept error: {}", e);
+                return UnixEvent::ReadZeroBytes;
+            }
+        };
+
+        match self.poller.fds.add_attach_client(stream) {
+            Ok(index) => UnixEvent::AttachConnected(index),
+            Err(_stream) => {
+                // клиент отключается сразу, когда _stream выходит из области видимости
+                warn!("attach: all client slots are busy, connection refused");
+                UnixEvent::ReadZeroBytes
+            }
+        }
+    }
+
+    fn match_attach_client_event(&self, index: usize, stream: &UnixStream) -> UnixEvent<'_> {
+        let res = Self::read_event(stream.as_raw_fd(), &mut self.buf.get_mut_slice());
+        self.poller.fds.record_read(index, &res);
+        match res {
+            Ok(0) | Err(_) => {
+                trace!("attach client {} closed: {:?}", index, res);
+                UnixEvent::AttachClosed(index)
+            }
+            Ok(n) => {
+                trace!("attach client {} match Ok({n}) bytes", index);
+                UnixEvent::AttachInput(index, self.buf.get_slice_len(n))
+            }
+        }
+    }
+
+    pub fn system_event(&self) -> Result<UnixEvent<'_>, UnixError> {
         trace!("poll(&mut fds, {:?})", self.poller.poll_timeout);
         match self.poller.poll() {
             Err(e) => {
@@ -597,7 +1020,14 @@ impl UnixApp {
         // trace!("{:#?}", self.fds);
 
         // Извлекаем необходимую информацию из итератора
-        if let Some((fd, index)) = self.poller.revent_iter().next() {
+        if let Some((fd, index, revents)) = self.poller.revent_iter().next() {
+            // сначала дописываю очередь, чтение (если оно тоже готово) придет следующим poll
+            if revents.contains(PollFlags::POLLOUT) {
+                drop(fd);
+                self.poller.fds.flush(index);
+                return Ok(UnixEvent::WriteReady(index));
+            }
+
             match &*fd {
                 Fd::Signal { fd, .. } => {
                     return self.match_signal_event(index, fd);
@@ -608,31 +1038,61 @@ impl UnixApp {
                 Fd::PtySlave { fd, .. } => {
                     return self.match_pty_slave_event(index, fd);
                 }
+                Fd::ChildStderr { fd, .. } => {
+                    return self.match_child_stderr_event(index, fd);
+                }
+                Fd::PasswordResponse { fd, .. } => {
+                    return self.match_password_response_event(index, fd);
+                }
                 Fd::Stdin { fd, .. } => {
                     return self.match_stdin_event(index, fd);
                 }
                 Fd::Stdout { .. } => {
                     // return self.match_stdout_event(index, fd);
                 }
+                Fd::AttachListener { fd: listener, .. } => {
+                    let accepted = listener.accept();
+                    // слот клиента занимается уже без заимствования списка дескрипторов
+                    drop(fd);
+                    return Ok(self.match_attach_listener_event(accepted));
+                }
+                Fd::AttachClient { fd: Some(stream), .. } => {
+                    return Ok(self.match_attach_client_event(index, stream));
+                }
+                Fd::AttachClient { fd: None, .. } => {
+                    return Ok(UnixEvent::ReadZeroBytes);
+                }
             }
         }
 
         Err(UnixError::PollEventNotHandle)
     }
 
-    pub fn send_to(&self, index: usize, buf: &Ref<[u8]>) {
+    pub fn send_to(&self, index: usize, buf: Bytes) {
         self.poller.fds.send_to(index, buf)
     }
 
-    pub fn write_to_stdout(&self, buf: &Ref<[u8]>) {
+    /// Счетчики активности дескрипторов
+    pub fn snapshot(&self) -> Vec<FdReport> {
+        self.poller.snapshot()
+    }
+
+    /// Есть данные для stdout или pty, которые еще не удалось записать
+    pub fn has_pending_writes(&self) -> bool {
+        self.poller.fds.has_pending_writes()
+    }
+
+    /// Запись в stdout, pty и stdin идет через очереди, они держат кусок без копирования:
+    /// готовый Vec передается через Bytes::from, заимствованный буфер копируется один раз
+    pub fn write_to_stdout(&self, buf: Bytes) {
         self.poller.fds.write_to_stdout(buf);
     }
 
-    pub fn write_to_stdin(&self, buf: &Ref<[u8]>) {
+    pub fn write_to_stdin(&self, buf: Bytes) {
         self.poller.fds.write_to_stdin(buf);
     }
 
-    pub fn write_to_pty_master(&self, buf: &Ref<[u8]>) {
+    pub fn write_to_pty_master(&self, buf: Bytes) {
         self.poller.fds.write_to_pty_master(buf);
     }
 }
@@ -645,56 +1105,41 @@ impl Drop for UnixApp {
     }
 }
 
-#[derive(Debug)]
-pub struct UnixAppStop {
-    is_stoped: bool,
-    is_stop: bool,
-    stop_time: Option<Instant>,
-    stop_code: Option<i32>,
-    stop_error: Option<String>,
-}
-
-impl UnixAppStop {
-    pub fn new() -> Self {
-        Self {
-            is_stoped: false,
-            is_stop: false,
-            stop_time: None,
-            stop_code: None,
-            stop_error: None,
+/// Создает сокет --attach-socket, доступный только владельцу
+/// Файл сокета, оставшийся от завершенного sshpass (к нему никто не подключается), заменяется
+fn bind_attach_socket(path: &Path) -> std::io::Result<UnixListener> {
+    let bind = || {
+        // права 0600 с момента создания, chmod после bind оставил бы окно для чужих подключений
+        let old = umask(Mode::from_bits_truncate(0o177));
+        let res = UnixListener::bind(path);
+        umask(old);
+        res
+    };
+
+    match bind() {
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(e);
+            }
+            trace!("remove stale attach socket {}", path.display());
+            std::fs::remove_file(path)?;
+            bind()
         }
+        res => res,
     }
+}
 
-    pub fn is_stop(&self) -> bool {
-        self.is_stop
-    }
-
-    pub fn is_stoped(&self) -> bool {
-        self.is_stoped
-    }
-
-    pub fn shutdown_starting(&mut self, stop_code: i32, error: Option<String>) {
-        self.stop_time = Some(Instant::now());
-        self.is_stop = true;
-        self.is_stoped = false;
-        self.stop_code = Some(stop_code);
-        self.stop_error = error;
-    }
-
-    pub fn shutdown_complited(&mut self) {
-        self.is_stop = false;
-        self.is_stoped = true;
-    }
-
-    pub fn shutdown_cancel(&mut self) {
-        self.is_stop = false;
-        self.is_stoped = false;
-        self.stop_time = None;
-        self.stop_code = None;
-        self.stop_error = None;
+/// waitpid(-1, WNOHANG), но вместе со статусом ядро отдает rusage завершившегося процесса
+/// (в nix обертки над wait4 нет)
+fn wait4_any() -> nix::Result<(WaitStatus, ChildUsage)> {
+    let mut status = 0;
+    let mut usage = std::mem::MaybeUninit::<libc::rusage>::zeroed();
+    let pid = unsafe { libc::wait4(-1, &mut status, WaitPidFlag::WNOHANG.bits(), usage.as_mut_ptr()) };
+    let pid = Errno::result(pid)?;
+    if pid == 0 {
+        return Ok((WaitStatus::StillAlive, ChildUsage::default()));
     }
 
-    pub fn stop_code(&self) -> i32 {
-        self.stop_code.unwrap_or(255)
-    }
+    let usage = unsafe { usage.assume_init() };
+    Ok((WaitStatus::from_raw(Pid::from_raw(pid), status)?, ChildUsage::from(&usage)))
 }
@@ -10,8 +10,14 @@ pub enum UnixEvent<'a> {
     // Signal(Signal, &'a siginfo),
     Stdin(usize, Ref<'a, [u8]>),
     PtyMaster(usize, Ref<'a, [u8]>),
+    /// вывод pty перенесен в stdout через splice, сколько байт
+    PtySpliced(usize, usize),
     PtySlave(usize, Ref<'a, [u8]>),
-    Signal(usize, Signal, Ref<'a, siginfo>),
+    /// stderr дочернего процесса, если он отделен от pty
+    ChildStderr(usize, Ref<'a, [u8]>),
+    /// ответ родительского процесса на запрос пароля (--password-fd-handshake)
+    PasswordResponse(usize, Ref<'a, [u8]>),
+    Signal(usize, Signal, siginfo),
         // struct signalfd_siginfo {
         //     uint32_t ssi_signo;    /* Signal number */
         //     int32_t  ssi_errno;    /* Error number (unused) */
@@ -36,6 +42,22 @@ pub enum UnixEvent<'a> {
         //     uint8_t  pad[X];       /* Pad size to 128 bytes (allow for
         //                               additional fields in the future) */
         // };
+    /// stdin закрыт с другой стороны (конец pipe или файла)
+    StdinEof(usize),
+    /// pty закрыт всеми потомками (EIO), весь их вывод прочитан
+    PtyClosed(usize),
+    /// дочерний процесс и его потомки закрыли stderr
+    ChildStderrEof(usize),
+    /// родительский процесс закрыл дескриптор ответа с паролем
+    PasswordResponseEof(usize),
+    /// дескриптор принял часть данных из своей очереди записи
+    WriteReady(usize),
+    /// к --attach-socket подключился клиент, индекс его слота
+    AttachConnected(usize),
+    /// клиент --attach-socket что то прислал
+    AttachInput(usize, Ref<'a, [u8]>),
+    /// клиент --attach-socket отключился, слот нужно освободить
+    AttachClosed(usize),
     ReadZeroBytes,
     PollTimeout,
     // ChildExited(Pid, i32),
@@ -0,0 +1,167 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use log::{info, trace, warn};
+
+/// Роль участника остановки, определяет очередность завершения
+/// Сначала перестаем читать ввод, затем дожидаемся вывода, последними сбрасываются приемники (логи)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StopStage {
+    /// источники данных (stdin), перестают читаться первыми
+    Input,
+    /// дочерний процесс и его вывод в pty
+    Output,
+    /// приемники данных (лог файлы), сбрасываются последними
+    Sink,
+}
+
+/// Стадии остановки приложения
+/// Переходы возможны только вперед: Running -> Stopping(Input -> Output -> Sink) -> Stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StopState {
+    Running,
+    /// Остановка начата, ждем пока участники текущей стадии закончат работу (drained)
+    /// или истечет deadline
+    Stopping(StopStage),
+    /// Приложение можно завершать
+    Stopped,
+}
+
+#[derive(Debug)]
+pub struct UnixAppStop {
+    state: StopState,
+    /// когда началась остановка, от этого момента считается deadline
+    stop_since: Instant,
+    stopping_timeout: Duration,
+    stop_code: Option<i32>,
+    stop_error: Option<String>,
+    /// участники, без которых остановка не завершится (кроме как по deadline)
+    participants: Vec<(StopStage, &'static str)>,
+    /// участники, которые еще не сообщили о готовности к остановке
+    pending: Vec<(StopStage, &'static str)>,
+    subscribers: Vec<mpsc::Sender<StopState>>,
+}
+
+impl UnixAppStop {
+    pub fn new(stopping_timeout: Duration) -> Self {
+        Self {
+            state: StopState::Running,
+            stop_since: Instant::now(),
+            stopping_timeout,
+            stop_code: None,
+            stop_error: None,
+            participants: vec![],
+            pending: vec![],
+            subscribers: vec![],
+        }
+    }
+
+    /// Регистрирует участника остановки
+    /// Стадия stage не закончится, пока участник не вызовет drained(name) либо не истечет deadline
+    pub fn participant(&mut self, stage: StopStage, name: &'static str) {
+        if !self.participants.iter().any(|(_, p)| *p == name) {
+            self.participants.push((stage, name));
+        }
+    }
+
+    /// Подписка на смену стадий, каждая новая стадия отправляется в канал
+    pub fn subscribe(&mut self) -> mpsc::Receiver<StopState> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn is_stop(&self) -> bool {
+        matches!(self.state, StopState::Stopping(_))
+    }
+
+    pub fn is_stoped(&self) -> bool {
+        self.state == StopState::Stopped
+    }
+
+    /// Начинает остановку
+    /// Повторный вызов во время остановки ничего не меняет: код и причина берутся от первого вызова
+    pub fn shutdown_starting(&mut self, stop_code: i32, error: Option<String>) {
+        if self.state != StopState::Running {
+            trace!("shutdown already {:?}, ignore code {}", self.state, stop_code);
+            return;
+        }
+
+        match &error {
+            Some(e) => warn!("shutdown starting, code {}: {}", stop_code, e),
+            None => info!("shutdown starting, code {}", stop_code),
+        }
+
+        self.stop_code = Some(stop_code);
+        self.stop_error = error;
+        self.pending = self.participants.clone();
+        self.transition(StopState::Stopping(StopStage::Input));
+        self.advance();
+    }
+
+    /// Участник закончил работу и не мешает остановке
+    /// Если остановка еще не начата, участник больше не будет ее задерживать
+    pub fn drained(&mut self, name: &'static str) {
+        self.participants.retain(|(_, p)| *p != name);
+        self.pending.retain(|(_, p)| *p != name);
+        trace!("{} drained, pending: {:?}", name, self.pending);
+
+        if self.is_stop() {
+            self.advance();
+        }
+    }
+
+    /// Немедленно завершает остановку, не дожидаясь участников
+    pub fn shutdown_complited(&mut self) {
+        if self.state == StopState::Running {
+            self.stop_code.get_or_insert(0);
+        }
+        self.transition(StopState::Stopped);
+    }
+
+    /// Проверяет deadline стадии Stopping, вызывается на каждой итерации цикла
+    pub fn tick(&mut self) {
+        if self.is_stop() && self.stop_since.elapsed() >= self.stopping_timeout {
+            warn!(
+                "shutdown deadline {:?} expired, not drained: {:?}",
+                self.stopping_timeout, self.pending
+            );
+            self.transition(StopState::Stopped);
+        }
+    }
+
+    pub fn stop_code(&self) -> i32 {
+        self.stop_code.unwrap_or(255)
+    }
+
+    pub fn stop_error(&self) -> Option<&str> {
+        self.stop_error.as_deref()
+    }
+
+    /// Переходит к первой стадии, в которой остались не завершившиеся участники
+    fn advance(&mut self) {
+        let next = self.pending.iter().map(|(stage, _)| *stage).min();
+        match next {
+            Some(stage) => self.transition(StopState::Stopping(stage)),
+            None => self.transition(StopState::Stopped),
+        }
+    }
+
+    fn transition(&mut self, state: StopState) {
+        if state <= self.state {
+            return;
+        }
+
+        trace!("shutdown state {:?} -> {:?}", self.state, state);
+        if self.state == StopState::Running {
+            self.stop_since = Instant::now();
+        }
+        self.state = state;
+        self.notify();
+    }
+
+    fn notify(&mut self) {
+        let state = self.state;
+        self.subscribers.retain(|tx| tx.send(state).is_ok());
+    }
+}
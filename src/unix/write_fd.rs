@@ -0,0 +1,79 @@
+use std::os::fd::AsFd;
+
+use nix::errno::Errno;
+use nix::fcntl::{splice, SpliceFFlags};
+use nix::unistd::write;
+
+use log::trace;
+
+/// Результат записи в файловый дескриптор
+#[derive(Debug, PartialEq, Eq)]
+pub enum WriteResult {
+    /// Записан весь буфер
+    Done(usize),
+    /// Записана только часть буфера, остаток нужно дописать позже
+    PartialWrite { written: usize },
+    /// Дескриптор в неблокирующем режиме и сейчас не готов принять данные (EAGAIN),
+    /// либо запись не приняла ни одного байта
+    WouldBlock,
+    /// Запись прервана сигналом (EINTR), можно повторить
+    Interrupted,
+    /// Читающая сторона закрыта (EPIPE), либо терминал отключен (EIO)
+    BrokenPipe,
+    /// Любая другая ошибка, повторять запись бессмысленно
+    Fatal(Errno),
+}
+
+/// Выполняет одну попытку записи буфера в файловый дескриптор
+pub fn write_fd<Fd: AsFd>(fd: Fd, buf: &[u8]) -> WriteResult {
+    match write(fd, buf) {
+        Ok(n) if n == buf.len() => WriteResult::Done(n),
+        // ни одного байта из непустого буфера: повтор сразу же зациклил бы запись,
+        // дописать остаток можно только когда дескриптор снова будет готов
+        Ok(0) => {
+            trace!("write = Ok(0) of {} bytes", buf.len());
+            WriteResult::WouldBlock
+        }
+        Ok(n) => {
+            trace!("write = Ok({n}) of {} bytes", buf.len());
+            WriteResult::PartialWrite { written: n }
+        }
+        Err(Errno::EAGAIN) => WriteResult::WouldBlock,
+        Err(Errno::EINTR) => WriteResult::Interrupted,
+        Err(Errno::EPIPE | Errno::EIO) => WriteResult::BrokenPipe,
+        Err(e) => WriteResult::Fatal(e),
+    }
+}
+
+/// Пишет буфер целиком, дописывая остаток после частичной записи
+/// и повторяя запись прерванную сигналом.
+/// Возвращает Done с общим количеством байт, либо первую неустранимую ошибку
+pub fn write_all_fd<Fd: AsFd>(fd: Fd, buf: &[u8]) -> WriteResult {
+    let mut written = 0;
+
+    while written < buf.len() {
+        match write_fd(fd.as_fd(), &buf[written..]) {
+            WriteResult::Done(n) => written += n,
+            WriteResult::PartialWrite { written: n } => written += n,
+            WriteResult::Interrupted => continue,
+            WriteResult::WouldBlock if written > 0 => {
+                return WriteResult::PartialWrite { written }
+            }
+            res => return res,
+        }
+    }
+
+    WriteResult::Done(written)
+}
+
+/// Переносит до len байт из from в pipe to внутри ядра, не копируя их в память процесса.
+/// Запись в pipe не блокируется: если pipe полон, возвращается EAGAIN
+pub fn splice_fd<Fd1: AsFd, Fd2: AsFd>(from: Fd1, to: Fd2, len: usize) -> nix::Result<usize> {
+    let flags = SpliceFFlags::SPLICE_F_MOVE | SpliceFFlags::SPLICE_F_NONBLOCK;
+    loop {
+        match splice(from.as_fd(), None, to.as_fd(), None, len, flags) {
+            Err(Errno::EINTR) => continue,
+            res => return res,
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::os::fd::AsFd;
+
+use bytes::{Buf, Bytes};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+use log::trace;
+
+use crate::unix::write_fd::{write_fd, WriteResult};
+
+/// Очередь данных, которые еще не удалось записать в дескриптор
+/// Пока очередь выше high_water, источник данных для этого дескриптора не читается,
+/// чтение возобновляется когда очередь опустится ниже low_water.
+/// Очередь хранит куски как есть, без копирования: один кусок вывода программы
+/// может одновременно стоять в очередях stdout и всех клиентов --attach-socket
+#[derive(Debug)]
+pub struct WriteQueue {
+    chunks: VecDeque<Bytes>,
+    /// байт во всех кусках
+    len: usize,
+    high_water: usize,
+    low_water: usize,
+    paused: bool,
+}
+
+impl WriteQueue {
+    pub fn new(high_water: usize, low_water: usize) -> Self {
+        Self {
+            chunks: VecDeque::new(),
+            len: 0,
+            high_water,
+            low_water: low_water.min(high_water),
+            paused: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Очередь для нового получателя: старые данные и обратное давление сбрасываются
+    pub fn clear(&mut self) {
+        self.chunks.clear();
+        self.len = 0;
+        self.paused = false;
+    }
+
+    pub fn push(&mut self, chunk: Bytes) {
+        if chunk.is_empty() {
+            return;
+        }
+        self.len += chunk.len();
+        self.chunks.push_back(chunk);
+    }
+
+    /// Пишет из очереди столько, сколько дескриптор готов принять без блокировки
+    /// Готовность проверяется через poll перед каждой записью, а запись идет кусками
+    /// не больше PIPE_BUF, поэтому дескриптор не нужно переводить в O_NONBLOCK
+    /// (stdout делится с родительским shell и менять его флаги нельзя)
+    pub fn flush<Fd: AsFd>(&mut self, fd: Fd) -> WriteResult {
+        let mut written = 0;
+
+        while let Some(chunk) = self.chunks.front_mut() {
+            if !writable(fd.as_fd()) {
+                break;
+            }
+            let part = &chunk[..chunk.len().min(libc::PIPE_BUF)];
+
+            match write_fd(fd.as_fd(), part) {
+                WriteResult::Done(n) | WriteResult::PartialWrite { written: n } => {
+                    chunk.advance(n);
+                    if chunk.is_empty() {
+                        self.chunks.pop_front();
+                    }
+                    self.len -= n;
+                    written += n;
+                }
+                WriteResult::Interrupted => continue,
+                WriteResult::WouldBlock => break,
+                res => {
+                    // дописать уже не получится, очередь больше не нужна
+                    self.chunks.clear();
+                    self.len = 0;
+                    return res;
+                }
+            }
+        }
+
+        trace!("write queue flushed {} bytes, left {}", written, self.len);
+
+        if self.is_empty() {
+            WriteResult::Done(written)
+        } else {
+            WriteResult::PartialWrite { written }
+        }
+    }
+
+    /// Смена состояния обратного давления:
+    /// Some(true) - очередь переполнилась и источник нужно приостановить,
+    /// Some(false) - очередь разгрузилась и источник можно снова читать
+    pub fn pressure(&mut self) -> Option<bool> {
+        if !self.paused && self.len > self.high_water {
+            self.paused = true;
+            return Some(true);
+        }
+
+        if self.paused && self.len <= self.low_water {
+            self.paused = false;
+            return Some(false);
+        }
+
+        None
+    }
+}
+
+/// Примет ли дескриптор запись прямо сейчас
+/// POLLERR и POLLHUP тоже считаются готовностью: ошибку вернет сама запись
+fn writable<Fd: AsFd>(fd: Fd) -> bool {
+    let mut fds = [PollFd::new(fd.as_fd(), PollFlags::POLLOUT)];
+    match poll(&mut fds, PollTimeout::ZERO) {
+        Ok(n) if n > 0 => fds[0].revents().is_some_and(|r| !r.is_empty()),
+        _ => false,
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt;
+use std::io::Write;
+
+use crate::redact;
+
+/// -v: запросы пароля, жизненный цикл дочернего процесса, таймауты
+pub const VERBOSE_EVENTS: u8 = 1;
+/// -vv: сводка по каждой записи в программу
+pub const VERBOSE_TRAFFIC: u8 = 2;
+/// -vvv: статистика poll по дескрипторам
+pub const VERBOSE_POLL: u8 = 3;
+
+/// Сколько байт отправленных данных показывать в сводке
+const PREVIEW_LIMIT: usize = 64;
+
+/// Подробный вывод в stderr для -v, -vv, -vvv, работает без SSHPASS_LOG
+/// В неканоническом режиме терминала OPOST выключен, поэтому строки заканчиваются \r\n
+#[derive(Debug, Clone, Copy)]
+pub struct Verbose {
+    level: u8,
+    raw_mode: bool,
+}
+
+impl Verbose {
+    pub fn new(level: u8, raw_mode: bool) -> Self {
+        Self { level, raw_mode }
+    }
+
+    pub fn enabled(&self, level: u8) -> bool {
+        self.level >= level
+    }
+
+    /// Печатает строку, если уровень включен; известные секреты маскируются
+    pub fn print(&self, level: u8, args: fmt::Arguments) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let line = redact::redact(format!("sshpass: {}", args).as_bytes());
+        let eol: &[u8] = if self.raw_mode { b"\r\n" } else { b"\n" };
+        let mut stderr = std::io::stderr().lock();
+        let _ = stderr.write_all(&line).and_then(|_| stderr.write_all(eol));
+    }
+
+    /// Сводка записи в программу: размер и начало данных, секреты замаскированы
+    pub fn sent(&self, what: &str, buf: &[u8]) {
+        if !self.enabled(VERBOSE_TRAFFIC) {
+            return;
+        }
+
+        let redacted = redact::redact(buf);
+        let preview: String = redacted
+            .iter()
+            .take(PREVIEW_LIMIT)
+            .flat_map(|b| std::ascii::escape_default(*b))
+            .map(char::from)
+            .collect();
+        let more = if redacted.len() > PREVIEW_LIMIT { "..." } else { "" };
+
+        self.print(
+            VERBOSE_TRAFFIC,
+            format_args!("sent {} bytes ({}): \"{}{}\"", buf.len(), what, preview, more),
+        );
+    }
+}
@@ -0,0 +1,385 @@
+mod support;
+
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::Signal;
+use nix::unistd::{pipe, read, write};
+
+use support::{sshpass_bin, Session, TIMEOUT};
+
+#[test]
+fn password_is_injected() {
+    let mut session = Session::selftest("secret", "secret");
+
+    assert!(session.expect("selftest: login ok", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+}
+
+#[test]
+fn wrong_password_exits_with_5() {
+    let mut session = Session::selftest("wrong", "secret");
+
+    assert_eq!(session.wait(TIMEOUT), Some(5), "{}", session.output());
+    assert!(session.output().contains("incorrect password"), "{}", session.output());
+}
+
+#[test]
+fn child_exit_code_is_propagated() {
+    let mut session = Session::spawn(&["sh", "-c", "echo done; exit 7"], &[]);
+
+    assert!(session.expect("done", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(7), "{}", session.output());
+}
+
+#[test]
+fn program_options_are_not_taken_by_sshpass() {
+    let mut session = Session::spawn(&["-p", "secret", "echo", "-p", "2222", "-v"], &[]);
+
+    assert!(session.expect("-p 2222 -v", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+}
+
+#[test]
+fn child_killed_by_signal_exits_with_128_plus_signal() {
+    let mut session = Session::spawn(&["sh", "-c", "kill -TERM $$"], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(128 + 15), "{}", session.output());
+}
+
+#[test]
+fn sigterm_stops_the_session() {
+    let mut session = Session::spawn(&["sh", "-c", "echo started; sleep 30"], &[]);
+    assert!(session.expect("started", TIMEOUT), "{}", session.output());
+
+    session.signal(Signal::SIGTERM);
+
+    // sshpass не ждет дочерний процесс дольше срока остановки
+    assert!(session.wait(Duration::from_secs(6)).is_some(), "{}", session.output());
+}
+
+#[test]
+fn sigint_is_forwarded_to_the_program() {
+    let script = r#"trap "echo got INT; exit 4" INT; echo started; sleep 30 & wait"#;
+    let mut session = Session::spawn(&["sh", "-c", script], &[]);
+    assert!(session.expect("started", TIMEOUT), "{}", session.output());
+
+    session.signal(Signal::SIGINT);
+
+    assert_eq!(session.wait(TIMEOUT), Some(4), "{}", session.output());
+    assert!(session.output().contains("got INT"), "{}", session.output());
+}
+
+#[test]
+fn sighup_is_forwarded_to_the_program() {
+    let script = r#"trap "echo got HUP; kill $!; exit 4" HUP; sleep 30 & echo started; wait"#;
+    let mut session = Session::spawn(&["sh", "-c", script], &[]);
+    assert!(session.expect("started", TIMEOUT), "{}", session.output());
+
+    session.signal(Signal::SIGHUP);
+
+    assert_eq!(session.wait(TIMEOUT), Some(4), "{}", session.output());
+    assert!(session.output().contains("got HUP"), "{}", session.output());
+}
+
+#[test]
+fn askpass_mode_answers_only_password_prompts() {
+    let script = r#"
+        "$SSH_ASKPASS" "Are you sure you want to continue connecting (yes/no)?" || echo "hostkey refused"
+        echo "answer=$("$SSH_ASKPASS" "user@host's Password: ")"
+    "#;
+    let mut session = Session::spawn(&["--askpass", "-p", "secret", "sh", "-c", script], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+    assert!(session.output().contains("hostkey refused"), "{}", session.output());
+    assert!(session.output().contains("answer=secret"), "{}", session.output());
+}
+
+#[test]
+fn read_only_discards_input() {
+    let mut session = Session::spawn(
+        &["--read-only", "sh", "-c", "echo ready; read line; echo got=$line"],
+        &[],
+    );
+    assert!(session.expect("ready", TIMEOUT), "{}", session.output());
+
+    session.send(b"typed\r");
+    assert!(!session.expect("got=", Duration::from_millis(500)), "{}", session.output());
+
+    // ^C приходит байтом и завершает сессию
+    session.send(&[0x03]);
+    assert!(session.wait(Duration::from_secs(6)).is_some(), "{}", session.output());
+    assert!(!session.output().contains("got=typed"), "{}", session.output());
+}
+
+#[test]
+fn read_only_keeps_escape_sequences() {
+    let mut session = Session::spawn(
+        &["--read-only", "--escape-char", "~", "sh", "-c", "echo ready; read line; echo got=$line"],
+        &[],
+    );
+    assert!(session.expect("ready", TIMEOUT), "{}", session.output());
+
+    // ввод до программы не доходит, а ~. после перевода строки завершает сессию
+    session.send(b"typed\r~.");
+    assert!(session.wait(Duration::from_secs(6)).is_some(), "{}", session.output());
+    assert!(!session.output().contains("got=typed"), "{}", session.output());
+}
+
+#[test]
+fn session_timeout_exits_with_9() {
+    let mut session = Session::spawn(&["--timeout", "1", "sleep", "30"], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(9), "{}", session.output());
+    assert!(session.output().contains("session timed out"), "{}", session.output());
+}
+
+#[test]
+fn missing_prompt_exits_with_8() {
+    let mut session = Session::spawn(&["-p", "secret", "--prompt-timeout", "1", "sleep", "30"], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(8), "{}", session.output());
+}
+
+#[test]
+fn scp_mode_reports_partial_transfer() {
+    // в режиме ssh имя файла в строке прогресса приняли бы за повторный запрос пароля
+    let script = r#"
+        printf "user@host's password: "; read pass; echo "got=$pass"
+        printf 'passwords.txt  100%%\r\n'
+        exit 1
+    "#;
+    let mut session = Session::spawn(&["--mode", "scp", "-p", "secret", "sh", "-c", script], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(10), "{}", session.output());
+    assert!(session.output().contains("got=secret"), "{}", session.output());
+}
+
+#[test]
+fn audit_file_records_session_without_password() {
+    let path = std::env::temp_dir().join(format!("sshpass-audit-{}.jsonl", std::process::id()));
+    let audit = path.to_str().unwrap();
+    let script = r#"printf "Password: "; read pass; exit 4"#;
+    let mut session = Session::spawn(&["--audit-file", audit, "-p", "secret", "sh", "-c", script], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(4), "{}", session.output());
+    let records = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let events = records
+        .lines()
+        .map(|line| line.split(r#""event":""#).nth(1).and_then(|e| e.split('"').next()).unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(events, ["session_start", "password_sent", "child_exit", "shutdown"], "{}", records);
+    assert!(records.contains(r#""code":4"#), "{}", records);
+    assert!(!records.contains("secret"), "{}", records);
+}
+
+#[test]
+fn ssh_options_are_injected_unless_given() {
+    // поддельный ssh печатает свои аргументы в скобках, чтобы были видны границы.
+    // В аргументах есть "password", поэтому подсказка заменена на ту, что не встретится
+    let dir = std::env::temp_dir().join(format!("sshpass-e2e-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ssh = dir.join("ssh");
+    std::fs::write(&ssh, "#!/bin/sh\nfor a; do printf '[%s]' \"$a\"; done; echo\n").unwrap();
+    std::fs::set_permissions(&ssh, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let ssh = ssh.to_str().unwrap();
+
+    let mut session = Session::spawn(
+        &[
+            "-p",
+            "secret",
+            "-P",
+            "no such prompt",
+            "--strict-host-key-checking",
+            "accept-new",
+            ssh,
+            "host",
+            "ls -l",
+        ],
+        &[],
+    );
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+    assert!(
+        session.output().contains(
+            "[-o][StrictHostKeyChecking=accept-new][-o][NumberOfPasswordPrompts=1]\
+             [-o][PreferredAuthentications=password,keyboard-interactive][host][ls -l]"
+        ),
+        "{}",
+        session.output()
+    );
+
+    // опция, заданная пользователем в форме "Key Value", не дублируется
+    let mut session = Session::spawn(
+        &[
+            "-p",
+            "secret",
+            "-P",
+            "no such prompt",
+            "--strict-host-key-checking",
+            "accept-new",
+            ssh,
+            "-o",
+            "stricthostkeychecking no",
+            "-oNumberOfPasswordPrompts=3",
+            "host",
+        ],
+        &[],
+    );
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+    assert!(
+        session.output().contains(
+            "[-o][PreferredAuthentications=password,keyboard-interactive]\
+             [-o][stricthostkeychecking no][-oNumberOfPasswordPrompts=3][host]"
+        ),
+        "{}",
+        session.output()
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn password_is_requested_over_handshake_fds() {
+    let (req_r, req_w) = pipe().unwrap();
+    let (resp_r, resp_w) = pipe().unwrap();
+    let fds = format!("{}:{}", req_w.as_raw_fd(), resp_r.as_raw_fd());
+    let mut session = Session::spawn(
+        &["--password-fd-handshake", &fds, sshpass_bin(), "selftest-child", "--attempts", "2"],
+        &[("SSHPASS_SELFTEST_EXPECT", "secret")],
+    );
+    drop((req_w, resp_r));
+
+    // запрос приходит только когда программа спросила пароль
+    let mut fds = [PollFd::new(req_r.as_fd(), PollFlags::POLLIN)];
+    assert_eq!(poll(&mut fds, PollTimeout::from(5000_u16)), Ok(1), "{}", session.output());
+    let mut request = [0u8; 1];
+    assert_eq!(read(req_r.as_raw_fd(), &mut request), Ok(1));
+    assert_eq!(&request, b"?");
+
+    write(&resp_w, b"secret\n").unwrap();
+
+    assert!(session.expect("selftest: login ok", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+}
+
+#[test]
+fn splice_output_passes_output_unchanged() {
+    // вывод после входа идет в stdout-pipe мимо sshpass и должен дойти целиком
+    let script = r#"printf "Password: "; read pass; sleep 1.5; seq 1 100000; exit 3"#;
+    let output = std::process::Command::new(sshpass_bin())
+        .args(["-v", "--raw-output", "--splice-output", "-p", "secret", "sh", "-c", script])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert_eq!(output.status.code(), Some(3), "{}", stderr);
+    assert!(stderr.contains("output goes to stdout with splice"), "{}", stderr);
+    let lines = stdout.lines().skip_while(|line| !line.starts_with('1'));
+    assert!(lines.map(|line| line.trim_end_matches('\r')).eq((1..=100000).map(|n| n.to_string())));
+}
+
+#[test]
+fn doctor_reports_broken_log_config() {
+    let output = std::process::Command::new(sshpass_bin())
+        .args(["doctor", "--json"])
+        .env("SSHPASS_LOG", "bogus")
+        .output()
+        .unwrap();
+    let report = String::from_utf8_lossy(&output.stdout);
+
+    assert_eq!(output.status.code(), Some(1), "{}", report);
+    assert!(report.contains(r#""name":"pty","status":"ok""#), "{}", report);
+    assert!(report.contains(r#""name":"log","status":"fail""#), "{}", report);
+}
+
+#[test]
+fn program_does_not_inherit_sshpass_fds() {
+    // параллельные тесты сами оставляют наследуемые дескрипторы, поэтому проверяются только
+    // дескрипторы sshpass: копии pty, кроме stdio, и signalfd
+    let script = r#"t=$(readlink /proc/$$/fd/0); n=0; s=0
+for f in /proc/$$/fd/*; do
+  l=$(readlink $f)
+  [ "$l" = "$t" ] && n=$((n+1))
+  [ "$l" = "anon_inode:[signalfd]" ] && s=$((s+1))
+done
+echo "pty: $n signalfd: $s""#;
+    let mut session = Session::spawn(&["sh", "-c", script], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+    assert!(session.output().contains("pty: 3 signalfd: 0"), "{}", session.output());
+}
+
+#[test]
+fn preflight_exits_with_11_when_destination_refuses() {
+    // кеш проверок в отдельном каталоге, что бы не зависеть от прошлых запусков
+    let runtime = std::env::temp_dir().join(format!("sshpass-preflight-{}", std::process::id()));
+    std::fs::create_dir_all(&runtime).unwrap();
+    std::fs::set_permissions(&runtime, std::fs::Permissions::from_mode(0o700)).unwrap();
+    let env = [("XDG_RUNTIME_DIR", runtime.to_str().unwrap())];
+    let args = ["--preflight=2", "-p", "secret", "ssh", "-p", "1", "127.0.0.1"];
+    let mut session = Session::spawn(&args, &env);
+
+    assert_eq!(session.wait(TIMEOUT), Some(11), "{}", session.output());
+    assert!(session.output().contains("127.0.0.1:1 unreachable"), "{}", session.output());
+    std::fs::remove_dir_all(&runtime).unwrap();
+}
+
+#[test]
+fn window_size_follows_the_terminal() {
+    let mut session = Session::spawn(&["sh", "-c", "echo ready; read line; stty size"], &[]);
+    assert!(session.expect("ready", TIMEOUT), "{}", session.output());
+
+    session.resize(132, 43);
+    // SIGWINCH обрабатывается в цикле sshpass, ввод не должен его обогнать
+    std::thread::sleep(Duration::from_millis(200));
+    session.send(b"\n");
+
+    assert!(session.expect("43 132", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+}
+
+#[test]
+fn missing_program_exits_with_3() {
+    let mut session = Session::spawn(&["-p", "secret", "/nonexistent/program"], &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(3), "{}", session.output());
+    assert!(session.output().contains("sshpass: /nonexistent/program: "), "{}", session.output());
+    assert!(!session.output().contains("panicked"), "{}", session.output());
+}
+
+#[test]
+fn password_fd_is_read_up_to_the_newline() {
+    // вызывающий процесс держит pipe открытым, как обычный супервизор с -d
+    let (pass_r, pass_w) = pipe().unwrap();
+    write(&pass_w, b"secret\n").unwrap();
+    let fd = pass_r.as_raw_fd().to_string();
+    let mut session = Session::spawn(
+        &["-d", &fd, sshpass_bin(), "selftest-child", "--attempts", "2"],
+        &[("SSHPASS_SELFTEST_EXPECT", "secret")],
+    );
+
+    assert!(session.expect("selftest: login ok", TIMEOUT), "{}", session.output());
+    assert_eq!(session.wait(TIMEOUT), Some(0), "{}", session.output());
+    drop(pass_w);
+}
+
+#[test]
+fn password_cmd_timeout_kills_its_children() {
+    let pidfile = std::env::temp_dir().join(format!("sshpass-password-cmd-{}", std::process::id()));
+    let command = format!("sleep 300 & echo $! > {}; wait", pidfile.display());
+    let args = ["--password-cmd", &command, "--password-cmd-timeout", "1", "true"];
+    let mut session = Session::spawn(&args, &[]);
+
+    assert_eq!(session.wait(TIMEOUT), Some(3), "{}", session.output());
+    let pid = std::fs::read_to_string(&pidfile).unwrap();
+    std::fs::remove_file(&pidfile).unwrap();
+    // убитый sleep может остаться зомби, если init в контейнере не собирает сирот
+    let state = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim())).unwrap_or_default();
+    assert!(state.is_empty() || state.contains(") Z "), "sleep {} survived: {}", pid.trim(), state);
+}
@@ -0,0 +1,155 @@
+//! Запуск sshpass под псевдотерминалом для end-to-end тестов
+//! В роли ssh выступает сам sshpass в режиме selftest-child
+
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::pty::openpty;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::{read, write, Pid};
+
+/// Сколько ждать вывода или завершения по умолчанию
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Путь к собранному sshpass
+pub fn sshpass_bin() -> &'static str {
+    env!("CARGO_BIN_EXE_sshpass")
+}
+
+/// sshpass, запущенный с управляющим терминалом
+/// Все, что он выводит в терминал, копится в output
+pub struct Session {
+    master: OwnedFd,
+    child: Child,
+    output: Vec<u8>,
+}
+
+impl Session {
+    pub fn spawn(args: &[&str], env: &[(&str, &str)]) -> Self {
+        let pty = openpty(None, None).expect("openpty");
+        let stdio = || Stdio::from(pty.slave.try_clone().expect("dup pty slave"));
+
+        let mut cmd = Command::new(sshpass_bin());
+        cmd.args(args)
+            .envs(env.iter().copied())
+            .env_remove("SSHPASS_LOG")
+            .stdin(stdio())
+            .stdout(stdio())
+            .stderr(stdio());
+
+        // свой сеанс и pty в роли управляющего терминала, как у sshpass в интерактивном shell
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = cmd.spawn().expect("spawn sshpass");
+        // без копии slave в тесте чтение master вернет EIO, когда sshpass завершится
+        drop(pty.slave);
+
+        Self {
+            master: pty.master,
+            child,
+            output: vec![],
+        }
+    }
+
+    /// sshpass -p PASSWORD sshpass selftest-child, ожидающий пароль EXPECTED
+    pub fn selftest(password: &str, expected: &str) -> Self {
+        Self::spawn(
+            &["-p", password, sshpass_bin(), "selftest-child", "--attempts", "2"],
+            &[("SSHPASS_SELFTEST_EXPECT", expected)],
+        )
+    }
+
+    /// Все, что было выведено до сих пор
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.output).replace('\r', "")
+    }
+
+    /// Ждет, пока в выводе появится needle
+    pub fn expect(&mut self, needle: &str, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while !self.output().contains(needle) {
+            if Instant::now() >= deadline || !self.read_some(deadline) {
+                return self.output().contains(needle);
+            }
+        }
+        true
+    }
+
+    /// Ждет завершения sshpass, вывод при этом продолжает читаться
+    /// Возвращает код выхода, None если sshpass не завершился за timeout
+    pub fn wait(&mut self, timeout: Duration) -> Option<i32> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.child.try_wait().expect("try_wait") {
+                // дочитываю то, что осталось в pty
+                while self.read_some(Instant::now() + Duration::from_millis(100)) {}
+                return status.code();
+            }
+
+            if Instant::now() >= deadline {
+                return None;
+            }
+
+            self.read_some(Instant::now() + Duration::from_millis(50));
+        }
+    }
+
+    /// Ввод с клавиатуры
+    pub fn send(&self, buf: &[u8]) {
+        write(&self.master, buf).expect("write pty");
+    }
+
+    /// Меняет размер окна терминала, как это делает эмулятор терминала: sshpass получит SIGWINCH
+    pub fn resize(&self, cols: u16, rows: u16) {
+        let size = libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 };
+        let res = unsafe { libc::ioctl(self.master.as_raw_fd(), libc::TIOCSWINSZ, &size) };
+        Errno::result(res).expect("TIOCSWINSZ");
+    }
+
+    pub fn signal(&self, signal: Signal) {
+        kill(Pid::from_raw(self.child.id() as i32), signal).expect("kill");
+    }
+
+    /// Читает одну порцию вывода, false если до deadline ничего не пришло или pty закрыт
+    fn read_some(&mut self, deadline: Instant) -> bool {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let timeout = PollTimeout::try_from(left).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(self.master.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, timeout) {
+            Ok(n) if n > 0 => {}
+            _ => return false,
+        }
+
+        let mut buf = [0u8; 4096];
+        match read(self.master.as_raw_fd(), &mut buf) {
+            Ok(0) | Err(Errno::EIO) => false,
+            Ok(n) => {
+                self.output.extend_from_slice(&buf[..n]);
+                true
+            }
+            Err(Errno::EINTR) => true,
+            Err(e) => panic!("read pty: {}", e),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}